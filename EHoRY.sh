@@ -258,7 +258,7 @@ qchoice "快速切换Shamiko模式" "select_modules \"19\""
 }
 
 downopenselinux() {
-murl="https://lz.qaiu.top/d/lz/iXBKa368frid"
+murl="lanzou://iXBKa368frid"
 mhash="1911ddfdd0262f85ff24dbd03aab0d64e83a68a580ce7993d6b78017a761b183"
 download_module "$murl" "$mhash"
 installer
@@ -545,7 +545,7 @@ case $1 in
     20) share_key="id0lL351icuf" ;;
     22) share_key="i8HY8351icza" ;;
 esac
-murl="https://lz.qaiu.top/d/lz/$share_key"
+murl="lanzou://$share_key"
 }
 
 # 下载提供的模块
@@ -860,19 +860,8 @@ WARMLIST=(
 }
 
 ddpeekaboo() {
-wfpeekaboo() {
-    echos "$YE好像没刷入peekaboo模块呢！请私信酷安@yu13140反馈错误$RE"
-    rm -rf $YSHELL_PATH/*
-    exit 1
-}
 ddnohello() {
-if [[ $APATCH_VERSION -le 11010 ]] && [[ $APATCH_VERSION -ge 10983 ]]; then
-    echos "$YE您的APatch版本太低，不建议您嵌入Nohello模块$RE"
-    return 1
-fi
-rshy --download "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/peekaboo/nohello.kpm" "nohello.kpm"
-[[ ! -f $YSHELL_PATH/nohello.kpm ]] && echos "${YE}Nohello未下载成功，取消安装操作$RE" && return 1
-insnh=1
+    nohello="--nohello"
 }
 if [[ ! $ENVIRONMENT = "APatch" ]]; then
     echos "$YE非APatch用户请不要安装peekaboo"
@@ -880,160 +869,21 @@ if [[ ! $ENVIRONMENT = "APatch" ]]; then
     return 1
 fi
 
-echos "$RED这是一个高危选项，请确保手机自备救砖能力$RE"
-echos " "
-echos "$GR若你已经确认过风险，并选择安装，请输入 1 "
-echos "1.安装                         2.不安装"
-echos "请输入对应的选项：$RE\c"
-
-read warnpeekaboo
-case $warnpeekaboo in
-    1) 
-    echos "$GR正在进入安装模块环节$RE" ;;
-    *) 
-    echos "$YE你选择退出安装peekaboo$RE"; return ;;
-esac
+local nohello=""
+qchoice "NoHello" "ddnohello"
 
+# 超级密钥由 rshy 以不回显的方式读取，boot 会先备份，写入后读回校验
+# 刷入前的高危确认由 rshy 给出，选择退出时返回 2
 echos " "
-echos "$YE请输入当前APatch的超级密钥，这不会侵犯您的任何隐私"  
-echos "输入超级密钥：$RE\c"
-read super_key
-
-if [[ -z $super_key ]]; then
-    echos " "
-    echos "$YE超级密钥不能为空！"
-    echos "请再次输入超级密钥：$RE\c"
-    read super_key
-fi
-
-if [[ -d /data/data/me.garfieldhan.apatch.next ]] && [[ $NEXT_AP = 1 ]]; then
-    APP_AP="me.garfieldhan.apatch.next"
-    APP_AP_PATHS=$(pm path $APP_AP | sed -n 's/^package://p' | awk -F'base' '{print $1}')    
-elif [[ -d /data/data/me.bmax.apatch ]]  && [[ $NEXT_AP = 0 ]]; then
-    APP_AP="me.bmax.apatch"
-    APP_AP_PATHS=$(pm path $APP_AP | sed -n 's/^package://p' | awk -F'base' '{print $1}')    
-else
-    echos "$YE未找到你的APatch，请确认是否安装了APatch管理器$RE"
-    return 1
-fi
-APP_AP_PATH="$APP_AP_PATHS/lib/arm64" 
-
-if [[ ! -d "/dev/block/by-name" ]]; then   
-    SITE="/dev/block/bootdevice/by-name"
-    if [[ ! -d "/dev/block/bootdevice/by-name" ]]; then
-        echos "$YE未检测到分区路径，接下来不会嵌入peekboo$RE"
-        return 1
-    fi 
-else
-    SITE="/dev/block/by-name" 
-fi
-    if [[ $($APP_AP_PATH/libkpatch.so "$super_key" kpm num) -ne 0 ]]; then
-        echos "$YE检测到已经装有内核模块"
-        echos "如果仍要使用会把已经安装的内核模块删除"
-        echos " "
-        echos "1.仍要安装                        2.不安装"
-        echos "请输入对应的选项：$RE\c"
-        read peekabooinit
-        case $peekabooinit in
-        1) 
-        echos "$GR你选择了仍要安装$RE";;
-        2) 
-        echos "$YE你选择退出安装peekaboo$RE"; return ;;
-        *)
-        echos "$YE输入错误，默认不安装$RE"; return ;;
-        esac
-    fi
-
-local version="$APATCH_VERSION"
-
-case 1 in
-    $(( version == 11021 )) )
-        echos "$WH检测到您正在使用APatch Next(11021)"
-        echos "推荐使用cherish_peekaboo_1.5.5"
-        echos "正在下载中……$RE"        
-        rshy --download "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/peekaboo/cherish_peekaboo_1.5.5.kpm" "peekaboo.kpm"
-    ;;
-    $(( version >= 10983 && version <= 11010 )) )
-       echos "$WH检测到您正在使用APatch($APATCH_VERSION)"
-       echos "推荐使用cherish_peekaboo_1.5"
-       echos "正在下载中……$RE"
-       rshy --download "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/peekaboo/cherish_peekaboo_1.5.kpm" "peekaboo.kpm"
-    ;;
-    *) 
-        echos "$WH检测到您正在使用APatch($APATCH_VERSION)"
-        echos "推荐使用cherish_peekaboo_1.5.5"
-        echos "正在下载中……$RE"
-        rshy --download "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/peekaboo/cherish_peekaboo_1.5.5.kpm" "peekaboo.kpm"
+rshy kpm embed $nohello
+case $? in
+    0)
+    echos "$GR已成功刷入peekaboo模块$RE" ;;
+    2)
+    echos "$YE你选择退出安装peekaboo$RE" ;;
+    *)
+    echos "$YE未刷入peekaboo模块！$RE" ;;
 esac
-
-[[ ! -f $YSHELL_PATH/peekaboo.kpm ]] && echos "${YE}peekaboo未下载成功，取消安装操作$RE" && return 1
-
-qchoice "NoHello" "ddnohello"
-
-BOOTAB="$(getprop ro.build.ab_update)"
-Partition_location=$(getprop ro.boot.slot_suffix)
-if [[ $BOOTAB = "true" ]]; then
-    echos "检测到设备支持A/B分区"    
-    if [[ "$Partition_location" == "_a" ]]; then
-        echos "$GR你目前处于 A 分区$RE"
-        position=$(ls -l $SITE/boot_a | awk '{print $NF}')
-    elif [[ "$Partition_location" == "_b" ]]; then
-        echos "$GR你目前处于 B 分区$RE"
-        position=$(ls -l $SITE/boot_b | awk '{print $NF}')        
-    fi
-else
-    position=$(ls -l $SITE/boot | awk '{print $NF}')
-fi
-
-cd $YSHELL_PATH
-cp -af $APP_AP_PATH/libkptools.so ./kptools
-cp -af $APP_AP_PATHS/base.apk ./base.zip
-cp -af $APP_AP_PATH/libmagiskboot.so ./magiskboot
-command -v ./magiskboot >/dev/null 2>&1 || { >&2 echo "- 未找到APatch目录下的magiskboot文件"; return 1; }
-command -v ./kptools >/dev/null 2>&1 || { >&2 echo "- 未找到APatch目录下的Kptools文件"; return 1; }
-mkdir ./apatch
-unzip base.zip -d ./apatch
-chmod -R 777 .
-if [[ ! -f /data/data/$APP_AP/new-boot.img ]]; then
-    echos "$WH未找到APatch修补后的boot.img！"
-    echos "正在提取设备中的boot分区$RE"
-    dd if="$position" of="new-boot.img" bs=4M
-else
-    cp -af /data/data/$APP_AP/new-boot.img ./
-fi
-
-./magiskboot unpack new-boot.img
-if [ ! $(./kptools -i kernel -l | grep patched=false) ]; then
-    ./kptools -u --image kernel --out rekernel
-else
-    mv kernel rekernel
-fi
-
-rm -f kernel
-if [[ $insnh = 1 ]]; then
-    ./kptools -p -i rekernel -s "$super_key" -k ./apatch/assets/kpimg -o $YSHELL_PATH/kernel -M peekaboo.kpm -V pre-kernel-init -T kpm -M nohello.kpm -V pre-kernel-init -T kpm 2>&1 | tee log.txt
-else
-    ./kptools -p -i rekernel -s "$super_key" -k ./apatch/assets/kpimg -o $YSHELL_PATH/kernel -M peekaboo.kpm -V pre-kernel-init -T kpm 2>&1 | tee log.txt
-fi
-
-if [[ ! $(cat log.txt | grep "patch done") ]]; then
-    wfpeekaboo
-fi
-
-rm -f rekernel
-mv new-boot.img boot.img
-./magiskboot repack boot.img
-rm -f boot.img
-rm -f peekaboo.kpm
-mv new-boot.img boot.img
-
-dd if=boot.img of="$position" bs=4M
-if [[ $? -ne 0 ]]; then
-    echos "$YE未刷入peekaboo模块！$RE"
-else
-    echos "$GR已成功刷入peekaboo模块$RE"
-fi
-rm -rf $YSHELL_PATH/*
 }
 
 yuhide() {    
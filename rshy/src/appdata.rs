@@ -0,0 +1,190 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+use crate::{run_useful_tool_with_args, AppResult};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileAttributes {
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub context: Option<String>,
+}
+
+fn selinux_context(path: &Path) -> Option<String> {
+    let output = Command::new("stat").arg("-c").arg("%C").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let context = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if context.is_empty() || context == "?" {
+        None
+    } else {
+        Some(context)
+    }
+}
+
+fn set_selinux_context(path: &Path, context: &str) -> AppResult {
+    let output = Command::new("chcon").arg(context).arg(path).output()?;
+    if !output.status.success() {
+        eprintln!("设置 {} 的 SELinux 上下文失败: {}", path.display(), String::from_utf8_lossy(&output.stderr).trim());
+        return Err("设置 SELinux 上下文失败".into());
+    }
+    Ok(())
+}
+
+const AID_USER_OFFSET: u32 = 100000;
+const AID_APP_START: u32 = 10000;
+
+// 应用数据文件的 MCS 类别由 uid 推导，与 seapp_contexts 中 levelFrom=all 的规则一致
+// 计算类别前先减去 AID_APP_START，系统 uid 没有类别
+pub fn app_data_context(uid: u32) -> String {
+    let user_id = uid / AID_USER_OFFSET;
+    let Some(app_id) = (uid % AID_USER_OFFSET).checked_sub(AID_APP_START) else {
+        return "u:object_r:app_data_file:s0".to_string();
+    };
+    format!(
+        "u:object_r:app_data_file:s0:c{},c{},c{},c{}",
+        app_id & 0xff,
+        256 + ((app_id >> 8) & 0xff),
+        512 + (user_id & 0xff),
+        768 + ((user_id >> 8) & 0xff),
+    )
+}
+
+pub fn read_attributes(path: &Path) -> AppResult<FileAttributes> {
+    let metadata = fs::metadata(path)?;
+    Ok(FileAttributes {
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        mode: metadata.mode() & 0o7777,
+        context: selinux_context(path),
+    })
+}
+
+// 新文件沿用所在目录的属主和上下文，权限与应用自己创建的文件一致
+fn attributes_for_new_file(path: &Path) -> AppResult<FileAttributes> {
+    let parent = path.parent().ok_or("无效的文件路径")?;
+    let dir = read_attributes(parent)?;
+    let context = match dir.context {
+        Some(context) if context.contains(":app_data_file:") => context,
+        _ => app_data_context(dir.uid),
+    };
+
+    Ok(FileAttributes {
+        uid: dir.uid,
+        gid: dir.gid,
+        mode: 0o600,
+        context: Some(context),
+    })
+}
+
+pub fn apply_attributes(path: &Path, attributes: &FileAttributes) -> AppResult {
+    if let Err(e) = std::os::unix::fs::chown(path, Some(attributes.uid), Some(attributes.gid)) {
+        eprintln!("设置 {} 的属主失败: {}", path.display(), e);
+        return Err(e.into());
+    }
+
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(attributes.mode)) {
+        eprintln!("设置 {} 的权限失败: {}", path.display(), e);
+        return Err(e.into());
+    }
+
+    if let Some(context) = &attributes.context {
+        set_selinux_context(path, context)?;
+    }
+
+    Ok(())
+}
+
+// 先写入同目录下的临时文件并还原属性，再原子替换，避免应用读到 root 属主或错误标签的文件
+pub fn write_app_file(path: &str, content: &[u8]) -> AppResult {
+    let path = Path::new(path);
+    let attributes = if path.exists() {
+        read_attributes(path)?
+    } else {
+        attributes_for_new_file(path)?
+    };
+
+    let file_name = path.file_name().ok_or("无效的文件路径")?.to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.rshy-tmp", file_name));
+
+    let result = (|| -> AppResult {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        apply_attributes(&temp_path, &attributes)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        eprintln!("写入 {} 失败: {}", path.display(), e);
+        return Err(e);
+    }
+
+    let written = read_attributes(path)?;
+    if written != attributes {
+        eprintln!("写入后 {} 的属性与预期不一致: {:?}", path.display(), written);
+        return Err("文件属性还原失败".into());
+    }
+
+    Ok(())
+}
+
+fn launcher_activity(package_name: &str) -> Option<String> {
+    let output = run_useful_tool_with_args("cmd", &[
+        "package", "resolve-activity", "--brief",
+        "-a", "android.intent.action.MAIN",
+        "-c", "android.intent.category.LAUNCHER",
+        package_name,
+    ]).ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim())
+        .rfind(|line| line.starts_with(package_name) && line.contains('/'))
+        .map(|line| line.to_string())
+}
+
+// 重启应用使其重新读取配置，没有启动入口的应用只会被停止
+pub fn restart_app(package_name: &str) -> AppResult {
+    let output = run_useful_tool_with_args("cmd", &["activity", "force-stop", package_name])?;
+    if !output.status.success() {
+        eprintln!("停止 {} 失败: {}", package_name, String::from_utf8_lossy(&output.stderr).trim());
+        return Err("停止应用失败".into());
+    }
+    println!("已停止 {}", package_name);
+
+    let Some(component) = launcher_activity(package_name) else {
+        println!("{} 没有启动入口，请手动打开应用", package_name);
+        return Ok(());
+    };
+
+    let output = run_useful_tool_with_args("cmd", &["activity", "start", "-n", &component])?;
+    if !output.status.success() {
+        eprintln!("启动 {} 失败: {}", component, String::from_utf8_lossy(&output.stderr).trim());
+        return Err("启动应用失败".into());
+    }
+    println!("已重新启动 {}", package_name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_app_data_categories_from_uid() {
+        assert_eq!(app_data_context(10213), "u:object_r:app_data_file:s0:c213,c256,c512,c768");
+        assert_eq!(app_data_context(10000), "u:object_r:app_data_file:s0:c0,c256,c512,c768");
+        assert_eq!(app_data_context(1010213), "u:object_r:app_data_file:s0:c213,c256,c522,c768");
+        assert_eq!(app_data_context(10300), "u:object_r:app_data_file:s0:c44,c257,c512,c768");
+        assert_eq!(app_data_context(1000), "u:object_r:app_data_file:s0");
+    }
+}
@@ -0,0 +1,584 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{axml, der, get_all_apk_paths, kpm, pm, AppResult};
+
+const APK_SIG_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const APK_SIGNATURE_SCHEME_IDS: &[u32] = &[0x7109_871a, 0xf053_68c0, 0x1b93_ad61];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AppSignature {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub markers: Vec<String>,
+    #[serde(default)]
+    pub classes: Vec<String>,
+    #[serde(default)]
+    pub cert_digests: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl Confidence {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Confidence::Low => "低",
+            Confidence::Medium => "中",
+            Confidence::High => "高",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppMatch {
+    pub signature_id: String,
+    pub package: String,
+    pub apk_path: String,
+    pub confidence: Confidence,
+    pub evidence: Vec<String>,
+}
+
+fn signature(id: &str, name: &str, packages: &[&str], markers: &[&str], classes: &[&str]) -> AppSignature {
+    let owned = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+    AppSignature {
+        id: id.to_string(),
+        name: name.to_string(),
+        packages: owned(packages),
+        markers: owned(markers),
+        classes: owned(classes),
+        cert_digests: Vec::new(),
+    }
+}
+
+pub fn builtin_signatures() -> Vec<AppSignature> {
+    vec![
+        signature(
+            "hma",
+            "隐藏应用列表",
+            &["icu.nullptr.hidemyapplist", "com.tsng.hidemyapplist"],
+            &["icu.nullptr.hidemyapplist", "com.tsng.hidemyapplist"],
+            &["icu.nullptr.hidemyapplist.", "com.tsng.hidemyapplist."],
+        ),
+        signature(
+            "lsposed",
+            "LSPosed 管理器",
+            &["org.lsposed.manager"],
+            &["org.lsposed.manager", "org.lsposed.lspd"],
+            &["org.lsposed.manager.", "org.lsposed.lspd."],
+        ),
+        signature(
+            "magisk",
+            "Magisk",
+            &["com.topjohnwu.magisk"],
+            &["com.topjohnwu.magisk"],
+            &["com.topjohnwu.magisk."],
+        ),
+        signature(
+            "apatch",
+            "APatch",
+            &kpm::APATCH_PACKAGES,
+            &kpm::APATCH_PACKAGES,
+            &["me.bmax.apatch.", "me.garfieldhan.apatch.next."],
+        ),
+        signature(
+            "kernelsu",
+            "KernelSU 管理器",
+            &["me.weishu.kernelsu", "com.sukisu.ultra", "com.rifsxd.ksunext"],
+            &["me.weishu.kernelsu", "com.sukisu.ultra", "com.rifsxd.ksunext"],
+            &["me.weishu.kernelsu.", "com.sukisu.ultra.", "com.rifsxd.ksunext."],
+        ),
+        signature(
+            "shizuku",
+            "Shizuku",
+            &["moe.shizuku.privileged.api"],
+            &["moe.shizuku.privileged.api", "rikka.shizuku"],
+            &["moe.shizuku.manager.", "rikka.shizuku."],
+        ),
+    ]
+}
+
+// 用户提供的 JSON 签名库会覆盖同 id 的内置条目
+pub fn load_signatures(extra_file: Option<&str>) -> AppResult<Vec<AppSignature>> {
+    let mut signatures = builtin_signatures();
+
+    if let Some(path) = extra_file {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("读取签名库 {} 失败: {}", path, e);
+                return Err(e.into());
+            }
+        };
+        let extra: Vec<AppSignature> = match serde_json::from_str(&content) {
+            Ok(extra) => extra,
+            Err(e) => {
+                eprintln!("解析签名库 {} 失败: {}", path, e);
+                return Err(e.into());
+            }
+        };
+
+        for signature in extra {
+            signatures.retain(|existing| existing.id != signature.id);
+            signatures.push(signature);
+        }
+    }
+
+    Ok(signatures)
+}
+
+fn normalize_digest(digest: &str) -> String {
+    digest.chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn match_signature(
+    signature: &AppSignature,
+    manifest: &axml::Manifest,
+    cert_digests: &[String],
+) -> Option<(Confidence, Vec<String>)> {
+    let mut evidence = Vec::new();
+    let mut confidence = None;
+
+    match &manifest.package {
+        Some(package) if signature.packages.contains(package) => {
+            evidence.push(format!("原始包名: {}", package));
+            confidence = Some(Confidence::High);
+        }
+        _ => {}
+    }
+
+    for digest in cert_digests {
+        if signature.cert_digests.iter().any(|known| normalize_digest(known) == *digest) {
+            evidence.push(format!("签名证书 SHA-256: {}", digest));
+            confidence = Some(Confidence::High);
+        }
+    }
+
+    let class_names = manifest.application_class.iter()
+        .chain(manifest.components.iter().map(|component| &component.name));
+    let matched_classes: Vec<&String> = class_names
+        .filter(|class| signature.classes.iter().any(|prefix| class.starts_with(prefix.as_str())))
+        .collect();
+    if !matched_classes.is_empty() {
+        for class in matched_classes.iter().take(3) {
+            evidence.push(format!("类名: {}", class));
+        }
+        if matched_classes.len() > 3 {
+            evidence.push(format!("类名: 另有 {} 个匹配", matched_classes.len() - 3));
+        }
+        confidence = confidence.max(Some(Confidence::Medium));
+    }
+
+    for marker in &signature.markers {
+        if manifest.mentions(marker) {
+            evidence.push(format!("清单标记: {}", marker));
+            confidence = confidence.max(Some(Confidence::Low));
+        }
+    }
+
+    confidence.map(|confidence| (confidence, evidence))
+}
+
+pub fn inspect_apk(apk_path: &str, signatures: &[AppSignature]) -> AppResult<Vec<AppMatch>> {
+    let manifest = axml::read_apk_manifest(apk_path)?;
+
+    let cert_digests = if signatures.iter().any(|s| !s.cert_digests.is_empty()) {
+        signing_cert_digests(apk_path).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let package = manifest.package.clone().unwrap_or_default();
+    let matches = signatures.iter()
+        .filter_map(|signature| {
+            match_signature(signature, &manifest, &cert_digests).map(|(confidence, evidence)| AppMatch {
+                signature_id: signature.id.clone(),
+                package: package.clone(),
+                apk_path: apk_path.to_string(),
+                confidence,
+                evidence,
+            })
+        })
+        .collect();
+
+    Ok(matches)
+}
+
+// 内置条目没有预置证书摘要，已安装的官方版本在时，用它的签名证书找出改了包名的同签名应用
+fn learn_cert_digests(pm: &dyn pm::PackageManager, signatures: &[AppSignature]) -> Vec<AppSignature> {
+    signatures.iter()
+        .map(|signature| {
+            let mut signature = signature.clone();
+            for package in &signature.packages.clone() {
+                let Some(apk) = pm.package_paths(package).unwrap_or_default().into_iter().next() else {
+                    continue;
+                };
+                for digest in signing_cert_digests(&apk.to_string_lossy()).unwrap_or_default() {
+                    if !signature.cert_digests.iter().any(|known| normalize_digest(known) == digest) {
+                        signature.cert_digests.push(digest);
+                    }
+                }
+            }
+            signature
+        })
+        .collect()
+}
+
+pub fn scan_installed_apps(pm: &dyn pm::PackageManager, signatures: &[AppSignature]) -> AppResult<Vec<AppMatch>> {
+    let apk_paths = get_all_apk_paths(pm)?;
+
+    println!("找到 {} 个第三方应用APK文件路径", apk_paths.len());
+
+    if apk_paths.is_empty() {
+        println!("未找到任何第三方应用APK文件");
+        return Ok(Vec::new());
+    }
+
+    let existing_apk_paths: Vec<String> = apk_paths.into_iter()
+        .filter(|path| fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false))
+        .collect();
+
+    println!("实际可访问的 APK 文件: {} 个", existing_apk_paths.len());
+
+    if existing_apk_paths.is_empty() {
+        println!("没有可访问的APK文件");
+        return Ok(Vec::new());
+    }
+
+    let pb = ProgressBar::new(existing_apk_paths.len() as u64);
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
+        .unwrap()
+        .progress_chars("#>-"));
+
+    let found = Arc::new(Mutex::new(Vec::new()));
+    let processed = Arc::new(AtomicU64::new(0));
+    let signatures = Arc::new(learn_cert_digests(pm, signatures));
+
+    let chunk_size = (existing_apk_paths.len() / num_cpus::get()).max(1);
+    let mut handles = Vec::new();
+
+    for chunk in existing_apk_paths.chunks(chunk_size) {
+        let chunk = chunk.to_vec();
+        let found = Arc::clone(&found);
+        let processed = Arc::clone(&processed);
+        let signatures = Arc::clone(&signatures);
+
+        handles.push(thread::spawn(move || {
+            for apk_path in chunk {
+                match inspect_apk(&apk_path, &signatures) {
+                    Ok(matches) if !matches.is_empty() => {
+                        found.lock().unwrap().extend(matches);
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        eprintln!("解析 {} 的清单失败: {}", apk_path, e);
+                    }
+                }
+                processed.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+    }
+
+    while !handles.iter().all(|handle| handle.is_finished()) {
+        pb.set_position(processed.load(Ordering::Relaxed));
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    pb.set_position(processed.load(Ordering::Relaxed));
+    pb.finish_with_message("扫描完成");
+
+    let mut matches = Arc::try_unwrap(found).unwrap().into_inner().unwrap();
+    matches.sort_by(|a, b| {
+        a.signature_id.cmp(&b.signature_id)
+            .then(b.confidence.cmp(&a.confidence))
+            .then(a.package.cmp(&b.package))
+    });
+    Ok(matches)
+}
+
+pub fn signing_cert_digests(apk_path: &str) -> AppResult<Vec<String>> {
+    let certificates = match read_signing_block_certificates(apk_path) {
+        Ok(certificates) if !certificates.is_empty() => certificates,
+        _ => read_v1_certificates(apk_path)?,
+    };
+
+    Ok(certificates.iter()
+        .map(|certificate| der::to_hex(&Sha256::digest(certificate)))
+        .collect())
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn le_u64(data: &[u8], offset: usize) -> Option<u64> {
+    data.get(offset..offset + 8).map(|b| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(b);
+        u64::from_le_bytes(bytes)
+    })
+}
+
+// 按 uint32 长度前缀切分序列
+fn length_prefixed(data: &[u8]) -> Vec<&[u8]> {
+    let mut items = Vec::new();
+    let mut offset = 0;
+    while let Some(length) = le_u32(data, offset) {
+        let start = offset + 4;
+        let Some(end) = start.checked_add(length as usize) else {
+            break;
+        };
+        match data.get(start..end) {
+            Some(item) => items.push(item),
+            None => break,
+        }
+        offset = end;
+    }
+    items
+}
+
+fn read_signing_block_certificates(apk_path: &str) -> AppResult<Vec<Vec<u8>>> {
+    let mut file = File::open(apk_path)?;
+    let file_size = file.metadata()?.len();
+
+    let tail_size = file_size.min(65536 + 22);
+    file.seek(SeekFrom::Start(file_size - tail_size))?;
+    let mut tail = vec![0u8; tail_size as usize];
+    file.read_exact(&mut tail)?;
+
+    let eocd = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| tail[i..i + 4] == [0x50, 0x4b, 0x05, 0x06])
+        .ok_or("未找到 ZIP 目录结束标记")?;
+    let central_directory = le_u32(&tail, eocd + 16).ok_or("ZIP 目录结束标记损坏")? as u64;
+    if central_directory < 24 || central_directory > file_size {
+        return Ok(Vec::new());
+    }
+
+    file.seek(SeekFrom::Start(central_directory - 24))?;
+    let mut footer = [0u8; 24];
+    file.read_exact(&mut footer)?;
+    if &footer[8..] != APK_SIG_BLOCK_MAGIC {
+        return Ok(Vec::new());
+    }
+
+    let block_size = le_u64(&footer, 0).ok_or("APK 签名块损坏")?;
+    let block_end = block_size.checked_add(8).ok_or("APK 签名块大小无效")?;
+    if block_end > central_directory || block_size < 24 {
+        return Err("APK 签名块大小无效".into());
+    }
+    let block_start = central_directory - block_size - 8;
+    file.seek(SeekFrom::Start(block_start + 8))?;
+    let mut pairs = vec![0u8; (block_size - 24) as usize];
+    file.read_exact(&mut pairs)?;
+
+    let mut certificates = Vec::new();
+    let mut offset = 0;
+    while let Some(length) = le_u64(&pairs, offset) {
+        let start = offset + 8;
+        let end = usize::try_from(length).ok()
+            .and_then(|length| start.checked_add(length))
+            .ok_or("APK 签名块大小无效")?;
+        let Some(pair) = pairs.get(start..end) else {
+            break;
+        };
+        offset = end;
+
+        let Some(id) = le_u32(pair, 0) else {
+            continue;
+        };
+        if !APK_SIGNATURE_SCHEME_IDS.contains(&id) {
+            continue;
+        }
+
+        for signers in length_prefixed(&pair[4..]) {
+            for signer in length_prefixed(signers) {
+                let Some(signed_data) = length_prefixed(signer).into_iter().next() else {
+                    continue;
+                };
+                let sections = length_prefixed(signed_data);
+                if let Some(encoded_certificates) = sections.get(1) {
+                    for certificate in length_prefixed(encoded_certificates) {
+                        if !certificates.iter().any(|c: &Vec<u8>| c == certificate) {
+                            certificates.push(certificate.to_vec());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(certificates)
+}
+
+fn read_v1_certificates(apk_path: &str) -> AppResult<Vec<Vec<u8>>> {
+    let mut archive = zip::ZipArchive::new(File::open(apk_path)?)?;
+    let signature_files: Vec<String> = archive.file_names()
+        .filter(|name| {
+            name.starts_with("META-INF/")
+                && (name.ends_with(".RSA") || name.ends_with(".DSA") || name.ends_with(".EC"))
+        })
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut certificates = Vec::new();
+    for name in signature_files {
+        let mut data = Vec::new();
+        archive.by_name(&name)?.read_to_end(&mut data)?;
+
+        // ContentInfo -> [0] SignedData -> [0] IMPLICIT certificates
+        let (content_info, _) = der::read_tlv(&data)?;
+        let Some(explicit) = der::children(content_info.content)?
+            .into_iter()
+            .find(|tlv| tlv.tag == der::TAG_CONTEXT_0) else {
+            continue;
+        };
+        let (signed_data, _) = der::read_tlv(explicit.content)?;
+        if let Some(certificate_set) = der::children(signed_data.content)?
+            .into_iter()
+            .find(|tlv| tlv.tag == der::TAG_CONTEXT_0)
+        {
+            for certificate in der::children(certificate_set.content)? {
+                if certificate.tag == der::TAG_SEQUENCE {
+                    certificates.push(certificate.raw.to_vec());
+                }
+            }
+        }
+    }
+
+    Ok(certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    const FIXTURE_CERT: &str = "689390665c1d4add61ec39104620ff0691c7b6ae882fb2b67a85da281ab0d484";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/appscan").join(name)
+    }
+
+    // 只安装了 HMA 官方包名的设备
+    struct OfficialHma;
+
+    impl pm::PackageManager for OfficialHma {
+        fn list_packages(&self, _: pm::PackageFilter) -> AppResult<Vec<pm::PackageInfo>> {
+            Ok(Vec::new())
+        }
+        fn package_paths(&self, package: &str) -> AppResult<Vec<PathBuf>> {
+            match package {
+                "icu.nullptr.hidemyapplist" => Ok(vec![fixture("v1_signed.apk")]),
+                _ => Err("not installed".into()),
+            }
+        }
+        fn uid_of(&self, _: &str) -> AppResult<Option<u32>> {
+            Ok(None)
+        }
+        fn install(&self, _: &Path) -> AppResult {
+            unreachable!()
+        }
+        fn uninstall(&self, _: &str) -> AppResult {
+            unreachable!()
+        }
+        fn disable_component(&self, _: &str) -> AppResult {
+            unreachable!()
+        }
+        fn start_service(&self, _: &str) -> AppResult {
+            unreachable!()
+        }
+    }
+
+    #[test]
+    fn reads_v1_signing_certificate() {
+        let digests = signing_cert_digests(&fixture("v1_signed.apk").to_string_lossy()).unwrap();
+        assert_eq!(digests, [FIXTURE_CERT]);
+    }
+
+    #[test]
+    fn learns_cert_digests_from_installed_official_app() {
+        let learned = learn_cert_digests(&OfficialHma, &builtin_signatures());
+        for signature in &learned {
+            let expected: &[&str] = if signature.id == "hma" { &[FIXTURE_CERT] } else { &[] };
+            assert_eq!(signature.cert_digests, expected, "{}", signature.id);
+        }
+
+        let hma: Vec<AppSignature> = learned.into_iter().filter(|signature| signature.id == "hma").collect();
+        let matches = inspect_apk(&fixture("v1_signed.apk").to_string_lossy(), &hma).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].confidence, Confidence::High);
+        assert!(matches[0].evidence.contains(&format!("签名证书 SHA-256: {}", FIXTURE_CERT)));
+    }
+
+    #[test]
+    fn apatch_signature_covers_both_managers() {
+        let apatch = builtin_signatures().into_iter().find(|signature| signature.id == "apatch").unwrap();
+        assert_eq!(apatch.packages, kpm::APATCH_PACKAGES);
+    }
+
+    // 签名块 + 指向签名块之后的 ZIP 目录结束标记
+    fn crafted_apk(name: &str, block_size: u64, pairs: &[u8]) -> String {
+        let mut data = Vec::new();
+        data.extend_from_slice(&block_size.to_le_bytes());
+        data.extend_from_slice(pairs);
+        data.extend_from_slice(&block_size.to_le_bytes());
+        data.extend_from_slice(APK_SIG_BLOCK_MAGIC);
+        let central_directory = data.len() as u32;
+        data.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06]);
+        data.extend_from_slice(&[0u8; 12]);
+        data.extend_from_slice(&central_directory.to_le_bytes());
+        data.extend_from_slice(&[0u8; 2]);
+
+        let path = std::env::temp_dir().join(format!("rshy-appscan-{}-{}.apk", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn assert_invalid_block(path: &str) {
+        let error = read_signing_block_certificates(path).unwrap_err();
+        let _ = fs::remove_file(path);
+        assert_eq!(error.to_string(), "APK 签名块大小无效");
+    }
+
+    #[test]
+    fn rejects_overflowing_block_size() {
+        assert_invalid_block(&crafted_apk("block", u64::MAX - 4, &[0u8; 8]));
+    }
+
+    #[test]
+    fn rejects_overflowing_pair_length() {
+        assert_invalid_block(&crafted_apk("pair", 32, &u64::MAX.to_le_bytes()));
+    }
+
+    #[test]
+    fn length_prefixed_stops_on_overflow() {
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"ok");
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(length_prefixed(&data), [b"ok".as_slice()]);
+    }
+}
@@ -0,0 +1,336 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::{der, AppResult};
+
+pub const BY_NAME_DIR: &str = "/dev/block/by-name";
+
+const VBMETA_MAGIC: &[u8; 4] = b"AVB0";
+const FOOTER_MAGIC: &[u8; 4] = b"AVBf";
+const HEADER_SIZE: usize = 256;
+const FOOTER_SIZE: u64 = 64;
+const MAX_VBMETA_SIZE: u64 = 64 * 1024;
+const MAX_CHAIN_DEPTH: usize = 4;
+
+const DESCRIPTOR_TAG_CHAIN_PARTITION: u64 = 4;
+// 描述符头 16 字节 + 链式分区描述符固定字段 76 字节
+const CHAIN_DESCRIPTOR_NAME_OFFSET: usize = 92;
+
+const FLAG_HASHTREE_DISABLED: u32 = 1;
+const FLAG_VERIFICATION_DISABLED: u32 = 2;
+// 无法从镜像得知引导加载程序的 libavb 版本，沿用常见值
+const DEFAULT_AVB_VERSION: &str = "1.2";
+
+fn be_u32(data: &[u8], offset: usize) -> AppResult<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "vbmeta 数据越界".into())
+}
+
+fn be_u64(data: &[u8], offset: usize) -> AppResult<u64> {
+    data.get(offset..offset + 8)
+        .map(|b| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(b);
+            u64::from_be_bytes(bytes)
+        })
+        .ok_or_else(|| "vbmeta 数据越界".into())
+}
+
+#[derive(Debug, Clone)]
+pub struct VbmetaImage {
+    pub partition: String,
+    pub data: Vec<u8>,
+    pub required_version: (u32, u32),
+    pub flags: u32,
+    pub release: String,
+    pub chained_partitions: Vec<String>,
+}
+
+impl VbmetaImage {
+    // data 从 AVB0 开始，多余的填充会被截掉
+    pub fn parse(partition: &str, data: &[u8]) -> AppResult<Self> {
+        if data.len() < HEADER_SIZE || &data[..4] != VBMETA_MAGIC {
+            return Err(format!("{} 不是有效的 vbmeta 镜像", partition).into());
+        }
+
+        let auth_size = be_u64(data, 12)?;
+        let aux_size = be_u64(data, 20)?;
+        let total = (HEADER_SIZE as u64).checked_add(auth_size).and_then(|n| n.checked_add(aux_size));
+        let total = match total {
+            Some(total) if total <= data.len() as u64 && total <= MAX_VBMETA_SIZE => total as usize,
+            _ => return Err(format!("{} 的 vbmeta 大小无效", partition).into()),
+        };
+        let data = &data[..total];
+
+        let aux = &data[HEADER_SIZE + auth_size as usize..];
+        let descriptors_offset = be_u64(data, 96)? as usize;
+        let descriptors_size = be_u64(data, 104)? as usize;
+        let descriptors = aux.get(descriptors_offset..descriptors_offset.saturating_add(descriptors_size))
+            .ok_or_else(|| format!("{} 的描述符越界", partition))?;
+
+        let release = String::from_utf8_lossy(&data[128..176])
+            .trim_end_matches('\0')
+            .to_string();
+
+        Ok(VbmetaImage {
+            partition: partition.to_string(),
+            data: data.to_vec(),
+            required_version: (be_u32(data, 4)?, be_u32(data, 8)?),
+            flags: be_u32(data, 120)?,
+            release,
+            chained_partitions: chained_partitions(descriptors)?,
+        })
+    }
+}
+
+fn chained_partitions(descriptors: &[u8]) -> AppResult<Vec<String>> {
+    let mut partitions = Vec::new();
+    let mut offset = 0;
+
+    while offset + 16 <= descriptors.len() {
+        let tag = be_u64(descriptors, offset)?;
+        let following = be_u64(descriptors, offset + 8)? as usize;
+        let end = offset.checked_add(16 + following).filter(|end| *end <= descriptors.len())
+            .ok_or("vbmeta 描述符越界")?;
+        let descriptor = &descriptors[offset..end];
+
+        if tag == DESCRIPTOR_TAG_CHAIN_PARTITION {
+            let name_length = be_u32(descriptor, 20)? as usize;
+            let name = descriptor.get(CHAIN_DESCRIPTOR_NAME_OFFSET..CHAIN_DESCRIPTOR_NAME_OFFSET + name_length)
+                .ok_or("链式分区描述符越界")?;
+            partitions.push(String::from_utf8_lossy(name).into_owned());
+        }
+
+        offset = end;
+    }
+
+    Ok(partitions)
+}
+
+// 分区本身是 vbmeta 镜像时直接读取，否则通过分区末尾的 AVB footer 定位
+pub fn read_partition_vbmeta(path: &Path) -> AppResult<Vec<u8>> {
+    let mut file = File::open(path)?;
+
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)?;
+    if &magic == VBMETA_MAGIC {
+        file.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::new();
+        file.take(MAX_VBMETA_SIZE).read_to_end(&mut data)?;
+        return Ok(data);
+    }
+
+    let size = file.seek(SeekFrom::End(0))?;
+    if size < FOOTER_SIZE {
+        return Err(format!("{} 太小，不包含 AVB footer", path.display()).into());
+    }
+    file.seek(SeekFrom::Start(size - FOOTER_SIZE))?;
+    let mut footer = [0u8; FOOTER_SIZE as usize];
+    file.read_exact(&mut footer)?;
+    if &footer[..4] != FOOTER_MAGIC {
+        return Err(format!("{} 没有 AVB footer", path.display()).into());
+    }
+
+    let vbmeta_offset = be_u64(&footer, 20)?;
+    let vbmeta_size = be_u64(&footer, 28)?;
+    if vbmeta_size > MAX_VBMETA_SIZE || vbmeta_offset.saturating_add(vbmeta_size) > size {
+        return Err(format!("{} 的 AVB footer 无效", path.display()).into());
+    }
+
+    file.seek(SeekFrom::Start(vbmeta_offset))?;
+    let mut data = vec![0u8; vbmeta_size as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[derive(Debug, Clone)]
+pub struct VbmetaChain {
+    pub images: Vec<VbmetaImage>,
+}
+
+impl VbmetaChain {
+    // 与 libavb 计算 ro.boot.vbmeta.digest 的方式一致：按加载顺序拼接所有 vbmeta 后取 SHA-256
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        for image in &self.images {
+            hasher.update(&image.data);
+        }
+        der::to_hex(&hasher.finalize())
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.images.iter().map(|image| image.data.len()).sum()
+    }
+
+    // 锁定且校验通过的设备上引导加载程序应当传递的属性值
+    pub fn expected_props(&self, live_avb_version: Option<&str>) -> Vec<(&'static str, String)> {
+        let (major, minor) = self.images[0].required_version;
+        let avb_version = match live_avb_version {
+            Some(version) if version_at_least(version, (major, minor)) => version.to_string(),
+            _ if (major, minor) > (1, 2) => format!("{}.{}", major, minor),
+            _ => DEFAULT_AVB_VERSION.to_string(),
+        };
+
+        vec![
+            ("ro.boot.vbmeta.digest", self.digest()),
+            ("ro.boot.vbmeta.size", self.total_size().to_string()),
+            ("ro.boot.vbmeta.hash_alg", "sha256".to_string()),
+            ("ro.boot.vbmeta.avb_version", avb_version),
+            ("ro.boot.vbmeta.device_state", "locked".to_string()),
+            ("ro.boot.vbmeta.invalidate_on_error", "yes".to_string()),
+            ("ro.boot.verifiedbootstate", "green".to_string()),
+            ("ro.boot.flash.locked", "1".to_string()),
+            ("ro.boot.veritymode", "enforcing".to_string()),
+        ]
+    }
+
+    // vbmeta 本身关闭了校验时，锁定状态下不可能正常启动，这本身就是检测点
+    pub fn image_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for image in &self.images {
+            if image.flags & FLAG_VERIFICATION_DISABLED != 0 {
+                problems.push(format!("{} 的 vbmeta 禁用了校验 (flags={})", image.partition, image.flags));
+            } else if image.flags & FLAG_HASHTREE_DISABLED != 0 {
+                problems.push(format!("{} 的 vbmeta 禁用了 hashtree (flags={})", image.partition, image.flags));
+            }
+        }
+        problems
+    }
+}
+
+fn version_at_least(version: &str, required: (u32, u32)) -> bool {
+    let mut parts = version.split('.').map(|part| part.trim().parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) >= required,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PropMismatch {
+    pub name: &'static str,
+    pub live: Option<String>,
+    pub expected: String,
+}
+
+pub fn audit_props(expected: &[(&'static str, String)], live: impl Fn(&str) -> Option<String>) -> Vec<PropMismatch> {
+    expected.iter()
+        .filter_map(|(name, value)| {
+            let current = live(name);
+            (current.as_deref() != Some(value.as_str())).then(|| PropMismatch {
+                name,
+                live: current,
+                expected: value.clone(),
+            })
+        })
+        .collect()
+}
+
+fn load_image(dir: &Path, partition: &str, slot_suffix: &str, depth: usize, images: &mut Vec<VbmetaImage>) -> AppResult {
+    if depth > MAX_CHAIN_DEPTH {
+        return Err("vbmeta 链式分区嵌套过深".into());
+    }
+
+    let slotted = dir.join(format!("{}{}", partition, slot_suffix));
+    let path = if slotted.exists() { slotted } else { dir.join(partition) };
+    let data = read_partition_vbmeta(&path)
+        .map_err(|e| format!("读取 {} 失败: {}", path.display(), e))?;
+
+    let image = VbmetaImage::parse(partition, &data)?;
+    let chained = image.chained_partitions.clone();
+    images.push(image);
+
+    for name in chained {
+        if images.iter().any(|image| image.partition == name) {
+            continue;
+        }
+        load_image(dir, &name, slot_suffix, depth + 1, images)?;
+    }
+    Ok(())
+}
+
+// dir 通常为 /dev/block/by-name，也可以是存放分区镜像的普通目录
+pub fn load_chain(dir: &Path, slot_suffix: &str) -> AppResult<VbmetaChain> {
+    let mut images = Vec::new();
+    load_image(dir, "vbmeta", slot_suffix, 0, &mut images)?;
+    Ok(VbmetaChain { images })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/avb")
+    }
+
+    #[test]
+    fn parses_vbmeta_header_and_chain_descriptors() {
+        let data = read_partition_vbmeta(&fixture_dir().join("vbmeta_a")).unwrap();
+        let image = VbmetaImage::parse("vbmeta", &data).unwrap();
+
+        assert_eq!(image.required_version, (1, 0));
+        assert_eq!(image.flags, 0);
+        assert_eq!(image.release, "avbtool 1.2.0");
+        assert_eq!(image.chained_partitions, ["vbmeta_system", "boot"]);
+        // 分区末尾的填充不属于 vbmeta
+        assert_eq!(image.data.len(), 832);
+    }
+
+    #[test]
+    fn reads_vbmeta_through_footer() {
+        let data = read_partition_vbmeta(&fixture_dir().join("boot_a")).unwrap();
+        assert_eq!(&data[..4], VBMETA_MAGIC);
+
+        let image = VbmetaImage::parse("boot", &data).unwrap();
+        assert_eq!(image.data.len(), data.len());
+        assert!(image.chained_partitions.is_empty());
+    }
+
+    #[test]
+    fn computes_chain_digest() {
+        let chain = load_chain(&fixture_dir(), "_a").unwrap();
+        let partitions: Vec<_> = chain.images.iter().map(|image| image.partition.as_str()).collect();
+        assert_eq!(partitions, ["vbmeta", "vbmeta_system", "boot"]);
+        assert_eq!(chain.digest(), "540658bda52f7a2c8c875736cf982864724fcd712d04e55c01aaeaae0d89397a");
+        assert_eq!(chain.total_size(), 1728);
+        assert!(chain.image_problems().is_empty());
+
+        let expected = chain.expected_props(None);
+        assert!(expected.contains(&("ro.boot.vbmeta.avb_version", "1.2".to_string())));
+        let mismatches = audit_props(&expected, |name| match name {
+            "ro.boot.vbmeta.digest" => Some(chain.digest()),
+            "ro.boot.vbmeta.size" => Some("1728".to_string()),
+            _ => None,
+        });
+        assert!(mismatches.iter().all(|m| m.name != "ro.boot.vbmeta.digest" && m.name != "ro.boot.vbmeta.size"));
+        assert_eq!(mismatches.len(), expected.len() - 2);
+    }
+
+    #[test]
+    fn reports_disabled_verification() {
+        let mut data = read_partition_vbmeta(&fixture_dir().join("vbmeta_a")).unwrap();
+        data[120..124].copy_from_slice(&FLAG_VERIFICATION_DISABLED.to_be_bytes());
+        let chain = VbmetaChain { images: vec![VbmetaImage::parse("vbmeta", &data).unwrap()] };
+        assert_eq!(chain.image_problems(), ["vbmeta 的 vbmeta 禁用了校验 (flags=2)"]);
+    }
+
+    #[test]
+    fn rejects_image_without_footer() {
+        let path = std::env::temp_dir().join(format!("rshy-avb-{}.img", std::process::id()));
+        fs::write(&path, [0u8; 4096]).unwrap();
+        let error = read_partition_vbmeta(&path).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(error.to_string().ends_with("没有 AVB footer"));
+
+        let mut data = fs::read(fixture_dir().join("vbmeta_a")).unwrap();
+        data[..4].copy_from_slice(b"NOPE");
+        assert!(VbmetaImage::parse("vbmeta", &data).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+use std::fs::File;
+use std::io::Read;
+
+use crate::AppResult;
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_INDEX: u32 = 0xFFFF_FFFF;
+// 第三方 APK 的 zip 头中声明的大小不可信，清单超过这个大小时视为异常
+const MAX_MANIFEST_SIZE: u64 = 8 << 20;
+
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+// 混淆过的清单会清空属性名字符串，只能靠资源 ID 识别
+const KNOWN_ATTRIBUTES: &[(u32, &str)] = &[
+    (0x0101_0003, "name"),
+    (0x0101_0024, "value"),
+    (0x0101_0025, "resource"),
+    (0x0101_0018, "authorities"),
+    (0x0101_0010, "exported"),
+    (0x0101_000e, "enabled"),
+    (0x0101_0006, "permission"),
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub package: Option<String>,
+    pub application_class: Option<String>,
+    pub components: Vec<Component>,
+    pub meta_data: Vec<(String, String)>,
+    pub permissions: Vec<String>,
+    pub elements: Vec<Element>,
+    pub strings: Vec<String>,
+}
+
+impl Manifest {
+    pub fn parse(data: &[u8]) -> AppResult<Self> {
+        let document = parse_document(data)?;
+        let mut manifest = Manifest {
+            strings: document.strings,
+            ..Default::default()
+        };
+
+        for element in &document.elements {
+            match element.name.as_str() {
+                "manifest" => {
+                    manifest.package = element.attribute("package").map(|s| s.to_string());
+                }
+                "application" => {
+                    manifest.application_class = element.attribute("name").map(|s| s.to_string());
+                }
+                "activity" | "activity-alias" | "service" | "receiver" | "provider" => {
+                    if let Some(name) = element.attribute("name") {
+                        manifest.components.push(Component {
+                            kind: element.name.clone(),
+                            name: name.to_string(),
+                        });
+                    }
+                }
+                "meta-data" => {
+                    if let Some(name) = element.attribute("name") {
+                        let value = element.attribute("value")
+                            .or_else(|| element.attribute("resource"))
+                            .unwrap_or("");
+                        manifest.meta_data.push((name.to_string(), value.to_string()));
+                    }
+                }
+                "uses-permission" | "uses-permission-sdk-23" => {
+                    if let Some(name) = element.attribute("name") {
+                        manifest.permissions.push(name.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(package) = manifest.package.clone() {
+            manifest.application_class = manifest.application_class
+                .map(|class| resolve_class_name(&package, &class));
+            for component in &mut manifest.components {
+                component.name = resolve_class_name(&package, &component.name);
+            }
+        }
+        manifest.elements = document.elements;
+
+        Ok(manifest)
+    }
+
+    // 与 aapt dump xmltree 后 grep 的效果一致：字符串池中任意位置出现即算命中
+    pub fn mentions(&self, marker: &str) -> bool {
+        self.strings.iter().any(|s| s.contains(marker))
+    }
+}
+
+pub fn read_apk_manifest(apk_path: &str) -> AppResult<Manifest> {
+    let file = File::open(apk_path)?;
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut entry = archive.by_name("AndroidManifest.xml")?;
+
+    let mut data = Vec::with_capacity(entry.size().min(MAX_MANIFEST_SIZE) as usize);
+    (&mut entry).take(MAX_MANIFEST_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_MANIFEST_SIZE {
+        return Err(format!("{} 的清单文件过大", apk_path).into());
+    }
+
+    Manifest::parse(&data)
+}
+
+fn resolve_class_name(package: &str, class: &str) -> String {
+    if class.starts_with('.') {
+        format!("{}{}", package, class)
+    } else if !class.contains('.') {
+        format!("{}.{}", package, class)
+    } else {
+        class.to_string()
+    }
+}
+
+struct Document {
+    strings: Vec<String>,
+    elements: Vec<Element>,
+}
+
+fn corrupted(what: &str) -> Box<dyn std::error::Error> {
+    format!("AXML 数据损坏: {}", what).into()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> AppResult<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| corrupted("读取越界"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> AppResult<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| corrupted("读取越界"))
+}
+
+fn parse_document(data: &[u8]) -> AppResult<Document> {
+    if read_u16(data, 0)? != RES_XML_TYPE {
+        return Err(corrupted("不是二进制 XML 文件"));
+    }
+
+    let header_size = read_u16(data, 2)? as usize;
+    let total_size = (read_u32(data, 4)? as usize).min(data.len());
+
+    let mut strings = Vec::new();
+    let mut resource_ids = Vec::new();
+    let mut elements = Vec::new();
+
+    let mut offset = header_size;
+    while offset + 8 <= total_size {
+        let chunk_type = read_u16(data, offset)?;
+        let chunk_header_size = read_u16(data, offset + 2)? as usize;
+        let chunk_size = read_u32(data, offset + 4)? as usize;
+        if chunk_size < 8 || offset + chunk_size > total_size {
+            return Err(corrupted("块大小无效"));
+        }
+        let chunk = &data[offset..offset + chunk_size];
+
+        match chunk_type {
+            RES_STRING_POOL_TYPE => {
+                strings = parse_string_pool(chunk)?;
+            }
+            RES_XML_RESOURCE_MAP_TYPE => {
+                resource_ids = (chunk_header_size..chunk_size)
+                    .step_by(4)
+                    .filter_map(|pos| read_u32(chunk, pos).ok())
+                    .collect();
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                elements.push(parse_start_element(chunk, chunk_header_size, &strings, &resource_ids)?);
+            }
+            _ => {}
+        }
+
+        offset += chunk_size;
+    }
+
+    Ok(Document { strings, elements })
+}
+
+fn parse_string_pool(chunk: &[u8]) -> AppResult<Vec<String>> {
+    let string_count = read_u32(chunk, 8)? as usize;
+    let flags = read_u32(chunk, 16)?;
+    let strings_start = read_u32(chunk, 20)? as usize;
+    let header_size = read_u16(chunk, 2)? as usize;
+    let utf8 = flags & UTF8_FLAG != 0;
+
+    let mut strings = Vec::with_capacity(string_count.min(chunk.len() / 4));
+    for i in 0..string_count {
+        let string_offset = read_u32(chunk, header_size + i * 4)? as usize;
+        let position = strings_start + string_offset;
+        let value = if utf8 {
+            decode_utf8_string(chunk, position)
+        } else {
+            decode_utf16_string(chunk, position)
+        };
+        strings.push(value.unwrap_or_default());
+    }
+
+    Ok(strings)
+}
+
+// 长度字段占 1 或 2 个字节，返回 (长度, 占用字节数)
+fn decode_utf8_length(chunk: &[u8], position: usize) -> AppResult<(usize, usize)> {
+    let first = *chunk.get(position).ok_or_else(|| corrupted("字符串越界"))? as usize;
+    if first & 0x80 != 0 {
+        let second = *chunk.get(position + 1).ok_or_else(|| corrupted("字符串越界"))? as usize;
+        Ok((((first & 0x7f) << 8) | second, 2))
+    } else {
+        Ok((first, 1))
+    }
+}
+
+fn decode_utf8_string(chunk: &[u8], position: usize) -> AppResult<String> {
+    let (_, char_length_size) = decode_utf8_length(chunk, position)?;
+    let (byte_length, byte_length_size) = decode_utf8_length(chunk, position + char_length_size)?;
+    let start = position + char_length_size + byte_length_size;
+
+    let bytes = chunk.get(start..start + byte_length).ok_or_else(|| corrupted("字符串越界"))?;
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn decode_utf16_string(chunk: &[u8], position: usize) -> AppResult<String> {
+    let mut length = read_u16(chunk, position)? as usize;
+    let mut start = position + 2;
+    if length & 0x8000 != 0 {
+        length = ((length & 0x7fff) << 16) | read_u16(chunk, start)? as usize;
+        start += 2;
+    }
+
+    let units = (0..length)
+        .map(|i| read_u16(chunk, start + i * 2))
+        .collect::<AppResult<Vec<u16>>>()?;
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn string_at(strings: &[String], index: u32) -> Option<&str> {
+    if index == NO_INDEX {
+        return None;
+    }
+    strings.get(index as usize).map(|s| s.as_str())
+}
+
+fn parse_start_element(
+    chunk: &[u8],
+    header_size: usize,
+    strings: &[String],
+    resource_ids: &[u32],
+) -> AppResult<Element> {
+    let ext = header_size;
+    let name_index = read_u32(chunk, ext + 4)?;
+    let attribute_start = read_u16(chunk, ext + 8)? as usize;
+    let attribute_size = read_u16(chunk, ext + 10)? as usize;
+    let attribute_count = read_u16(chunk, ext + 12)? as usize;
+
+    let mut element = Element {
+        name: string_at(strings, name_index).unwrap_or("").to_string(),
+        attributes: Vec::with_capacity(attribute_count),
+    };
+
+    for i in 0..attribute_count {
+        let attr = ext + attribute_start + i * attribute_size.max(20);
+        let name_index = read_u32(chunk, attr + 4)?;
+        let raw_value = read_u32(chunk, attr + 8)?;
+        let data_type = *chunk.get(attr + 15).ok_or_else(|| corrupted("属性越界"))?;
+        let data = read_u32(chunk, attr + 16)?;
+
+        let name = match string_at(strings, name_index) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => attribute_name_from_resource(resource_ids, name_index),
+        };
+        let value = format_value(strings, raw_value, data_type, data);
+        element.attributes.push((name, value));
+    }
+
+    Ok(element)
+}
+
+fn attribute_name_from_resource(resource_ids: &[u32], index: u32) -> String {
+    match resource_ids.get(index as usize) {
+        Some(id) => KNOWN_ATTRIBUTES.iter()
+            .find(|(known, _)| known == id)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| format!("0x{:08x}", id)),
+        None => String::new(),
+    }
+}
+
+fn format_value(strings: &[String], raw_value: u32, data_type: u8, data: u32) -> String {
+    match data_type {
+        TYPE_STRING => string_at(strings, data).unwrap_or("").to_string(),
+        _ if raw_value != NO_INDEX => string_at(strings, raw_value).unwrap_or("").to_string(),
+        TYPE_INT_DEC => (data as i32).to_string(),
+        TYPE_INT_HEX => format!("0x{:x}", data),
+        TYPE_INT_BOOLEAN => (data != 0).to_string(),
+        TYPE_REFERENCE => format!("@0x{:08x}", data),
+        _ => format!("0x{:08x}", data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/axml/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn parses_utf16_manifest() {
+        let manifest = read_apk_manifest(&fixture("utf16.apk")).unwrap();
+        assert_eq!(manifest.package.as_deref(), Some("com.random.xyz"));
+        assert_eq!(manifest.application_class.as_deref(), Some("com.random.xyz.App"));
+        assert_eq!(manifest.permissions, ["android.permission.INTERNET"]);
+        assert_eq!(manifest.components, [Component {
+            kind: "service".to_string(),
+            name: "icu.nullptr.hidemyapplist.Service".to_string(),
+        }]);
+        assert!(manifest.meta_data.contains(&("xposedmodule".to_string(), "true".to_string())));
+        assert!(manifest.meta_data.contains(&("中文描述".to_string(), "93".to_string())));
+        assert!(manifest.mentions("hidemyapplist"));
+    }
+
+    // 属性名字符串被清空的混淆清单，只能通过资源 ID 还原属性名
+    #[test]
+    fn parses_obfuscated_utf8_manifest() {
+        let manifest = read_apk_manifest(&fixture("utf8.apk")).unwrap();
+        assert_eq!(manifest.package.as_deref(), Some("com.random.xyz"));
+        assert_eq!(manifest.strings.len(), 14);
+        assert_eq!(manifest.strings[0], "");
+        assert!(manifest.strings.iter().any(|s| s == "中文描述"));
+        assert!(manifest.meta_data.contains(&("xposedmodule".to_string(), "true".to_string())));
+    }
+
+    #[test]
+    fn rejects_non_axml() {
+        assert!(Manifest::parse(b"<manifest/>").is_err());
+        // 字符串池块声明的大小小于块头
+        let bad_chunk = [0x03, 0x00, 0x08, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x1c, 0x00, 0x04, 0x00, 0x00, 0x00];
+        assert!(Manifest::parse(&bad_chunk).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_manifest() {
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("rshy-axml-{}.apk", std::process::id()));
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        writer.start_file("AndroidManifest.xml", options).unwrap();
+        writer.write_all(&vec![0u8; MAX_MANIFEST_SIZE as usize + 1]).unwrap();
+        writer.finish().unwrap();
+
+        let error = read_apk_manifest(path.to_str().unwrap()).unwrap_err();
+        let _ = std::fs::remove_file(&path);
+        assert!(error.to_string().ends_with("的清单文件过大"));
+    }
+}
@@ -1,22 +1,132 @@
-use std::fs;
 use std::env;
-use base64::prelude::*;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+#[path = "../bundle.rs"]
+mod bundle;
+
+use bundle::{Asset, Bundle, Manifest, MANIFEST_NAME};
+
+const KNOWN_ABIS: [&str; 4] = ["arm64-v8a", "armeabi-v7a", "x86_64", "x86"];
+const ANY_ABI: &str = "any";
+
+// Files under <abi>/ belong to that ABI; other ELF files are tagged from e_machine
+fn detect_abi(data: &[u8]) -> &'static str {
+    if data.len() < 20 || &data[..4] != b"\x7fELF" {
+        return ANY_ABI;
+    }
+    match u16::from_le_bytes([data[18], data[19]]) {
+        183 => "arm64-v8a",
+        40 => "armeabi-v7a",
+        62 => "x86_64",
+        3 => "x86",
+        _ => ANY_ABI,
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn build_bundle(asset_dir: &Path, version: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut files = Vec::new();
+    collect_files(asset_dir, &mut files)?;
+    files.sort();
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut manifest = Manifest {
+        version: version.to_string(),
+        assets: Vec::new(),
+    };
+
+    for file in &files {
+        let relative = file.strip_prefix(asset_dir)?.to_string_lossy().replace('\\', "/");
+        let data = fs::read(file)?;
+
+        let (abi, name) = match relative.split_once('/') {
+            Some((abi, name)) if KNOWN_ABIS.contains(&abi) => (abi.to_string(), name.to_string()),
+            _ => (detect_abi(&data).to_string(), relative.clone()),
+        };
+        if manifest.assets.iter().any(|asset| asset.name == name && asset.abi == abi) {
+            return Err(format!("duplicate asset {} for {}", name, abi).into());
+        }
+
+        writer.start_file(relative.as_str(), options)?;
+        writer.write_all(&data)?;
+        println!("  {} ({}, {} bytes)", name, abi, data.len());
+
+        manifest.assets.push(Asset {
+            name,
+            path: relative,
+            abi,
+            version: version.to_string(),
+            size: data.len() as u64,
+            sha256: bundle::sha256_hex(&data),
+        });
+    }
+
+    writer.start_file(MANIFEST_NAME, options)?;
+    writer.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok(writer.finish()?.into_inner())
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input_file> <output_data_file>", args[0]);
+    let (version, positional) = match args.iter().position(|arg| arg == "--version") {
+        Some(index) if index + 1 < args.len() => {
+            let mut positional = args.clone();
+            let version = positional.remove(index + 1);
+            positional.remove(index);
+            (version, positional)
+        }
+        _ => (env!("CARGO_PKG_VERSION").to_string(), args.clone()),
+    };
+    if positional.len() != 3 {
+        eprintln!("Usage: {} <asset_dir> <output_bundle> [--version <version>]", args[0]);
         std::process::exit(1);
     }
 
-    let input_file = &args[1];
-    let output_file = &args[2];
+    let asset_dir = Path::new(&positional[1]);
+    let output_file = &positional[2];
+
+    let data = match build_bundle(asset_dir, &version) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Failed to bundle {}: {}", asset_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
 
-    let data = fs::read(input_file).expect("Failed to read input file");
+    // Read everything back so a broken bundle never gets embedded
+    let verified = Bundle::open(&data).and_then(|bundle| {
+        bundle.manifest.assets.iter().try_for_each(|asset| bundle.read(asset).map(|_| ()))?;
+        Ok(bundle.manifest.assets.len())
+    });
+    let count = match verified {
+        Ok(count) => count,
+        Err(e) => {
+            eprintln!("Bundle verification failed: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let encoded = BASE64_STANDARD.encode(&data);
+    fs::write(output_file, &data).expect("Failed to write output file");
 
-    fs::write(output_file, encoded).expect("Failed to write output file");
-    
-    println!("Encoded {} to {}", input_file, output_file);
-}
\ No newline at end of file
+    println!("Bundled {} assets from {} to {} ({} bytes)", count, asset_dir.display(), output_file, data.len());
+}
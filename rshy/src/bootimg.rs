@@ -0,0 +1,441 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{der, AppResult};
+
+const BOOT_MAGIC: &[u8; 8] = b"ANDROID!";
+const VENDOR_BOOT_MAGIC: &[u8; 8] = b"VNDRBOOT";
+const AVB_FOOTER_MAGIC: &[u8; 4] = b"AVBf";
+
+// v3 及以上的 boot 镜像固定使用 4096 字节的页
+const BOOT_V3_PAGE_SIZE: usize = 4096;
+const BOOT_V0_HEADER_SIZE: usize = 1632;
+const BOOT_V1_HEADER_SIZE: usize = 1648;
+const BOOT_V2_HEADER_SIZE: usize = 1660;
+const BOOT_V3_HEADER_SIZE: usize = 1580;
+const BOOT_V4_HEADER_SIZE: usize = 1584;
+const VENDOR_V3_HEADER_SIZE: usize = 2112;
+const VENDOR_V4_HEADER_SIZE: usize = 2128;
+
+const BOOT_ID_OFFSET: usize = 576;
+const BOOT_ID_SIZE: usize = 32;
+const RECOVERY_DTBO_OFFSET_FIELD: usize = 1636;
+const ARM64_IMAGE_MAGIC_OFFSET: usize = 0x38;
+
+fn le_u32(data: &[u8], offset: usize) -> AppResult<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "镜像头越界".into())
+}
+
+fn align(value: usize, page_size: usize) -> usize {
+    value.div_ceil(page_size) * page_size
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Boot,
+    VendorBoot,
+}
+
+impl ImageKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImageKind::Boot => "boot",
+            ImageKind::VendorBoot => "vendor_boot",
+        }
+    }
+}
+
+// 各版本镜像头中的区段顺序，以及记录区段大小的字段偏移
+fn section_layout(kind: ImageKind, version: u32) -> Vec<(&'static str, usize)> {
+    match (kind, version) {
+        (ImageKind::Boot, 0) => vec![("kernel", 8), ("ramdisk", 16), ("second", 24)],
+        (ImageKind::Boot, 1) => vec![("kernel", 8), ("ramdisk", 16), ("second", 24), ("recovery_dtbo", 1632)],
+        (ImageKind::Boot, 2) => vec![("kernel", 8), ("ramdisk", 16), ("second", 24), ("recovery_dtbo", 1632), ("dtb", 1648)],
+        (ImageKind::Boot, 3) => vec![("kernel", 8), ("ramdisk", 12)],
+        (ImageKind::Boot, _) => vec![("kernel", 8), ("ramdisk", 12), ("signature", 1580)],
+        (ImageKind::VendorBoot, 3) => vec![("vendor_ramdisk", 24), ("dtb", 2100)],
+        (ImageKind::VendorBoot, _) => vec![("vendor_ramdisk", 24), ("dtb", 2100), ("vendor_ramdisk_table", 2112), ("bootconfig", 2124)],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    Sha1,
+    Sha256,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: &'static str,
+    size_field: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BootImage {
+    pub kind: ImageKind,
+    pub header_version: u32,
+    pub page_size: usize,
+    // 镜像头所在的整页，重新打包时原样写回，只更新大小、偏移和校验字段
+    header: Vec<u8>,
+    pub sections: Vec<Section>,
+    // 最后一个区段之后的数据，从分区直接读取时通常是填充和 AVB footer
+    pub tail: Vec<u8>,
+}
+
+impl BootImage {
+    pub fn parse(data: &[u8]) -> AppResult<Self> {
+        let magic = data.get(..8).ok_or("文件太小，不是 boot 镜像")?;
+        let (kind, header_version) = if magic == BOOT_MAGIC {
+            (ImageKind::Boot, le_u32(data, 40)?)
+        } else if magic == VENDOR_BOOT_MAGIC {
+            (ImageKind::VendorBoot, le_u32(data, 8)?)
+        } else {
+            return Err("不是 boot 或 vendor_boot 镜像".into());
+        };
+
+        let (header_size, page_size) = match (kind, header_version) {
+            (ImageKind::Boot, 0) => (BOOT_V0_HEADER_SIZE, le_u32(data, 36)? as usize),
+            (ImageKind::Boot, 1) => (BOOT_V1_HEADER_SIZE, le_u32(data, 36)? as usize),
+            (ImageKind::Boot, 2) => (BOOT_V2_HEADER_SIZE, le_u32(data, 36)? as usize),
+            (ImageKind::Boot, 3) => (BOOT_V3_HEADER_SIZE, BOOT_V3_PAGE_SIZE),
+            (ImageKind::Boot, 4) => (BOOT_V4_HEADER_SIZE, BOOT_V3_PAGE_SIZE),
+            (ImageKind::VendorBoot, 3) => (VENDOR_V3_HEADER_SIZE, le_u32(data, 12)? as usize),
+            (ImageKind::VendorBoot, 4) => (VENDOR_V4_HEADER_SIZE, le_u32(data, 12)? as usize),
+            _ => return Err(format!("不支持的 {} 镜像头版本: {}", kind.label(), header_version).into()),
+        };
+        if !page_size.is_power_of_two() || !(2048..=65536).contains(&page_size) {
+            return Err(format!("无效的页大小: {}", page_size).into());
+        }
+
+        let header_end = align(header_size, page_size);
+        let header = data.get(..header_end).ok_or("镜像头不完整")?.to_vec();
+
+        let mut sections = Vec::new();
+        let mut offset = header_end;
+        for (name, size_field) in section_layout(kind, header_version) {
+            let size = le_u32(&header, size_field)? as usize;
+            let section = offset.checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .ok_or_else(|| format!("{} 区段超出镜像范围", name))?;
+            sections.push(Section { name, size_field, data: section.to_vec() });
+            offset = align(offset + size, page_size).min(data.len());
+        }
+
+        Ok(BootImage {
+            kind,
+            header_version,
+            page_size,
+            header,
+            sections,
+            tail: data[offset..].to_vec(),
+        })
+    }
+
+    pub fn load(path: &str) -> AppResult<Self> {
+        let data = fs::read(path).map_err(|e| format!("读取 {} 失败: {}", path, e))?;
+        Self::parse(&data).map_err(|e| format!("{}: {}", path, e).into())
+    }
+
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        self.sections.iter().find(|section| section.name == name).map(|section| section.data.as_slice())
+    }
+
+    pub fn kernel(&self) -> Option<&[u8]> {
+        self.section("kernel")
+    }
+
+    pub fn replace_kernel(&mut self, kernel: Vec<u8>) -> AppResult {
+        if self.kind != ImageKind::Boot {
+            return Err("vendor_boot 镜像不包含内核".into());
+        }
+        let section = self.sections.iter_mut()
+            .find(|section| section.name == "kernel")
+            .ok_or("镜像中没有内核区段")?;
+        section.data = kernel;
+        Ok(())
+    }
+
+    pub fn has_avb_footer(&self) -> bool {
+        self.tail.len() >= 64 && &self.tail[self.tail.len() - 64..self.tail.len() - 60] == AVB_FOOTER_MAGIC
+    }
+
+    // 只有 v0-v2 的 boot 镜像在头中保存校验值；与 magiskboot 一样，id 后 12 字节不为零时视为 SHA-256
+    pub fn checksum_kind(&self) -> Option<ChecksumKind> {
+        if self.kind != ImageKind::Boot || self.header_version > 2 {
+            return None;
+        }
+        let id = &self.header[BOOT_ID_OFFSET..BOOT_ID_OFFSET + BOOT_ID_SIZE];
+        if id[20..].iter().any(|b| *b != 0) {
+            Some(ChecksumKind::Sha256)
+        } else {
+            Some(ChecksumKind::Sha1)
+        }
+    }
+
+    // 与 mkbootimg 相同：依次计算每个区段的内容及其 32 位小端长度
+    fn compute_id(&self, kind: ChecksumKind) -> Vec<u8> {
+        let mut id = vec![0u8; BOOT_ID_SIZE];
+        match kind {
+            ChecksumKind::Sha1 => {
+                let mut hasher = Sha1::new();
+                for section in &self.sections {
+                    hasher.update(&section.data);
+                    hasher.update((section.data.len() as u32).to_le_bytes());
+                }
+                id[..20].copy_from_slice(&hasher.finalize());
+            }
+            ChecksumKind::Sha256 => {
+                let mut hasher = Sha256::new();
+                for section in &self.sections {
+                    hasher.update(&section.data);
+                    hasher.update((section.data.len() as u32).to_le_bytes());
+                }
+                id.copy_from_slice(&hasher.finalize());
+            }
+        }
+        id
+    }
+
+    pub fn checksum_valid(&self) -> Option<bool> {
+        let kind = self.checksum_kind()?;
+        Some(self.header[BOOT_ID_OFFSET..BOOT_ID_OFFSET + BOOT_ID_SIZE] == self.compute_id(kind)[..])
+    }
+
+    // 按页对齐重新拼接镜像并更新大小、recovery_dtbo 偏移和校验值，不包含原镜像尾部的数据
+    pub fn repack(&self) -> AppResult<Vec<u8>> {
+        let mut header = self.header.clone();
+        for section in &self.sections {
+            let size = u32::try_from(section.data.len()).map_err(|_| format!("{} 区段过大", section.name))?;
+            header[section.size_field..section.size_field + 4].copy_from_slice(&size.to_le_bytes());
+        }
+
+        let mut output = header;
+        for section in &self.sections {
+            if section.name == "recovery_dtbo" && !section.data.is_empty() {
+                let offset = (output.len() as u64).to_le_bytes();
+                output[RECOVERY_DTBO_OFFSET_FIELD..RECOVERY_DTBO_OFFSET_FIELD + 8].copy_from_slice(&offset);
+            }
+            output.extend_from_slice(&section.data);
+            output.resize(align(output.len(), self.page_size), 0);
+        }
+
+        if let Some(kind) = self.checksum_kind() {
+            let id = self.compute_id(kind);
+            output[BOOT_ID_OFFSET..BOOT_ID_OFFSET + BOOT_ID_SIZE].copy_from_slice(&id);
+        }
+
+        Ok(output)
+    }
+
+    // 重新解析打包结果，确认各区段内容与校验值都与预期一致
+    pub fn repack_checked(&self) -> AppResult<Vec<u8>> {
+        let data = self.repack()?;
+        let repacked = Self::parse(&data).map_err(|e| format!("重新打包的镜像无法解析: {}", e))?;
+        let consistent = repacked.sections.len() == self.sections.len()
+            && repacked.sections.iter().zip(&self.sections).all(|(a, b)| a.data == b.data)
+            && repacked.checksum_valid() != Some(false);
+        if !consistent {
+            return Err("重新打包的镜像内容与预期不一致".into());
+        }
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelFormat {
+    Raw,
+    Arm64Image,
+    Gzip,
+    Lz4Legacy,
+    Lz4,
+    Xz,
+    Lzma,
+    Bzip2,
+    Zstd,
+}
+
+impl KernelFormat {
+    pub fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x1f, 0x8b, ..] | [0x1f, 0x9e, ..] => KernelFormat::Gzip,
+            [0x02, 0x21, 0x4c, 0x18, ..] => KernelFormat::Lz4Legacy,
+            [0x04, 0x22, 0x4d, 0x18, ..] => KernelFormat::Lz4,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => KernelFormat::Xz,
+            [0x5d, 0x00, 0x00, ..] => KernelFormat::Lzma,
+            [b'B', b'Z', b'h', ..] => KernelFormat::Bzip2,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => KernelFormat::Zstd,
+            _ if data.get(ARM64_IMAGE_MAGIC_OFFSET..ARM64_IMAGE_MAGIC_OFFSET + 4) == Some(b"ARMd") => KernelFormat::Arm64Image,
+            _ => KernelFormat::Raw,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        !matches!(self, KernelFormat::Raw | KernelFormat::Arm64Image)
+    }
+
+    // magiskboot compress= 使用的格式名
+    pub fn label(&self) -> &'static str {
+        match self {
+            KernelFormat::Raw => "raw",
+            KernelFormat::Arm64Image => "arm64",
+            KernelFormat::Gzip => "gzip",
+            KernelFormat::Lz4Legacy => "lz4_legacy",
+            KernelFormat::Lz4 => "lz4",
+            KernelFormat::Xz => "xz",
+            KernelFormat::Lzma => "lzma",
+            KernelFormat::Bzip2 => "bzip2",
+            KernelFormat::Zstd => "zstd",
+        }
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    der::to_hex(&Sha256::digest(data))
+}
+
+// 压缩格式的内核暂时交给 magiskboot 处理，并检查其输出是否符合预期
+fn run_magiskboot(magiskboot: &Path, action: &str, input: &[u8], work_dir: &Path) -> AppResult<Vec<u8>> {
+    let work_dir = work_dir.join(".magiskboot");
+    fs::create_dir_all(&work_dir)?;
+    let input_path = work_dir.join("kernel.in");
+    let output_path = work_dir.join("kernel.out");
+    let _ = fs::remove_file(&output_path);
+    fs::write(&input_path, input)?;
+
+    let result = Command::new(magiskboot)
+        .arg(action)
+        .arg(&input_path)
+        .arg(&output_path)
+        .output();
+    let output = fs::read(&output_path);
+    let _ = fs::remove_file(&input_path);
+    let _ = fs::remove_file(&output_path);
+    let _ = fs::remove_dir(&work_dir);
+
+    let result = result.map_err(|e| format!("无法执行 {}: {}", magiskboot.display(), e))?;
+    if !result.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&result.stderr).trim());
+        return Err(format!("magiskboot {} 执行失败", action).into());
+    }
+    match output {
+        Ok(data) if !data.is_empty() => Ok(data),
+        _ => Err(format!("magiskboot {} 没有生成输出文件", action).into()),
+    }
+}
+
+pub fn decompress_kernel(magiskboot: &Path, kernel: &[u8], work_dir: &Path) -> AppResult<Vec<u8>> {
+    let format = KernelFormat::detect(kernel);
+    if !format.is_compressed() {
+        return Ok(kernel.to_vec());
+    }
+
+    let output = run_magiskboot(magiskboot, "decompress", kernel, work_dir)?;
+    if KernelFormat::detect(&output).is_compressed() {
+        return Err(format!("magiskboot 未能解压 {} 格式的内核", format.label()).into());
+    }
+    Ok(output)
+}
+
+pub fn compress_kernel(magiskboot: &Path, kernel: &[u8], format: KernelFormat, work_dir: &Path) -> AppResult<Vec<u8>> {
+    if !format.is_compressed() {
+        return Ok(kernel.to_vec());
+    }
+
+    let output = run_magiskboot(magiskboot, &format!("compress={}", format.label()), kernel, work_dir)?;
+    let actual = KernelFormat::detect(&output);
+    if actual != format {
+        return Err(format!("magiskboot 输出的内核格式为 {}，应为 {}", actual.label(), format.label()).into());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(format!("{}/tests/fixtures/bootimg/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    // 文件名、镜像头版本、页大小、区段、校验类型
+    type Case = (&'static str, u32, usize, &'static [&'static str], Option<ChecksumKind>);
+
+    fn section_names(image: &BootImage) -> Vec<&'static str> {
+        image.sections.iter().map(|section| section.name).collect()
+    }
+
+    #[test]
+    fn round_trips_boot_images() {
+        let cases: [Case; 5] = [
+            ("v0.img", 0, 2048, &["kernel", "ramdisk", "second"], Some(ChecksumKind::Sha1)),
+            ("v1.img", 1, 2048, &["kernel", "ramdisk", "second", "recovery_dtbo"], Some(ChecksumKind::Sha1)),
+            ("v2.img", 2, 4096, &["kernel", "ramdisk", "second", "recovery_dtbo", "dtb"], Some(ChecksumKind::Sha1)),
+            ("v3.img", 3, 4096, &["kernel", "ramdisk"], None),
+            ("v4.img", 4, 4096, &["kernel", "ramdisk", "signature"], None),
+        ];
+
+        for (name, version, page_size, sections, checksum) in cases {
+            let data = fixture(name);
+            let image = BootImage::parse(&data).unwrap();
+            assert_eq!(image.kind, ImageKind::Boot, "{}", name);
+            assert_eq!(image.header_version, version, "{}", name);
+            assert_eq!(image.page_size, page_size, "{}", name);
+            assert_eq!(section_names(&image), sections, "{}", name);
+            assert_eq!(image.checksum_kind(), checksum, "{}", name);
+            assert_eq!(image.checksum_valid(), checksum.map(|_| true), "{}", name);
+            assert!(image.tail.is_empty(), "{}", name);
+            assert_eq!(image.repack_checked().unwrap(), data, "{}", name);
+        }
+    }
+
+    #[test]
+    fn round_trips_vendor_boot() {
+        let data = fixture("vendor.img");
+        let image = BootImage::parse(&data).unwrap();
+        assert_eq!(image.kind, ImageKind::VendorBoot);
+        assert_eq!(image.header_version, 4);
+        assert_eq!(section_names(&image), ["vendor_ramdisk", "dtb", "vendor_ramdisk_table", "bootconfig"]);
+        assert_eq!(image.section("bootconfig"), Some(b"bootconfig=1\n".as_slice()));
+        assert_eq!(image.checksum_kind(), None);
+        assert_eq!(image.repack_checked().unwrap(), data);
+
+        let mut image = image;
+        assert!(image.replace_kernel(vec![0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn detects_avb_footer_in_tail() {
+        let image = BootImage::parse(&fixture("v4_footer.img")).unwrap();
+        assert!(image.has_avb_footer());
+        assert_eq!(image.tail.len(), 4096);
+        assert!(!BootImage::parse(&image.repack_checked().unwrap()).unwrap().has_avb_footer());
+    }
+
+    #[test]
+    fn replacing_kernel_updates_sizes_and_checksum() {
+        for name in ["v1.img", "v2.img", "v4.img"] {
+            let mut image = BootImage::parse(&fixture(name)).unwrap();
+            let kernel = vec![0x5a; image.kernel().unwrap().len() + 6000];
+            image.replace_kernel(kernel.clone()).unwrap();
+
+            let repacked = BootImage::parse(&image.repack_checked().unwrap()).unwrap();
+            assert_eq!(repacked.kernel(), Some(kernel.as_slice()), "{}", name);
+            assert_eq!(repacked.section("ramdisk"), image.section("ramdisk"), "{}", name);
+            assert_ne!(repacked.checksum_valid(), Some(false), "{}", name);
+        }
+    }
+
+    #[test]
+    fn detects_kernel_formats() {
+        assert_eq!(KernelFormat::detect(BootImage::parse(&fixture("v0.img")).unwrap().kernel().unwrap()), KernelFormat::Arm64Image);
+        assert_eq!(KernelFormat::detect(BootImage::parse(&fixture("v2.img")).unwrap().kernel().unwrap()), KernelFormat::Gzip);
+        assert!(BootImage::parse(b"NOTABOOTIMAGE").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+// 资源包格式，同时被主程序和 encoder 使用，因此不依赖 crate 内的其他模块
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Asset {
+    // 不含 ABI 目录的相对路径，如 cmd、templates/service.sh
+    pub name: String,
+    // 资源包内的条目路径
+    pub path: String,
+    // 与架构无关的资源为 any
+    pub abi: String,
+    pub version: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    pub assets: Vec<Asset>,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 资源包是一个 zip 文件，每个资源单独 deflate 压缩，清单记录解压后的大小和哈希
+pub struct Bundle<'a> {
+    data: &'a [u8],
+    pub manifest: Manifest,
+}
+
+impl<'a> Bundle<'a> {
+    pub fn open(data: &'a [u8]) -> Result<Self, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+        let mut content = String::new();
+        archive.by_name(MANIFEST_NAME)
+            .map_err(|e| format!("资源包缺少 {}: {}", MANIFEST_NAME, e))?
+            .read_to_string(&mut content)?;
+        let manifest = serde_json::from_str(&content).map_err(|e| format!("资源清单格式错误: {}", e))?;
+        Ok(Bundle { data, manifest })
+    }
+
+    // 解压后校验大小和哈希，与清单不一致时视为资源包损坏
+    pub fn read(&self, asset: &Asset) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(self.data))?;
+        let mut data = Vec::with_capacity(asset.size as usize);
+        archive.by_name(&asset.path)
+            .map_err(|e| format!("资源包缺少 {}: {}", asset.path, e))?
+            .read_to_end(&mut data)?;
+
+        if data.len() as u64 != asset.size || sha256_hex(&data) != asset.sha256 {
+            return Err(format!("资源 {} ({}) 校验失败", asset.name, asset.abi).into());
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::AppResult;
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_UTC_TIME: u8 = 0x17;
+pub const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub const TAG_SEQUENCE: u8 = 0x30;
+pub const TAG_SET: u8 = 0x31;
+pub const TAG_CONTEXT_0: u8 = 0xA0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub content: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+fn malformed(what: &str) -> Box<dyn std::error::Error> {
+    format!("DER 数据损坏: {}", what).into()
+}
+
+// 返回第一个 TLV 以及剩余的数据，只支持单字节 tag
+pub fn read_tlv(data: &[u8]) -> AppResult<(Tlv<'_>, &[u8])> {
+    let tag = *data.first().ok_or_else(|| malformed("数据为空"))?;
+    let first_length = *data.get(1).ok_or_else(|| malformed("缺少长度"))? as usize;
+
+    let (length, header_length) = if first_length & 0x80 == 0 {
+        (first_length, 2)
+    } else {
+        let count = first_length & 0x7f;
+        if count == 0 || count > 4 {
+            return Err(malformed("不支持的长度编码"));
+        }
+        let bytes = data.get(2..2 + count).ok_or_else(|| malformed("长度越界"))?;
+        let length = bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (length, 2 + count)
+    };
+
+    let end = header_length.checked_add(length).ok_or_else(|| malformed("长度溢出"))?;
+    if end > data.len() {
+        return Err(malformed("内容越界"));
+    }
+
+    Ok((
+        Tlv {
+            tag,
+            content: &data[header_length..end],
+            raw: &data[..end],
+        },
+        &data[end..],
+    ))
+}
+
+pub fn children(content: &[u8]) -> AppResult<Vec<Tlv<'_>>> {
+    let mut items = Vec::new();
+    let mut rest = content;
+    while !rest.is_empty() {
+        let (tlv, next) = read_tlv(rest)?;
+        items.push(tlv);
+        rest = next;
+    }
+    Ok(items)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+pub fn oid_to_string(content: &[u8]) -> String {
+    let Some((first, rest)) = content.split_first() else {
+        return String::new();
+    };
+
+    let mut parts = vec![(first / 40).to_string(), (first % 40).to_string()];
+    let mut value: u64 = 0;
+    for byte in rest {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            parts.push(value.to_string());
+            value = 0;
+        }
+    }
+    parts.join(".")
+}
+
+// 将 UTCTime / GeneralizedTime 统一为 YYYYMMDDHHMMSS
+pub fn time_to_string(tlv: &Tlv) -> Option<String> {
+    let text = std::str::from_utf8(tlv.content).ok()?.trim_end_matches('Z');
+    match tlv.tag {
+        TAG_UTC_TIME if text.len() >= 12 => {
+            let year: u32 = text[..2].parse().ok()?;
+            let century = if year >= 50 { "19" } else { "20" };
+            Some(format!("{}{}", century, &text[..12]))
+        }
+        TAG_GENERALIZED_TIME if text.len() >= 14 => Some(text[..14].to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::packages::PackageEntry;
+use crate::{utc_timestamp, AppResult};
+
+pub const BACKUP_DIR: &str = "/sdcard/一键解决隐藏问题/hma_backups";
+// 旧版本只保留一份备份，仍然允许从这里恢复
+const LEGACY_BACKUP: &str = "/sdcard/一键解决隐藏问题/config.json";
+
+const IDENTITY_CACHE: &str = "/data/cache/recovery/yshell/hma_identity.json";
+const HMA_PACKAGES: &[&str] = &["icu.nullptr.hidemyapplist", "com.tsng.hidemyapplist"];
+
+// 常见的环境检测应用，添加作用域时默认使用
+pub const KNOWN_DETECTORS: &[&str] = &[
+    "icu.nullptr.nativetest",
+    "com.android.nativetest",
+    "com.reveny.nativecheck",
+    "com.zhenxi.hunter",
+    "me.garfieldhan.holmes",
+    "io.github.vvb2060.mahoshojo",
+    "luna.safe.luna",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub is_whitelist: bool,
+    #[serde(default)]
+    pub app_list: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppScope {
+    #[serde(default)]
+    pub use_whitelist: bool,
+    #[serde(default = "default_true")]
+    pub exclude_system_apps: bool,
+    #[serde(default)]
+    pub apply_templates: Vec<String>,
+    #[serde(default)]
+    pub extra_app_list: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// 未建模的字段原样保留，避免不同版本的 HMA 配置在写回时丢失设置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HmaConfig {
+    pub config_version: u32,
+    #[serde(default)]
+    pub templates: BTreeMap<String, Template>,
+    #[serde(default)]
+    pub scope: BTreeMap<String, AppScope>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn merge_list(target: &mut Vec<String>, items: &[String]) -> usize {
+    let mut added = 0;
+    for item in items {
+        if !target.contains(item) {
+            target.push(item.clone());
+            added += 1;
+        }
+    }
+    added
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub added_templates: Vec<String>,
+    pub extended_templates: Vec<String>,
+    pub skipped_templates: Vec<String>,
+    pub added_scopes: Vec<String>,
+    pub extended_scopes: Vec<String>,
+}
+
+impl HmaConfig {
+    pub fn parse(content: &str) -> AppResult<Self> {
+        Ok(serde_json::from_str(content)?)
+    }
+
+    pub fn load(path: &str) -> AppResult<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("读取配置文件 {} 失败: {}", path, e);
+                return Err(e.into());
+            }
+        };
+
+        match Self::parse(&content) {
+            Ok(config) => Ok(config),
+            Err(e) => {
+                eprintln!("解析配置文件 {} 失败: {}", path, e);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> AppResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // 用户已有的模板和作用域设置优先，推荐配置只补充缺失的应用
+    pub fn merge(&mut self, recommended: &HmaConfig) -> MergeReport {
+        let mut report = MergeReport::default();
+
+        for (name, template) in &recommended.templates {
+            match self.templates.get_mut(name) {
+                None => {
+                    self.templates.insert(name.clone(), template.clone());
+                    report.added_templates.push(name.clone());
+                }
+                Some(existing) if existing.is_whitelist != template.is_whitelist => {
+                    report.skipped_templates.push(name.clone());
+                }
+                Some(existing) => {
+                    if merge_list(&mut existing.app_list, &template.app_list) > 0 {
+                        report.extended_templates.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        for (package, scope) in &recommended.scope {
+            let apply_templates: Vec<String> = scope.apply_templates.iter()
+                .filter(|name| !report.skipped_templates.contains(name))
+                .cloned()
+                .collect();
+
+            match self.scope.get_mut(package) {
+                None => {
+                    let mut scope = scope.clone();
+                    scope.apply_templates = apply_templates;
+                    self.scope.insert(package.clone(), scope);
+                    report.added_scopes.push(package.clone());
+                }
+                Some(existing) if existing.use_whitelist != scope.use_whitelist => {}
+                Some(existing) => {
+                    let added = merge_list(&mut existing.apply_templates, &apply_templates)
+                        + merge_list(&mut existing.extra_app_list, &scope.extra_app_list);
+                    if added > 0 {
+                        report.extended_scopes.push(package.clone());
+                    }
+                }
+            }
+        }
+
+        report
+    }
+
+    pub fn blacklist_templates(&self) -> Vec<String> {
+        self.templates.iter()
+            .filter(|(_, template)| !template.is_whitelist)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    // 返回实际新加入作用域的应用
+    pub fn add_to_scope(&mut self, packages: &[String], templates: &[String]) -> Vec<String> {
+        let mut added = Vec::new();
+        for package in packages {
+            if self.scope.contains_key(package) {
+                continue;
+            }
+            self.scope.insert(package.clone(), AppScope {
+                use_whitelist: false,
+                exclude_system_apps: true,
+                apply_templates: templates.to_vec(),
+                ..Default::default()
+            });
+            added.push(package.clone());
+        }
+        added
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.config_version == 0 {
+            problems.push("configVersion 无效".to_string());
+        }
+
+        for (name, template) in &self.templates {
+            if name.trim().is_empty() {
+                problems.push("存在名称为空的模板".to_string());
+            }
+            if template.app_list.iter().any(|app| app.trim().is_empty()) {
+                problems.push(format!("模板 {} 中存在空的包名", name));
+            }
+        }
+
+        for (package, scope) in &self.scope {
+            for name in &scope.apply_templates {
+                match self.templates.get(name) {
+                    None => problems.push(format!("{} 引用了不存在的模板 {}", package, name)),
+                    Some(template) if template.is_whitelist != scope.use_whitelist => {
+                        let kind = if template.is_whitelist { "白名单" } else { "黑名单" };
+                        problems.push(format!("{} 的工作模式与{}模板 {} 不一致", package, kind, name));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+// 校验原始 JSON，字段类型错误会在这里报告而不是被静默忽略
+pub fn validate_json(content: &str) -> Vec<String> {
+    let value: Value = match serde_json::from_str(content) {
+        Ok(value) => value,
+        Err(e) => return vec![format!("不是有效的 JSON: {}", e)],
+    };
+
+    let mut problems = Vec::new();
+    let Some(object) = value.as_object() else {
+        return vec!["顶层必须是 JSON 对象".to_string()];
+    };
+
+    if !object.get("configVersion").is_some_and(Value::is_u64) {
+        problems.push("缺少 configVersion 或类型不是整数".to_string());
+    }
+
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    match HmaConfig::parse(content) {
+        Ok(config) => problems.extend(config.validate()),
+        Err(e) => problems.push(format!("结构不符合 HMA 配置格式: {}", e)),
+    }
+
+    problems
+}
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub id: String,
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub size: u64,
+}
+
+impl Backup {
+    pub fn read_verified(&self) -> AppResult<String> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("读取备份 {} 失败: {}", self.path.display(), e);
+                return Err(e.into());
+            }
+        };
+
+        if let Some(expected) = &self.sha256 {
+            let actual = format!("{:x}", Sha256::digest(&data));
+            if &actual != expected {
+                eprintln!("备份 {} 哈希校验失败", self.id);
+                eprintln!("记录的哈希: {}", expected);
+                eprintln!("实际的哈希: {}", actual);
+                return Err("备份文件已损坏".into());
+            }
+        }
+
+        Ok(String::from_utf8(data)?)
+    }
+}
+
+// 在覆盖配置前调用，原文件不存在时不创建备份
+pub fn create_backup(config_path: &str) -> AppResult<Option<Backup>> {
+    let data = match fs::read(config_path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            eprintln!("读取原配置文件 {} 失败: {}", config_path, e);
+            return Err(e.into());
+        }
+    };
+
+    if let Err(e) = fs::create_dir_all(BACKUP_DIR) {
+        eprintln!("创建备份目录失败: {}", e);
+        return Err(e.into());
+    }
+
+    let timestamp = utc_timestamp();
+    let mut id = timestamp.clone();
+    let mut suffix = 1;
+    while Path::new(BACKUP_DIR).join(format!("{}.json", id)).exists() {
+        suffix += 1;
+        id = format!("{}-{}", timestamp, suffix);
+    }
+
+    let path = Path::new(BACKUP_DIR).join(format!("{}.json", id));
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+
+    if let Err(e) = fs::write(&path, &data) {
+        eprintln!("写入备份 {} 失败: {}", path.display(), e);
+        return Err(e.into());
+    }
+    if let Err(e) = fs::write(path.with_extension("sha256"), format!("{}\n", sha256)) {
+        eprintln!("写入备份哈希失败: {}", e);
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+
+    Ok(Some(Backup {
+        id,
+        path,
+        sha256: Some(sha256),
+        size: data.len() as u64,
+    }))
+}
+
+// ID 为 <时间戳> 或同一秒内重复备份时的 <时间戳>-<序号>，序号从 2 开始
+fn backup_order(id: &str) -> (&str, u32) {
+    match id.rsplit_once('-') {
+        Some((timestamp, suffix)) if timestamp.contains('-') => (timestamp, suffix.parse().unwrap_or(0)),
+        _ => (id, 1),
+    }
+}
+
+// 按时间从新到旧排列，旧版本的单一备份排在最后
+pub fn list_backups() -> AppResult<Vec<Backup>> {
+    let mut backups = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(BACKUP_DIR) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()) else {
+                continue;
+            };
+
+            let sha256 = fs::read_to_string(path.with_extension("sha256"))
+                .ok()
+                .map(|hash| hash.trim().to_string());
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            backups.push(Backup { id, path, sha256, size });
+        }
+    }
+
+    backups.sort_by(|a, b| backup_order(&b.id).cmp(&backup_order(&a.id)));
+
+    if let Ok(metadata) = fs::metadata(LEGACY_BACKUP) {
+        backups.push(Backup {
+            id: "legacy".to_string(),
+            path: PathBuf::from(LEGACY_BACKUP),
+            sha256: None,
+            size: metadata.len(),
+        });
+    }
+
+    Ok(backups)
+}
+
+// runtime.log 中每次客户端连接都会记录一行 "Client uid ... <uid>"，取最新的一行
+pub fn parse_log_uid(content: &str) -> Option<u32> {
+    content.lines()
+        .filter(|line| line.contains("Client uid"))
+        .filter_map(|line| line.split_whitespace().next_back()?.parse().ok())
+        .next_back()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HmaIdentity {
+    pub package: String,
+    pub uid: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub package: String,
+    pub uid: u32,
+    pub sources: Vec<&'static str>,
+}
+
+// 先用日志 uid、已知包名和数据目录中的配置文件挑出候选，再用数据目录属主和 dumpsys 交叉验证 uid
+pub fn rank_candidates(
+    entries: &[PackageEntry],
+    log_uid: Option<u32>,
+    has_config: impl Fn(&PackageEntry) -> bool,
+    owner_uid: impl Fn(&PackageEntry) -> Option<u32>,
+    dumpsys_uid: impl Fn(&str) -> Option<u32>,
+) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+
+    for entry in entries {
+        let mut sources = Vec::new();
+        if log_uid == Some(entry.uid) {
+            sources.push("runtime.log");
+        }
+        if HMA_PACKAGES.contains(&entry.name.as_str()) {
+            sources.push("已知包名");
+        }
+        if has_config(entry) {
+            sources.push("数据目录配置文件");
+        }
+        if sources.is_empty() {
+            continue;
+        }
+        sources.push("packages.list");
+
+        match owner_uid(entry) {
+            Some(uid) if uid != entry.uid => {
+                eprintln!("{} 的数据目录属主 {} 与 packages.list 中的 uid {} 不一致，已排除", entry.name, uid, entry.uid);
+                continue;
+            }
+            Some(_) => sources.push("数据目录属主"),
+            None => {}
+        }
+
+        match dumpsys_uid(&entry.name) {
+            Some(uid) if uid != entry.uid => {
+                eprintln!("{} 在 dumpsys 中的 uid {} 与 packages.list 中的 uid {} 不一致，已排除", entry.name, uid, entry.uid);
+                continue;
+            }
+            Some(_) => sources.push("dumpsys"),
+            None => {}
+        }
+
+        candidates.push(Candidate {
+            package: entry.name.clone(),
+            uid: entry.uid,
+            sources,
+        });
+    }
+
+    candidates.sort_by(|a, b| b.sources.len().cmp(&a.sources.len()).then(a.package.cmp(&b.package)));
+    candidates
+}
+
+pub fn looks_like_config(path: &str) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| HmaConfig::parse(&content).ok())
+        .is_some()
+}
+
+// 缓存只有在 packages.list 中包名和 uid 都未变化时才有效
+pub fn load_cached_identity(entries: &[PackageEntry]) -> Option<HmaIdentity> {
+    let content = fs::read_to_string(IDENTITY_CACHE).ok()?;
+    let identity: HmaIdentity = serde_json::from_str(&content).ok()?;
+    entries.iter()
+        .any(|entry| entry.name == identity.package && entry.uid == identity.uid)
+        .then_some(identity)
+}
+
+pub fn save_cached_identity(identity: &HmaIdentity) {
+    let saved = Path::new(IDENTITY_CACHE).parent()
+        .map(fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| fs::write(IDENTITY_CACHE, serde_json::to_string(identity).unwrap_or_default()));
+    if let Err(e) = saved {
+        eprintln!("保存 HMA 信息缓存失败: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packages;
+
+    fn fixture(path: &str) -> String {
+        fs::read_to_string(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap()
+    }
+
+    #[test]
+    fn reads_latest_client_uid_from_log() {
+        assert_eq!(parse_log_uid(&fixture("hma/runtime.log")), Some(10245));
+        assert_eq!(parse_log_uid("Service started\n"), None);
+    }
+
+    #[test]
+    fn ranks_and_cross_checks_candidates() {
+        let entries = packages::parse_packages_list(&fixture("packages/packages.list"));
+        let dumpsys = packages::parse_dumpsys_user_ids(&fixture("packages/dumpsys_package.txt"));
+
+        let candidates = rank_candidates(
+            &entries,
+            parse_log_uid(&fixture("hma/runtime.log")),
+            |entry| entry.name == "com.example.renamed" || entry.name == "io.github.vvb2060.mahoshojo",
+            |entry| match entry.name.as_str() {
+                "io.github.vvb2060.mahoshojo" => Some(10999),
+                "com.android.shell" => None,
+                _ => Some(entry.uid),
+            },
+            |package| dumpsys.iter().find(|(name, _)| name == package).map(|(_, uid)| *uid),
+        );
+
+        let ranked: Vec<_> = candidates.iter().map(|c| (c.package.as_str(), c.uid, c.sources.clone())).collect();
+        assert_eq!(ranked, [
+            ("com.example.renamed", 10245, vec!["runtime.log", "数据目录配置文件", "packages.list", "数据目录属主", "dumpsys"]),
+            ("icu.nullptr.hidemyapplist", 10213, vec!["已知包名", "packages.list", "数据目录属主", "dumpsys"]),
+        ]);
+    }
+
+    #[test]
+    fn orders_backups_by_timestamp_and_suffix() {
+        let mut ids = vec!["20260101-120000-10", "20260101-120000", "20260101-115959", "20260101-120000-2"];
+        ids.sort_by(|a, b| backup_order(b).cmp(&backup_order(a)));
+        assert_eq!(ids, ["20260101-120000-10", "20260101-120000-2", "20260101-120000", "20260101-115959"]);
+    }
+}
@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::fs;
+
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::{der, AppResult};
+
+pub const KEYBOX_FILE: &str = "/data/adb/tricky_store/keybox.xml";
+pub const BACKUP_DIR: &str = "/data/adb/tricky_store/keybox_backups";
+
+const OID_EC_PUBLIC_KEY: &str = "1.2.840.10045.2.1";
+const OID_RSA_ENCRYPTION: &str = "1.2.840.113549.1.1.1";
+const OID_COMMON_NAME: &str = "2.5.4.3";
+
+#[derive(Debug, Clone, Default)]
+struct XmlNode {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<XmlNode>,
+}
+
+impl XmlNode {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&XmlNode> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> + 'a {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_attributes(text: &str) -> AppResult<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=').ok_or("XML 属性格式错误")?;
+        let after = after.trim_start();
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or("XML 属性缺少引号")?;
+        let end = after[1..].find(quote).ok_or("XML 属性缺少结束引号")?;
+        attributes.push((name.trim().to_string(), unescape(&after[1..1 + end])));
+        rest = after[end + 2..].trim_start();
+    }
+    Ok(attributes)
+}
+
+// keybox.xml 结构简单，只需要元素、属性和文本
+fn parse_xml(content: &str) -> AppResult<XmlNode> {
+    let mut stack = vec![XmlNode::default()];
+    let mut rest = content;
+
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        if let Some(current) = stack.last_mut() {
+            current.text.push_str(&unescape(text));
+        }
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("<?") {
+            rest = &after[after.find("?>").ok_or("XML 声明未闭合")? + 2..];
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->").ok_or("XML 注释未闭合")? + 3..];
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or("XML 结束标签未闭合")?;
+            let name = after[..end].trim();
+            let node = stack.pop().ok_or("XML 标签不匹配")?;
+            if node.name != name || stack.is_empty() {
+                return Err(format!("XML 标签不匹配: </{}>", name).into());
+            }
+            stack.last_mut().ok_or("XML 标签不匹配")?.children.push(node);
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find('>').ok_or("XML 标签未闭合")?;
+            let tag = &rest[1..end];
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (name, attributes) = match tag.split_once(char::is_whitespace) {
+                Some((name, attributes)) => (name, parse_attributes(attributes)?),
+                None => (tag, Vec::new()),
+            };
+            let node = XmlNode {
+                name: name.to_string(),
+                attributes,
+                ..Default::default()
+            };
+            if self_closing {
+                stack.last_mut().ok_or("XML 标签不匹配")?.children.push(node);
+            } else {
+                stack.push(node);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+
+    if stack.len() != 1 {
+        return Err(format!("XML 标签 <{}> 未闭合", stack.last().map(|n| n.name.as_str()).unwrap_or("")).into());
+    }
+    let document = stack.pop().unwrap_or_default();
+    document.children.into_iter().next().ok_or_else(|| "XML 中没有根元素".into())
+}
+
+fn pem_body(text: &str) -> (Option<String>, String) {
+    let mut label = None;
+    let mut body = String::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("-----BEGIN ") {
+            label = Some(rest.trim_end_matches('-').to_string());
+        } else if !line.starts_with("-----") {
+            body.push_str(line);
+        }
+    }
+    (label, body)
+}
+
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub serial: String,
+    pub subject: String,
+    pub issuer: String,
+    subject_raw: Vec<u8>,
+    issuer_raw: Vec<u8>,
+    pub not_before: String,
+    pub not_after: String,
+    pub key_algorithm: String,
+}
+
+fn common_name(name: &der::Tlv) -> String {
+    der::children(name.content)
+        .unwrap_or_default()
+        .iter()
+        .filter(|set| set.tag == der::TAG_SET)
+        .flat_map(|set| der::children(set.content).unwrap_or_default())
+        .find_map(|attribute| {
+            let parts = der::children(attribute.content).ok()?;
+            match parts.as_slice() {
+                [oid, value, ..] if oid.tag == der::TAG_OID && der::oid_to_string(oid.content) == OID_COMMON_NAME => {
+                    Some(String::from_utf8_lossy(value.content).into_owned())
+                }
+                _ => None,
+            }
+        })
+        .unwrap_or_else(|| "(无 CN)".to_string())
+}
+
+fn normalize_serial(serial: &str) -> String {
+    let serial = serial.trim().to_ascii_lowercase();
+    let trimmed = serial.trim_start_matches('0');
+    if trimmed.is_empty() { "0".to_string() } else { trimmed.to_string() }
+}
+
+impl Certificate {
+    pub fn parse(data: &[u8]) -> AppResult<Self> {
+        let (certificate, _) = der::read_tlv(data)?;
+        let parts = der::children(certificate.content)?;
+        let tbs = parts.first().ok_or("证书缺少 tbsCertificate")?;
+        let mut fields = der::children(tbs.content)?;
+        if fields.first().is_some_and(|field| field.tag == der::TAG_CONTEXT_0) {
+            fields.remove(0);
+        }
+
+        let [serial, _, issuer, validity, subject, public_key, ..] = fields.as_slice() else {
+            return Err("证书结构不完整".into());
+        };
+        if serial.tag != der::TAG_INTEGER {
+            return Err("证书序列号格式错误".into());
+        }
+
+        let times = der::children(validity.content)?;
+        let (not_before, not_after) = match times.as_slice() {
+            [not_before, not_after] => (
+                der::time_to_string(not_before).ok_or("证书有效期格式错误")?,
+                der::time_to_string(not_after).ok_or("证书有效期格式错误")?,
+            ),
+            _ => return Err("证书有效期格式错误".into()),
+        };
+
+        let key_algorithm = der::children(public_key.content)?
+            .first()
+            .and_then(|algorithm| der::children(algorithm.content).ok())
+            .and_then(|algorithm| algorithm.first().map(|oid| der::oid_to_string(oid.content)))
+            .map(|oid| match oid.as_str() {
+                OID_EC_PUBLIC_KEY => "ecdsa".to_string(),
+                OID_RSA_ENCRYPTION => "rsa".to_string(),
+                _ => oid,
+            })
+            .unwrap_or_default();
+
+        Ok(Certificate {
+            serial: normalize_serial(&der::to_hex(serial.content)),
+            subject: common_name(subject),
+            issuer: common_name(issuer),
+            subject_raw: subject.raw.to_vec(),
+            issuer_raw: issuer.raw.to_vec(),
+            not_before,
+            not_after,
+            key_algorithm,
+        })
+    }
+
+    pub fn is_self_signed(&self) -> bool {
+        self.subject_raw == self.issuer_raw
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyEntry {
+    pub algorithm: String,
+    pub private_key_label: Option<String>,
+    pub declared_certificates: Option<usize>,
+    pub certificates: Vec<Certificate>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Keybox {
+    pub device_id: String,
+    pub declared_keyboxes: Option<usize>,
+    pub keyboxes: usize,
+    pub keys: Vec<KeyEntry>,
+}
+
+impl Keybox {
+    pub fn parse(content: &str) -> AppResult<Self> {
+        let root = parse_xml(content)?;
+        if root.name != "AndroidAttestation" {
+            return Err(format!("根元素应为 AndroidAttestation，实际为 {}", root.name).into());
+        }
+
+        let declared_keyboxes = root.child("NumberOfKeyboxes").and_then(|n| n.text.trim().parse().ok());
+        let keybox = root.child("Keybox").ok_or("缺少 Keybox 元素")?;
+
+        let mut keys = Vec::new();
+        for key in keybox.children_named("Key") {
+            let private_key_label = key.child("PrivateKey").map(|node| pem_body(&node.text).0.unwrap_or_default());
+            let chain = key.child("CertificateChain").ok_or("Key 缺少 CertificateChain 元素")?;
+
+            let mut certificates = Vec::new();
+            for (index, node) in chain.children_named("Certificate").enumerate() {
+                let (_, body) = pem_body(&node.text);
+                let data = base64::engine::general_purpose::STANDARD.decode(body)
+                    .map_err(|e| format!("第 {} 个证书 base64 解码失败: {}", index + 1, e))?;
+                let certificate = Certificate::parse(&data)
+                    .map_err(|e| format!("第 {} 个证书解析失败: {}", index + 1, e))?;
+                certificates.push(certificate);
+            }
+
+            keys.push(KeyEntry {
+                algorithm: key.attribute("algorithm").unwrap_or("").to_ascii_lowercase(),
+                private_key_label,
+                declared_certificates: chain.child("NumberOfCertificates").and_then(|n| n.text.trim().parse().ok()),
+                certificates,
+            });
+        }
+
+        Ok(Keybox {
+            device_id: keybox.attribute("DeviceID").unwrap_or("").to_string(),
+            declared_keyboxes,
+            keyboxes: root.children_named("Keybox").count(),
+            keys,
+        })
+    }
+
+    // now 格式为 YYYYMMDDHHMMSS，返回 (错误, 警告)
+    pub fn validate(&self, now: &str) -> (Vec<String>, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        if self.declared_keyboxes.is_some_and(|n| n != self.keyboxes) {
+            warnings.push(format!("NumberOfKeyboxes 与实际的 Keybox 数量 {} 不一致", self.keyboxes));
+        }
+        if self.keys.is_empty() {
+            errors.push("Keybox 中没有任何 Key".to_string());
+        }
+
+        for key in &self.keys {
+            let name = if key.algorithm.is_empty() { "(未知算法)" } else { key.algorithm.as_str() };
+            if key.algorithm != "ecdsa" && key.algorithm != "rsa" {
+                errors.push(format!("Key {} 的 algorithm 属性无效", name));
+            }
+
+            match &key.private_key_label {
+                None => errors.push(format!("Key {} 缺少 PrivateKey", name)),
+                Some(label) => {
+                    let expected = if key.algorithm == "rsa" { "RSA PRIVATE KEY" } else { "EC PRIVATE KEY" };
+                    if label != expected && label != "PRIVATE KEY" {
+                        errors.push(format!("Key {} 的私钥类型为 {}，应为 {}", name, label, expected));
+                    }
+                }
+            }
+
+            if key.declared_certificates.is_some_and(|n| n != key.certificates.len()) {
+                warnings.push(format!("Key {} 的 NumberOfCertificates 与实际证书数量 {} 不一致", name, key.certificates.len()));
+            }
+
+            let Some(leaf) = key.certificates.first() else {
+                errors.push(format!("Key {} 的证书链为空", name));
+                continue;
+            };
+            if leaf.key_algorithm != key.algorithm {
+                errors.push(format!("Key {} 的叶子证书公钥算法为 {}", name, leaf.key_algorithm));
+            }
+
+            for pair in key.certificates.windows(2) {
+                if pair[0].issuer_raw != pair[1].subject_raw {
+                    errors.push(format!("Key {} 的证书链顺序错误: {} 不是由 {} 签发", name, pair[0].subject, pair[1].subject));
+                }
+            }
+            if key.certificates.last().is_some_and(|root| !root.is_self_signed()) {
+                warnings.push(format!("Key {} 的证书链不以自签名根证书结尾", name));
+            }
+
+            for certificate in &key.certificates {
+                if certificate.not_after.as_str() < now {
+                    errors.push(format!("证书 {} (序列号 {}) 已于 {} 过期", certificate.subject, certificate.serial, certificate.not_after));
+                } else if certificate.not_before.as_str() > now {
+                    warnings.push(format!("证书 {} (序列号 {}) 尚未生效", certificate.subject, certificate.serial));
+                }
+            }
+        }
+
+        (errors, warnings)
+    }
+
+    pub fn serials(&self) -> Vec<&str> {
+        self.keys.iter()
+            .flat_map(|key| key.certificates.iter().map(|certificate| certificate.serial.as_str()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationEntry {
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevocationList {
+    entries: HashMap<String, RevocationEntry>,
+}
+
+// 格式与 Google 公布的证书吊销列表一致: {"entries": {"<序列号>": {"status": ..., "reason": ...}}}
+pub fn check_revocation(keybox: &Keybox, revocation_file: &str) -> AppResult<Vec<String>> {
+    let content = match fs::read_to_string(revocation_file) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("读取吊销列表 {} 失败: {}", revocation_file, e);
+            return Err(e.into());
+        }
+    };
+    let list: RevocationList = match serde_json::from_str(&content) {
+        Ok(list) => list,
+        Err(e) => {
+            eprintln!("解析吊销列表 {} 失败: {}", revocation_file, e);
+            return Err(e.into());
+        }
+    };
+
+    let revoked: HashMap<String, &RevocationEntry> = list.entries.iter()
+        .map(|(serial, entry)| (normalize_serial(serial), entry))
+        .collect();
+
+    Ok(keybox.serials()
+        .into_iter()
+        .filter_map(|serial| {
+            revoked.get(serial).map(|entry| format!("证书序列号 {} 已被吊销 (状态: {}, 原因: {})", serial, entry.status, entry.reason))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: &str = "20260101000000";
+
+    fn fixture_path(name: &str) -> String {
+        format!("{}/tests/fixtures/keybox/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn fixture(name: &str) -> Keybox {
+        Keybox::parse(&fs::read_to_string(fixture_path(name)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parses_valid_keybox() {
+        let keybox = fixture("valid.xml");
+        assert_eq!(keybox.device_id, "rshy-test");
+        assert_eq!((keybox.declared_keyboxes, keybox.keyboxes), (Some(1), 1));
+
+        let [key] = keybox.keys.as_slice() else { panic!("应只有一个 Key") };
+        assert_eq!(key.algorithm, "ecdsa");
+        assert_eq!(key.private_key_label.as_deref(), Some("EC PRIVATE KEY"));
+        assert_eq!(key.declared_certificates, Some(2));
+
+        let [leaf, root] = key.certificates.as_slice() else { panic!("证书链应有两个证书") };
+        assert_eq!((leaf.subject.as_str(), leaf.issuer.as_str()), ("Test Leaf", "Test Root"));
+        assert_eq!((leaf.not_before.as_str(), leaf.not_after.as_str()), ("20200101000000", "20500101000000"));
+        assert_eq!(leaf.key_algorithm, "ecdsa");
+        assert!(!leaf.is_self_signed());
+        assert!(root.is_self_signed());
+        assert_eq!(keybox.serials(), ["abc", "1"]);
+
+        let (errors, warnings) = keybox.validate(NOW);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(keybox.validate("20190101000000").1.len(), 2);
+    }
+
+    #[test]
+    fn rejects_out_of_order_chain() {
+        let (errors, warnings) = fixture("out_of_order.xml").validate(NOW);
+        assert_eq!(errors, ["Key ecdsa 的证书链顺序错误: Test Root 不是由 Test Leaf 签发"]);
+        assert_eq!(warnings, ["Key ecdsa 的证书链不以自签名根证书结尾"]);
+    }
+
+    #[test]
+    fn rejects_expired_certificate() {
+        let (errors, warnings) = fixture("expired.xml").validate(NOW);
+        assert_eq!(errors, ["证书 Test Leaf (序列号 def) 已于 20210101000000 过期"]);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn reports_revoked_serials() {
+        let revocation = fixture_path("revocation.json");
+        assert_eq!(check_revocation(&fixture("valid.xml"), &revocation).unwrap(), [
+            "证书序列号 abc 已被吊销 (状态: REVOKED, 原因: KEY_COMPROMISE)",
+        ]);
+        assert!(check_revocation(&fixture("expired.xml"), &revocation).unwrap().is_empty());
+        assert!(check_revocation(&fixture("valid.xml"), &fixture_path("missing.json")).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_keybox() {
+        let valid = fs::read_to_string(fixture_path("valid.xml")).unwrap();
+        let error = |content: &str| Keybox::parse(content).unwrap_err().to_string();
+
+        assert_eq!(error(&valid.replace("</Keybox>", "")), "XML 标签不匹配: </AndroidAttestation>");
+        assert_eq!(error(&valid.replace("</AndroidAttestation>", "")), "XML 标签 <AndroidAttestation> 未闭合");
+        assert_eq!(error("<Keybox></Keybox>"), "根元素应为 AndroidAttestation，实际为 Keybox");
+        assert_eq!(error("<AndroidAttestation DeviceID=test></AndroidAttestation>"), "XML 属性缺少引号");
+        assert_eq!(error("<AndroidAttestation></AndroidAttestation>"), "缺少 Keybox 元素");
+
+        let base64 = valid.replacen("MIIB", "MII*", 1);
+        assert!(error(&base64).starts_with("第 1 个证书 base64 解码失败"), "{}", error(&base64));
+        let truncated = valid.replacen("MIIB", "AAAA", 1);
+        assert!(error(&truncated).starts_with("第 1 个证书解析失败"), "{}", error(&truncated));
+    }
+}
@@ -0,0 +1,269 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use serde::Deserialize;
+
+use crate::{prompt, AppResult};
+
+pub const APATCH_VERSION_FILE: &str = "/data/adb/ap/version";
+pub const APATCH_PACKAGE: &str = "me.bmax.apatch";
+pub const APATCH_NEXT_PACKAGE: &str = "me.garfieldhan.apatch.next";
+// 官方版与 APatch Next 的管理器，版本号无法区分两者，只能看实际安装的是哪一个
+pub const APATCH_PACKAGES: [&str; 2] = [APATCH_PACKAGE, APATCH_NEXT_PACKAGE];
+const CATALOG_BASE_URL: &str = "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/peekaboo";
+
+pub fn read_apatch_version() -> Option<u32> {
+    fs::read_to_string(APATCH_VERSION_FILE).ok()?
+        .lines()
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatalogEntry {
+    pub module: String,
+    pub file: String,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub min_version: Option<u32>,
+    #[serde(default)]
+    pub max_version: Option<u32>,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl CatalogEntry {
+    fn new(module: &str, file: &str, min_version: Option<u32>, max_version: Option<u32>, optional: bool) -> Self {
+        CatalogEntry {
+            module: module.to_string(),
+            file: file.to_string(),
+            url: None,
+            min_version,
+            max_version,
+            optional,
+        }
+    }
+
+    pub fn supports(&self, version: u32) -> bool {
+        self.min_version.is_none_or(|min| version >= min) && self.max_version.is_none_or(|max| version <= max)
+    }
+
+    pub fn download_url(&self) -> String {
+        self.url.clone().unwrap_or_else(|| format!("{}/{}", CATALOG_BASE_URL, self.file))
+    }
+
+    pub fn version_range(&self) -> String {
+        match (self.min_version, self.max_version) {
+            (Some(min), Some(max)) => format!("{}-{}", min, max),
+            (Some(min), None) => format!(">={}", min),
+            (None, Some(max)) => format!("<={}", max),
+            (None, None) => "全部".to_string(),
+        }
+    }
+}
+
+// 同一模块按顺序匹配，第一个支持当前版本的条目生效
+pub fn builtin_catalog() -> Vec<CatalogEntry> {
+    vec![
+        CatalogEntry::new("peekaboo", "cherish_peekaboo_1.5.kpm", Some(10983), Some(11010), false),
+        CatalogEntry::new("peekaboo", "cherish_peekaboo_1.5.5.kpm", None, None, false),
+        // 10983-11010 的 APatch 加载 NoHello 会出现异常
+        CatalogEntry::new("nohello", "nohello.kpm", None, Some(10982), true),
+        CatalogEntry::new("nohello", "nohello.kpm", Some(11011), None, true),
+    ]
+}
+
+pub fn load_catalog(path: Option<&str>) -> AppResult<Vec<CatalogEntry>> {
+    let Some(path) = path else {
+        return Ok(builtin_catalog());
+    };
+
+    let content = fs::read_to_string(path).map_err(|e| format!("读取 {} 失败: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| format!("KPM 目录 {} 格式错误: {}", path, e).into())
+}
+
+pub fn select<'a>(catalog: &'a [CatalogEntry], module: &str, version: u32) -> Option<&'a CatalogEntry> {
+    catalog.iter().find(|entry| entry.module == module && entry.supports(version))
+}
+
+// 超级密钥文件必须只有属主可读，且属于 root 或当前用户
+pub fn read_superkey_file(path: &Path) -> AppResult<String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+    if metadata.mode() & 0o077 != 0 {
+        return Err(format!("{} 的权限过宽，请执行 chmod 600", path.display()).into());
+    }
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != 0 && metadata.uid() != euid {
+        return Err(format!("{} 的属主不是 root", path.display()).into());
+    }
+
+    let content = fs::read_to_string(path)?;
+    let key = content.lines().next().unwrap_or_default().trim().to_string();
+    if key.is_empty() {
+        return Err(format!("{} 中没有超级密钥", path.display()).into());
+    }
+    Ok(key)
+}
+
+// 终端输入时关闭回显，避免超级密钥显示在屏幕上
+pub fn prompt_superkey() -> AppResult<String> {
+    if let Some(key) = prompt::preset("superkey")? {
+        println!("使用预设应答中的超级密钥");
+        return Ok(key.to_string());
+    }
+
+    print!("请输入 APatch 的超级密钥: ");
+    io::stdout().flush()?;
+
+    let fd = io::stdin().as_raw_fd();
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    let is_terminal = unsafe { libc::tcgetattr(fd, &mut original) } == 0;
+    if is_terminal {
+        let mut silent = original;
+        silent.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    }
+
+    let mut key = String::new();
+    let result = io::stdin().lock().read_line(&mut key);
+    if is_terminal {
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+        println!();
+    }
+    result?;
+
+    let key = key.trim().to_string();
+    if key.is_empty() {
+        return Err("超级密钥不能为空".into());
+    }
+    Ok(key)
+}
+
+fn hide_superkey(text: &str, superkey: &str) -> String {
+    if superkey.is_empty() {
+        return text.to_string();
+    }
+    text.replace(superkey, "******")
+}
+
+// 命令失败时只输出去掉超级密钥后的错误信息
+fn check_output(tool: &str, output: Output, superkey: &str) -> AppResult<String> {
+    let stdout = hide_superkey(&String::from_utf8_lossy(&output.stdout), superkey);
+    if !output.status.success() {
+        let stderr = hide_superkey(&String::from_utf8_lossy(&output.stderr), superkey);
+        let message = if stderr.trim().is_empty() { stdout.trim().to_string() } else { stderr.trim().to_string() };
+        return Err(format!("{} 执行失败: {}", tool, message).into());
+    }
+    Ok(stdout)
+}
+
+pub struct Kpatch {
+    pub path: PathBuf,
+    superkey: String,
+}
+
+impl Kpatch {
+    pub fn new(path: PathBuf, superkey: String) -> Self {
+        Kpatch { path, superkey }
+    }
+
+    pub fn superkey(&self) -> &str {
+        &self.superkey
+    }
+
+    fn run(&self, args: &[&str]) -> AppResult<String> {
+        let output = Command::new(&self.path)
+            .arg(&self.superkey)
+            .arg("kpm")
+            .args(args)
+            .output()
+            .map_err(|e| format!("无法执行 {}: {}", self.path.display(), e))?;
+        check_output("kpatch", output, &self.superkey)
+    }
+
+    pub fn count(&self) -> AppResult<usize> {
+        let output = self.run(&["num"])?;
+        output.trim().parse().map_err(|_| format!("无法解析 kpatch 输出: {}", output.trim()).into())
+    }
+
+    pub fn list(&self) -> AppResult<Vec<String>> {
+        Ok(self.run(&["list"])?
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    pub fn info(&self, name: &str) -> AppResult<String> {
+        self.run(&["info", name])
+    }
+
+    pub fn load(&self, path: &Path, args: Option<&str>) -> AppResult<String> {
+        let path = path.to_string_lossy();
+        match args {
+            Some(args) => self.run(&["load", &path, args]),
+            None => self.run(&["load", &path]),
+        }
+    }
+
+    pub fn unload(&self, name: &str) -> AppResult<String> {
+        self.run(&["unload", name])
+    }
+}
+
+pub struct Kptools {
+    pub path: PathBuf,
+}
+
+impl Kptools {
+    fn run(&self, args: &[&str], superkey: &str) -> AppResult<String> {
+        let output = Command::new(&self.path)
+            .args(args)
+            .output()
+            .map_err(|e| format!("无法执行 {}: {}", self.path.display(), e))?;
+        check_output("kptools", output, superkey)
+    }
+
+    pub fn is_patched(&self, kernel: &Path) -> AppResult<bool> {
+        let output = self.run(&["-i", &kernel.to_string_lossy(), "-l"], "")?;
+        Ok(!output.contains("patched=false"))
+    }
+
+    pub fn unpatch(&self, kernel: &Path, output: &Path) -> AppResult {
+        self.run(&["-u", "--image", &kernel.to_string_lossy(), "--out", &output.to_string_lossy()], "")?;
+        if fs::metadata(output).map(|m| m.len()).unwrap_or(0) == 0 {
+            return Err("kptools 没有生成还原后的内核".into());
+        }
+        Ok(())
+    }
+
+    // 以 pre-kernel-init 方式嵌入所有模块，并检查 kptools 是否报告 patch done
+    pub fn patch(&self, kernel: &Path, kpimg: &Path, superkey: &str, modules: &[PathBuf], output: &Path) -> AppResult {
+        let kernel = kernel.to_string_lossy();
+        let kpimg = kpimg.to_string_lossy();
+        let output_path = output.to_string_lossy();
+        let module_paths: Vec<String> = modules.iter().map(|module| module.to_string_lossy().into_owned()).collect();
+
+        let mut args = vec!["-p", "-i", &kernel, "-s", superkey, "-k", &kpimg, "-o", &output_path];
+        for module in &module_paths {
+            args.extend(["-M", module.as_str(), "-V", "pre-kernel-init", "-T", "kpm"]);
+        }
+
+        let log = self.run(&args, superkey)?;
+        if !log.contains("patch done") {
+            eprintln!("{}", log.trim());
+            return Err("kptools 没有完成修补".into());
+        }
+        if fs::metadata(output).map(|m| m.len()).unwrap_or(0) == 0 {
+            return Err("kptools 没有生成修补后的内核".into());
+        }
+        Ok(())
+    }
+}
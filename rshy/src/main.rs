@@ -1,34 +1,54 @@
 use std::process::Command;
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
 use std::fs::File;
 use std::io::{self, Write, Read};
-use std::process::Stdio;
 use std::time::Duration;
 use sha2::{Sha256, Digest};
 use rand::Rng;
 use std::thread;
 use serde_json::Value;
+use serde::{Deserialize, Serialize};
 use tokio;
 use tokio::time::timeout;
 use reqwest::Client;
-use std::sync::{Arc, Mutex};
-use num_cpus;
-use regex;
 use indicatif::{ProgressBar, ProgressStyle};
 use futures::StreamExt;
 use rusqlite::{Connection, Result};
-use std::sync::atomic::{AtomicU64, Ordering};
 use zip::ZipWriter;
 use zip::write::FileOptions;
 use std::os::unix::fs::PermissionsExt;
+use pm::PackageManager;
+
+mod appdata;
+mod appscan;
+mod avb;
+mod axml;
+mod bootimg;
+mod bundle;
+mod der;
+mod hma;
+mod keybox;
+mod kpm;
+mod packages;
+mod partition;
+mod pm;
+mod prompt;
+mod source;
+mod tools;
+mod tricky;
+mod xml;
 
 type AppResult<T = ()> = Result<T, Box<dyn std::error::Error>>;
 
 #[tokio::main]
 async fn main() -> AppResult {
     let args: Vec<String> = env::args().collect();
+    let args = match prompt::init(&args) {
+        Ok(args) => args,
+        Err(_) => std::process::exit(1),
+    };
 
     if args.len() < 2 {
         print_help();
@@ -39,6 +59,9 @@ async fn main() -> AppResult {
         Ok(()) => {
             std::process::exit(0);
         },
+        Err(e) if e.is::<prompt::Aborted>() => {
+            std::process::exit(prompt::EXIT_ABORTED);
+        },
         Err(_) => {
             std::process::exit(1);
         }
@@ -65,8 +88,9 @@ async fn handle_command(args: &[String]) -> AppResult {
         "--update" => handle_update().await,
         "--cleanmodules" => handle_clean_modules(),
         "initrc" => init_rc(),
-        "hidemyapplist" => hidemyapplist().await,
-        "recoverapplist" => recoverapplist(),
+        "hidemyapplist" => hidemyapplist(&pm::system()).await,
+        "recoverapplist" => recoverapplist(&pm::system()),
+        "hma" => handle_hma(args).await,
         "lsplog" => {
             clean_lsplog();
             Ok(())
@@ -81,6 +105,18 @@ async fn handle_command(args: &[String]) -> AppResult {
             Ok(())
         },
         "updatetarget" => update_target_file(),
+        "tricky" => handle_tricky(args),
+        "vbmeta" => match args.get(2).map(|s| s.as_str()) {
+            Some("audit") => audit_vbmeta(),
+            _ => {
+                print_help();
+                Err("参数不足".into())
+            }
+        },
+        "assets" => handle_assets(args),
+        "bootimg" => handle_bootimg(args),
+        "partition" => handle_partition(args),
+        "kpm" => handle_kpm(args).await,
         "awjclean" => handle_awjclean(),
         "aptroot" => handle_aptroot(),
         "rurudelete" => handle_rurudelete(),
@@ -89,6 +125,7 @@ async fn handle_command(args: &[String]) -> AppResult {
         "momo" => handle_momo(args),
         "hunter" => handle_hunter(args),
         "nativedetector" => handle_nativedetector(args).await,
+        "find-app" => handle_find_app(args),
         _ => {
             eprintln!("Unknown command: {}", args[1]);
             print_help();
@@ -185,245 +222,1515 @@ async fn handle_nativedetector(args: &[String]) -> AppResult {
     nativedetector(&args[2]).await
 }
 
-async fn handle_download(args: &[String]) -> AppResult {
+async fn handle_hma(args: &[String]) -> AppResult {
     if args.len() < 3 {
-        return Err("Download URL is required".into());
+        print_help();
+        return Err("参数不足".into());
     }
 
-    let url = &args[2];
-    let mut use_cdn = true;
-    let mut save_path = None;
-    let mut expected_hash = None;
-    
-    let mut i = 3;
-    while i < args.len() {
-        match args[i].as_str() {
-            "--no-cdn" => {
-                use_cdn = false;
-                i += 1;
-            }
-            _ if save_path.is_none() => {
-                save_path = Some(std::path::PathBuf::from(&args[i]));
-                i += 1;
-            }
-            _ if expected_hash.is_none() => {
-                expected_hash = Some(args[i].clone());
+    let pm = pm::system();
+    match args[2].as_str() {
+        "list" => hma_list(&pm),
+        "merge" => hma_merge(&pm, args.get(3).map(|s| s.as_str())).await,
+        "scope" => {
+            let mut packages = Vec::new();
+            let mut templates = Vec::new();
+            let mut i = 3;
+            while i < args.len() {
+                if args[i] == "--template" {
+                    match args.get(i + 1) {
+                        Some(name) => templates.push(name.clone()),
+                        None => {
+                            eprintln!("--template 需要指定模板名称");
+                            return Err("缺少模板名称".into());
+                        }
+                    }
+                    i += 1;
+                } else {
+                    packages.push(args[i].clone());
+                }
                 i += 1;
             }
-            _ => {
-                eprintln!("未知参数: {}", args[i]);
-                print_help();
-                return Err("未知参数".into());
+            hma_scope(&pm, &packages, &templates)
+        },
+        "validate" => hma_validate(&pm, args.get(3).map(|s| s.as_str())),
+        "backups" => hma_backups(),
+        "restore" => match args.get(3) {
+            Some(id) => hma_restore(&pm, Some(id)),
+            None => {
+                eprintln!("请指定要恢复的备份 ID，可通过 rshy hma backups 查看");
+                Err("缺少备份 ID".into())
             }
+        },
+        _ => {
+            eprintln!("未知的 hma 子命令: {}", args[2]);
+            print_help();
+            Err("未知的 hma 子命令".into())
         }
     }
-
-    download_file(url.clone(), use_cdn, save_path, expected_hash).await?;
-    Ok(())
 }
 
-fn handle_tools(args: &[String]) -> AppResult {
+fn handle_tricky(args: &[String]) -> AppResult {
     if args.len() < 3 {
-        return Err("Tool name is required".into());
+        print_help();
+        return Err("参数不足".into());
     }
-    let tool_name = &args[2];
-    run_useful_tool(tool_name)?;
-    println!("工具执行完成");
-    Ok(())
-}
 
-fn handle_integrity_check(args: &[String]) -> AppResult {
-    if args.len() < 4 {
-        return Err("File path and expected hash value are required".into());
+    match args[2].as_str() {
+        "target" | "keybox" if args.len() < 4 => {
+            print_help();
+            Err("参数不足".into())
+        },
+        "target" => handle_tricky_target(args),
+        "keybox" => handle_tricky_keybox(args),
+        "patch" => {
+            let date = match args.iter().position(|arg| arg == "--date") {
+                Some(index) => match args.get(index + 1) {
+                    Some(date) => Some(date.as_str()),
+                    None => {
+                        eprintln!("--date 需要指定日期");
+                        return Err("缺少日期".into());
+                    }
+                },
+                None => None,
+            };
+            generate_security_patch(date, args.iter().any(|arg| arg == "--dry-run"))
+        },
+        "validate" => validate_tricky_store(),
+        _ => {
+            eprintln!("未知的 tricky 子命令: {}", args[2]);
+            print_help();
+            Err("未知的 tricky 子命令".into())
+        }
     }
-    let file_path = &args[2];
-    let expected_hash = &args[3];
-    integrity_check(file_path, expected_hash)
 }
 
-fn print_help() {
-    eprintln!("rshy - EHoRY Cli");
-    eprintln!();
-    eprintln!("Usage: rshy <Arguments> <Extra arguments>");
-    eprintln!();
-    eprintln!("Arguments:");
-    eprintln!("  hidemyapplist");
-    eprintln!("  recoverapplist");
-    eprintln!("  lsplog");
-    eprintln!("  magisklog");
-    eprintln!("  shamiko_pattern");
-    eprintln!("  updatetarget");
-    eprintln!("  awjclean");
-    eprintln!("  rurudelete");
-    eprintln!("  aptroot");
-    eprintln!("  initrc");
-    eprintln!("  momo [Extra <tee> / <systemmount> / <development> / <sdk> / <addon> ]");
-    eprintln!("  nativetest [Extra <futile10> / <boothash> ]");
-    eprintln!("  holmes [Extra <somethingwrong> / <9ff> ]");
-    eprintln!("  hunter [Extra <shizuku> / <manager> ]");
-    eprintln!("  nativedetector [Extra <vbmeta> / <magicmount> / <lsp5> ]");
-    eprintln!();
-    eprintln!("Options:");
-    eprintln!("  -h, --help");
-    eprintln!("  -V, --version");
-    eprintln!("  -i, --integritycheck <file_path> <expected_hash>");
-    eprintln!("  -d, --delete <file|dir|files_in_dir> <path>");
-    eprintln!("  -o, --download <URL> [save_path] [expected_hash] [--no-cdn]");
-    eprintln!("  -t, --tools <tool_name>");
-    eprintln!("  --color");
-    eprintln!("  --yiyan");
-    eprintln!("  --zygiskcheck");
-    eprintln!("  --update");
-    eprintln!("  --cleanmodules");
-}
-
-fn compute_sha256(file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let mut file = File::open(file_path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 4096];
-    
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+fn generate_security_patch(date: Option<&str>, dry_run: bool) -> AppResult {
+    let patch = match date {
+        Some(date) => match tricky::SecurityPatch::forced(date) {
+            Some(patch) => patch,
+            None => {
+                eprintln!("无效的日期: {} (格式应为 YYYY-MM-DD)", date);
+                return Err("无效的日期".into());
+            }
+        },
+        None => {
+            let system = get_system_prop("ro.build.version.security_patch");
+            let vendor = get_system_prop("ro.vendor.build.security_patch");
+            let boot = get_system_prop("ro.bootimage.build.security_patch");
+            println!("系统补丁: {}", system.as_deref().unwrap_or("未知"));
+            println!("供应商补丁: {}", vendor.as_deref().unwrap_or("未知"));
+            println!("Boot 补丁: {}", boot.as_deref().unwrap_or("未知"));
+
+            match tricky::SecurityPatch::from_props(system.as_deref(), vendor.as_deref(), boot.as_deref()) {
+                Some(patch) => patch,
+                None => {
+                    eprintln!("无法读取系统安全补丁日期，请使用 --date 指定");
+                    return Err("无法读取系统安全补丁日期".into());
+                }
+            }
         }
-        hasher.update(&buffer[..bytes_read]);
+    };
+
+    let content = patch.render();
+    if dry_run {
+        print!("{}", content);
+        return Ok(());
     }
-    
-    let result = hasher.finalize();
-    Ok(format!("{:x}", result))
-}
 
-async fn handle_update() -> AppResult {
-    let url = "https://textdb.online/y13140".to_string();
-    let temp_file = std::env::temp_dir().join("y13140");
-    download_small_file_silent(&url, &temp_file).await?;
-    let content = fs::read_to_string(&temp_file)?;
-    let _ = fs::remove_file(&temp_file);
+    if let Err(e) = fs::create_dir_all(tricky::TRICKY_STORE_DIR) {
+        eprintln!("创建目录失败: {}", e);
+        return Err(e.into());
+    }
+    if let Err(e) = fs::write(tricky::SECURITY_PATCH_FILE, &content) {
+        eprintln!("写入 {} 失败: {}", tricky::SECURITY_PATCH_FILE, e);
+        return Err(e.into());
+    }
 
-    let version = content.trim().replace(|c: char| c.is_whitespace(), "");
-    println!("{}", version);
-    
+    println!("已生成 {}:", tricky::SECURITY_PATCH_FILE);
+    print!("{}", content);
     Ok(())
 }
 
-async fn download_small_file_silent(url: &str, file_path: &std::path::Path) -> AppResult {
-    let client = Client::new();
-
-    let response = client.get(url)
-        .send()
-        .await
-        .map_err(|e| format!("下载失败: {}", e))?;
-
-    if !response.status().is_success() {
-        return Err(format!("HTTP错误: {}", response.status()).into());
+fn validate_tricky_store() -> AppResult {
+    let version = fs::read_to_string(tricky::MODULE_PROP)
+        .ok()
+        .and_then(|content| tricky::parse_module_version(&content));
+    match version {
+        Some((major, minor, patch)) => println!("Tricky Store 版本: v{}.{}.{}", major, minor, patch),
+        None => println!("未能读取 Tricky Store 版本，按最新版本校验"),
     }
 
-    let content = response.bytes()
-        .await
-        .map_err(|e| format!("读取响应失败: {}", e))?;
-
-    let mut file = File::create(file_path)
-        .map_err(|e| format!("创建文件失败: {}", e))?;
-    
-    file.write_all(&content)
-        .map_err(|e| format!("写入文件失败: {}", e))?;
-    
-    Ok(())
-}
+    let (mut errors, warnings) = tricky::validate_config_files(tricky::TRICKY_STORE_DIR, version);
+    if let Ok(content) = fs::read_to_string(keybox::KEYBOX_FILE) {
+        match keybox::Keybox::parse(&content) {
+            Ok(keybox) => errors.extend(keybox.validate(&utc_timestamp().replace('-', "")).0),
+            Err(e) => errors.push(format!("keybox.xml 解析失败: {}", e)),
+        }
+    }
 
-fn integrity_check(file_path: &str, expected_hash: &str) -> AppResult {
-    if !Path::new(file_path).exists() {
-        return Err("未检测到文件，请确认是否下载完毕".into());
+    for warning in &warnings {
+        println!("警告: {}", warning);
+    }
+    for error in &errors {
+        eprintln!("错误: {}", error);
     }
 
-    let computed_hash = compute_sha256(file_path)?;
-    if computed_hash == expected_hash {
-        println!("sha256完整性校验通过");
+    if errors.is_empty() {
+        println!("Tricky Store 配置校验通过");
         Ok(())
     } else {
-        eprintln!("sha256完整性校验未通过");
-        eprintln!("请检查文件是否已经下载100%");
-        eprintln!("如果未发现其他问题，请私信@yu13140报告此错误");
-        std::process::exit(1);
+        Err("Tricky Store 配置校验失败".into())
     }
 }
 
-fn check_zygisk() -> Result<(), Box<dyn std::error::Error>> {
-    let db_path = "/data/adb/magisk.db";
+fn handle_tricky_keybox(args: &[String]) -> AppResult {
+    let mut revocation = None;
+    let mut force = false;
+    let mut files = Vec::new();
 
-    if !Path::new(db_path).exists() {
-        println!("Magisk 数据库文件未找到");
-        return Ok(());
+    let mut i = 4;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--revocation" => {
+                let Some(path) = args.get(i + 1) else {
+                    eprintln!("--revocation 需要指定吊销列表文件");
+                    return Err("缺少吊销列表文件".into());
+                };
+                revocation = Some(path.as_str());
+                i += 1;
+            }
+            "--force" => force = true,
+            _ => files.push(args[i].as_str()),
+        }
+        i += 1;
     }
 
-    let conn = match Connection::open(db_path) {
-        Ok(conn) => conn,
+    match args[3].as_str() {
+        "check" => {
+            let file = files.first().copied().unwrap_or(keybox::KEYBOX_FILE);
+            if check_keybox(file, revocation)? {
+                Ok(())
+            } else {
+                Err("keybox 校验失败".into())
+            }
+        },
+        "install" => match files.first() {
+            Some(file) => install_keybox(file, revocation, force),
+            None => {
+                eprintln!("请指定要安装的 keybox 文件");
+                Err("参数不足".into())
+            }
+        },
+        "backup" => backup_keybox().map(|_| ()),
+        _ => {
+            eprintln!("未知的 tricky keybox 子命令: {}", args[3]);
+            print_help();
+            Err("未知的 tricky keybox 子命令".into())
+        }
+    }
+}
+
+// 打印 keybox 的证书信息和校验结果，返回是否可以使用
+fn check_keybox(file: &str, revocation: Option<&str>) -> AppResult<bool> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
         Err(e) => {
-            println!("无法打开 Magisk 数据库: {}", e);
-            return Ok(());
+            eprintln!("读取 {} 失败: {}", file, e);
+            return Err(e.into());
         }
     };
 
-    let mut stmt = match conn.prepare("SELECT value FROM settings WHERE key='zygisk'") {
-        Ok(stmt) => stmt,
+    let keybox = match keybox::Keybox::parse(&content) {
+        Ok(keybox) => keybox,
         Err(e) => {
-            println!("无法准备 SQL 查询: {}", e);
-            return Ok(());
+            eprintln!("解析 {} 失败: {}", file, e);
+            return Ok(false);
         }
     };
-    
-    let current_state: Result<i32, rusqlite::Error> = stmt.query_row([], |row| row.get(0));
-    
-    match current_state {
-        Ok(state) => {
-            println!("{}", state);
-        },
-        Err(e) => {
-            println!("无法查询 Zygisk 状态: {}", e);
+
+    println!("keybox: {}", file);
+    println!("DeviceID: {}", keybox.device_id);
+    for key in &keybox.keys {
+        println!("Key [{}] 证书 {} 张:", key.algorithm, key.certificates.len());
+        for certificate in &key.certificates {
+            println!("  序列号 {}  {} <- {}  有效期至 {}", certificate.serial, certificate.subject, certificate.issuer, certificate.not_after);
         }
     }
-    
-    Ok(())
-}
 
-fn show_startup_animation() {
-    print!("\x1B[?25l");
-    io::stdout().flush().unwrap();
+    let now = utc_timestamp().replace('-', "");
+    let (mut errors, warnings) = keybox.validate(&now);
+    if let Some(revocation) = revocation {
+        errors.extend(keybox::check_revocation(&keybox, revocation)?);
+    }
 
-    print!("\x1B[2J\x1B[H");
-    io::stdout().flush().unwrap();
+    for warning in &warnings {
+        println!("警告: {}", warning);
+    }
+    for error in &errors {
+        eprintln!("错误: {}", error);
+    }
 
-    let title_lines = [
-        ".########.##.....##..#######..########..##....##...",
-        ".##.......##.....##.##.....##.##.....##..##..##....",
-        ".##.......##.....##.##.....##.##.....##...####.....",
-        ".######...#########.##.....##.########.....##......",
-        ".##.......##.....##.##.....##.##...##......##......",
-        ".##.......##.....##.##.....##.##....##.....##......",
-        ".########.##.....##..#######..##.....##....##......",
-    ];
+    if errors.is_empty() {
+        println!("keybox 校验通过");
+    }
+    Ok(errors.is_empty())
+}
 
-    let colors = [196, 202, 208, 214, 220, 226, 190, 154, 118, 82, 46, 47, 48, 49, 50, 51, 45, 39, 33, 27, 21];
-    let progress_width = 20;
-    let increment = 2;
+fn backup_keybox() -> AppResult<Option<String>> {
+    if !Path::new(keybox::KEYBOX_FILE).exists() {
+        println!("当前没有 keybox，跳过备份");
+        return Ok(None);
+    }
 
-    let total_cols = title_lines[0].len();
+    if let Err(e) = fs::create_dir_all(keybox::BACKUP_DIR) {
+        eprintln!("创建备份目录失败: {}", e);
+        return Err(e.into());
+    }
 
-    println!("\n\n");
+    let hash = compute_sha256(keybox::KEYBOX_FILE)?;
+    let backup_path = format!("{}/keybox-{}-{}.xml", keybox::BACKUP_DIR, utc_timestamp(), &hash[..8]);
+    if let Err(e) = fs::copy(keybox::KEYBOX_FILE, &backup_path) {
+        eprintln!("备份 keybox 失败: {}", e);
+        return Err(e.into());
+    }
 
-    for _ in 0..7 {
-        for _ in 0..total_cols {
-            print!(" ");
+    println!("已备份当前 keybox 到 {} (SHA-256: {})", backup_path, hash);
+    Ok(Some(backup_path))
+}
+
+fn install_keybox(file: &str, revocation: Option<&str>, force: bool) -> AppResult {
+    if !check_keybox(file, revocation)? {
+        if !force {
+            eprintln!("keybox 未通过校验，已取消安装 (使用 --force 强制安装)");
+            return Err("keybox 未通过校验".into());
         }
-        println!();
+        println!("已指定 --force，忽略校验错误");
     }
 
-    fn print_title_by_column(title_lines: &[&str], percentage: u32, colors: &[u32]) {
-        let total_cols = title_lines[0].len();
-        let cols_to_show = (total_cols as u32 * percentage / 100) as usize;
-        
-        let mut output = String::new();
+    backup_keybox()?;
+
+    if let Err(e) = fs::create_dir_all(tricky::TRICKY_STORE_DIR) {
+        eprintln!("创建目录失败: {}", e);
+        return Err(e.into());
+    }
+    if let Err(e) = fs::copy(file, keybox::KEYBOX_FILE) {
+        eprintln!("安装 keybox 失败: {}", e);
+        return Err(e.into());
+    }
+
+    println!("已安装 keybox 到 {}", keybox::KEYBOX_FILE);
+    Ok(())
+}
+
+fn handle_tricky_target(args: &[String]) -> AppResult {
+    let mut mode = None;
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    let (mut user, mut system) = (false, false);
+    let mut packages = Vec::new();
+
+    let mut i = 4;
+    while i < args.len() {
+        match args[i].as_str() {
+            option @ ("--mode" | "--include" | "--exclude") => {
+                let Some(value) = args.get(i + 1) else {
+                    eprintln!("{} 需要指定参数", option);
+                    return Err("缺少参数".into());
+                };
+                match option {
+                    "--mode" => mode = Some(tricky::TargetMode::parse(value)?),
+                    "--include" => include.push(value.clone()),
+                    _ => exclude.push(value.clone()),
+                }
+                i += 1;
+            }
+            "--user" => user = true,
+            "--system" => system = true,
+            _ => packages.push(args[i].clone()),
+        }
+        i += 1;
+    }
+    // 同时指定 --user 和 --system 等同于不过滤
+    let filter = match (user, system) {
+        (true, false) => pm::PackageFilter::ThirdParty,
+        (false, true) => pm::PackageFilter::System,
+        _ => pm::PackageFilter::All,
+    };
+
+    match args[3].as_str() {
+        "list" => tricky_target_list(),
+        "add" => tricky_target_add(&packages, mode.unwrap_or(tricky::TargetMode::Auto)),
+        "remove" => tricky_target_remove(&packages),
+        "mode" => match (packages.as_slice(), mode) {
+            ([package], Some(mode)) => tricky_target_add(std::slice::from_ref(package), mode),
+            ([package, mode], None) => tricky_target_add(std::slice::from_ref(package), tricky::TargetMode::parse(mode)?),
+            _ => {
+                eprintln!("用法: rshy tricky target mode <package> <auto|generate|leaf>");
+                Err("参数错误".into())
+            }
+        },
+        "sync" => {
+            let installed = installed_packages(&pm::system(), filter)?;
+            tricky_target_sync(&installed, &include, &exclude, mode.unwrap_or(tricky::TargetMode::Auto))
+        },
+        _ => {
+            eprintln!("未知的 tricky target 子命令: {}", args[3]);
+            print_help();
+            Err("未知的 tricky target 子命令".into())
+        }
+    }
+}
+
+fn tricky_target_list() -> AppResult {
+    let targets = tricky::TargetList::load(tricky::TARGET_FILE)?;
+    let entries = targets.entries();
+    if entries.is_empty() {
+        println!("{} 中没有任何应用", tricky::TARGET_FILE);
+        return Ok(());
+    }
+
+    for (package, mode) in entries {
+        println!("{} [{}]", package, mode.label());
+    }
+    Ok(())
+}
+
+fn tricky_target_add(packages: &[String], mode: tricky::TargetMode) -> AppResult {
+    if packages.is_empty() {
+        eprintln!("请指定要添加的包名");
+        return Err("参数不足".into());
+    }
+
+    let mut targets = tricky::TargetList::load(tricky::TARGET_FILE)?;
+    for package in packages {
+        if targets.add(package, mode) {
+            println!("已设置 {} [{}]", package, mode.label());
+        } else {
+            println!("{} 已存在，无需修改", package);
+        }
+    }
+    targets.save(tricky::TARGET_FILE)
+}
+
+fn tricky_target_remove(packages: &[String]) -> AppResult {
+    if packages.is_empty() {
+        eprintln!("请指定要移除的包名");
+        return Err("参数不足".into());
+    }
+
+    let mut targets = tricky::TargetList::load(tricky::TARGET_FILE)?;
+    for package in packages {
+        if targets.remove(package) {
+            println!("已移除 {}", package);
+        } else {
+            println!("{} 不在列表中", package);
+        }
+    }
+    targets.save(tricky::TARGET_FILE)
+}
+
+fn tricky_target_sync(installed: &[String], include: &[String], exclude: &[String], mode: tricky::TargetMode) -> AppResult {
+    let mut targets = tricky::TargetList::load(tricky::TARGET_FILE)?;
+    let added = targets.sync(installed, include, exclude, mode);
+    targets.save(tricky::TARGET_FILE)?;
+
+    println!("已同步 {} 个新应用到 {}", added.len(), tricky::TARGET_FILE);
+    Ok(())
+}
+
+fn handle_assets(args: &[String]) -> AppResult {
+    match (args.get(2).map(|s| s.as_str()), args.get(3)) {
+        (Some("list"), _) => assets_list(),
+        (Some("extract"), Some(dir)) => assets_extract(Path::new(dir), &args[4..]),
+        (Some("extract"), None) => {
+            eprintln!("用法: rshy assets extract <dir> [name...]");
+            Err("参数不足".into())
+        },
+        (Some(other), _) => {
+            eprintln!("未知的 assets 子命令: {}", other);
+            print_help();
+            Err("未知的 assets 子命令".into())
+        },
+        (None, _) => {
+            print_help();
+            Err("参数不足".into())
+        }
+    }
+}
+
+fn assets_list() -> AppResult {
+    let bundle = tools::bundle().inspect_err(|e| eprintln!("{}", e))?;
+    let abis = tools::device_abis();
+    println!("内置资源包版本: {}", bundle.manifest.version);
+    println!("本机 ABI: {}", abis.join(", "));
+    // * 标记当前设备上实际会使用的版本
+    for asset in &bundle.manifest.assets {
+        let selected = tools::select_asset(&bundle.manifest.assets, &asset.name, abis)
+            .is_ok_and(|selected| std::ptr::eq(selected, asset));
+        let marker = if selected { "*" } else { " " };
+        println!("{} {:<24} {:<12} {:<8} {:>10} 字节  {}", marker, asset.name, asset.abi, asset.version, asset.size, &asset.sha256[..16]);
+    }
+    Ok(())
+}
+
+// 按资源包内的路径释放，不同 ABI 的同名工具不会互相覆盖
+fn assets_extract(dir: &Path, names: &[String]) -> AppResult {
+    let bundle = tools::bundle().inspect_err(|e| eprintln!("{}", e))?;
+    let selected: Vec<&bundle::Asset> = bundle.manifest.assets.iter()
+        .filter(|asset| names.is_empty() || names.contains(&asset.name))
+        .collect();
+
+    let missing: Vec<&String> = names.iter()
+        .filter(|name| !selected.iter().any(|asset| &asset.name == *name))
+        .collect();
+    if !missing.is_empty() {
+        eprintln!("资源包中没有: {}", missing.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", "));
+        return Err("未找到资源".into());
+    }
+
+    for asset in selected {
+        let relative = Path::new(&asset.path);
+        if relative.components().any(|component| !matches!(component, std::path::Component::Normal(_))) {
+            eprintln!("资源路径 {} 无效，已跳过", asset.path);
+            continue;
+        }
+
+        let data = bundle.read(asset).inspect_err(|e| eprintln!("{}", e))?;
+        let target = dir.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).inspect_err(|e| eprintln!("创建目录 {} 失败: {}", parent.display(), e))?;
+        }
+        fs::write(&target, &data).inspect_err(|e| eprintln!("写入 {} 失败: {}", target.display(), e))?;
+        let mode = if asset.abi == "any" { 0o644 } else { 0o755 };
+        fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+        println!("已释放 {} -> {}", asset.name, target.display());
+    }
+    Ok(())
+}
+
+fn handle_bootimg(args: &[String]) -> AppResult {
+    if args.len() < 4 {
+        print_help();
+        return Err("参数不足".into());
+    }
+
+    let mut magiskboot = None;
+    let mut kernel = None;
+    let mut positional = Vec::new();
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--magiskboot" | "--kernel" => {
+                let Some(value) = args.get(i + 1) else {
+                    eprintln!("{} 需要指定文件路径", args[i]);
+                    return Err("缺少文件路径".into());
+                };
+                if args[i] == "--kernel" {
+                    kernel = Some(value.as_str());
+                } else {
+                    magiskboot = Some(Path::new(value.as_str()));
+                }
+                i += 1;
+            }
+            _ => positional.push(args[i].as_str()),
+        }
+        i += 1;
+    }
+
+    match (args[2].as_str(), positional.as_slice()) {
+        ("info", [image]) => bootimg_info(image),
+        ("unpack", [image]) => bootimg_unpack(image, ".", magiskboot),
+        ("unpack", [image, out_dir]) => bootimg_unpack(image, out_dir, magiskboot),
+        ("repack", [image, output]) => bootimg_repack(image, output, kernel, magiskboot),
+        ("info" | "unpack" | "repack", _) => {
+            print_help();
+            Err("参数不足".into())
+        },
+        _ => {
+            eprintln!("未知的 bootimg 子命令: {}", args[2]);
+            print_help();
+            Err("未知的 bootimg 子命令".into())
+        }
+    }
+}
+
+fn bootimg_info(image_path: &str) -> AppResult {
+    let image = bootimg::BootImage::load(image_path).inspect_err(|e| eprintln!("{}", e))?;
+
+    println!("类型: {} v{}", image.kind.label(), image.header_version);
+    println!("页大小: {}", image.page_size);
+    for section in &image.sections {
+        if section.data.is_empty() {
+            continue;
+        }
+        println!("{:<22} {:>10} 字节  sha256={}", section.name, section.data.len(), bootimg::sha256_hex(&section.data));
+    }
+    if let Some(kernel) = image.kernel() {
+        println!("内核格式: {}", bootimg::KernelFormat::detect(kernel).label());
+    }
+    match image.checksum_valid() {
+        Some(true) => println!("镜像头校验值: 正确"),
+        Some(false) => println!("镜像头校验值: 不匹配"),
+        None => {}
+    }
+    if image.has_avb_footer() {
+        println!("镜像末尾包含 AVB footer");
+    }
+
+    Ok(())
+}
+
+fn bootimg_unpack(image_path: &str, out_dir: &str, magiskboot: Option<&Path>) -> AppResult {
+    let image = bootimg::BootImage::load(image_path).inspect_err(|e| eprintln!("{}", e))?;
+    if let Err(e) = fs::create_dir_all(out_dir) {
+        eprintln!("创建目录 {} 失败: {}", out_dir, e);
+        return Err(e.into());
+    }
+
+    for section in &image.sections {
+        if section.data.is_empty() {
+            continue;
+        }
+
+        let data = match (section.name, magiskboot) {
+            ("kernel", Some(magiskboot)) => bootimg::decompress_kernel(magiskboot, &section.data, Path::new(out_dir))
+                .inspect_err(|e| eprintln!("解压内核失败: {}", e))?,
+            _ => section.data.clone(),
+        };
+        let path = Path::new(out_dir).join(section.name);
+        if let Err(e) = fs::write(&path, &data) {
+            eprintln!("写入 {} 失败: {}", path.display(), e);
+            return Err(e.into());
+        }
+        println!("已提取 {} ({} 字节)", path.display(), data.len());
+    }
+
+    Ok(())
+}
+
+fn bootimg_repack(image_path: &str, output_path: &str, kernel_path: Option<&str>, magiskboot: Option<&Path>) -> AppResult {
+    let mut image = bootimg::BootImage::load(image_path).inspect_err(|e| eprintln!("{}", e))?;
+    let work_dir = Path::new(output_path).parent().unwrap_or(Path::new("."));
+
+    if let Some(kernel_path) = kernel_path {
+        let original = bootimg::KernelFormat::detect(image.kernel().unwrap_or_default());
+        let mut kernel = fs::read(kernel_path).inspect_err(|e| eprintln!("读取 {} 失败: {}", kernel_path, e))?;
+
+        // 原内核是压缩格式而新内核未压缩时，按原格式重新压缩
+        if original.is_compressed() && !bootimg::KernelFormat::detect(&kernel).is_compressed() {
+            let Some(magiskboot) = magiskboot else {
+                eprintln!("原内核为 {} 格式，需要通过 --magiskboot 指定 magiskboot 进行压缩", original.label());
+                return Err("缺少 magiskboot".into());
+            };
+            kernel = bootimg::compress_kernel(magiskboot, &kernel, original, work_dir)
+                .inspect_err(|e| eprintln!("压缩内核失败: {}", e))?;
+        }
+        image.replace_kernel(kernel).inspect_err(|e| eprintln!("{}", e))?;
+    }
+
+    let data = image.repack_checked().inspect_err(|e| eprintln!("{}", e))?;
+
+    if let Err(e) = fs::write(output_path, &data) {
+        eprintln!("写入 {} 失败: {}", output_path, e);
+        return Err(e.into());
+    }
+    if image.has_avb_footer() {
+        println!("原镜像末尾的 AVB footer 未写入新镜像");
+    }
+    if image.header_version == 4 && kernel_path.is_some() && image.section("signature").is_some_and(|s| !s.is_empty()) {
+        println!("内核已替换，镜像中的 boot 签名将不再有效");
+    }
+    println!("已生成 {} ({} 字节, sha256={})", output_path, data.len(), bootimg::sha256_hex(&data));
+
+    Ok(())
+}
+
+struct PartitionOptions {
+    dirs: Vec<PathBuf>,
+    slot_suffix: Option<String>,
+    backup_dir: PathBuf,
+    force: bool,
+}
+
+// 解析分区相关的选项，其余参数原样返回
+fn parse_partition_options(args: &[String]) -> AppResult<(PartitionOptions, Vec<&str>)> {
+    let mut by_name = None;
+    let mut slot_suffix = None;
+    let mut backup_dir = None;
+    let mut force = false;
+    let mut rest = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--by-name" | "--slot" | "--backup-dir" => {
+                let Some(value) = args.get(i + 1) else {
+                    eprintln!("{} 需要指定参数", args[i]);
+                    return Err("缺少参数".into());
+                };
+                match args[i].as_str() {
+                    "--by-name" => by_name = Some(PathBuf::from(value)),
+                    "--slot" => slot_suffix = Some(value.clone()),
+                    _ => backup_dir = Some(PathBuf::from(value)),
+                }
+                i += 1;
+            }
+            "--force" => force = true,
+            _ => rest.push(args[i].as_str()),
+        }
+        i += 1;
+    }
+
+    // 只有 A/B 设备才需要槽位后缀
+    let slot_suffix = slot_suffix.or_else(|| {
+        (get_system_prop("ro.build.ab_update").as_deref() == Some("true"))
+            .then(|| get_system_prop("ro.boot.slot_suffix"))
+            .flatten()
+    });
+    let options = PartitionOptions {
+        dirs: match by_name {
+            Some(dir) => vec![dir],
+            None => partition::BY_NAME_DIRS.iter().map(PathBuf::from).collect(),
+        },
+        slot_suffix,
+        backup_dir: backup_dir.unwrap_or_else(|| PathBuf::from(partition::BACKUP_DIR)),
+        force,
+    };
+    Ok((options, rest))
+}
+
+fn handle_partition(args: &[String]) -> AppResult {
+    if args.len() < 3 {
+        print_help();
+        return Err("参数不足".into());
+    }
+
+    let (options, positional) = parse_partition_options(&args[3..])?;
+    match (args[2].as_str(), positional.as_slice()) {
+        ("info", [name]) => partition_info(name, &options),
+        ("backup", [name]) => {
+            let target = resolve_partition(name, &options)?;
+            partition_backup(&target, &options).map(|_| ())
+        },
+        ("write", [name, image]) => partition_write(name, image, &options),
+        ("backups", []) => partition_backups(&options),
+        ("restore", [backup]) => partition_restore(backup, &options),
+        ("info" | "backup" | "write" | "restore", _) => {
+            print_help();
+            Err("参数不足".into())
+        },
+        _ => {
+            eprintln!("未知的 partition 子命令: {}", args[2]);
+            print_help();
+            Err("未知的 partition 子命令".into())
+        }
+    }
+}
+
+fn resolve_partition(name: &str, options: &PartitionOptions) -> AppResult<partition::Partition> {
+    match partition::resolve(&options.dirs, name, options.slot_suffix.as_deref()) {
+        Ok(target) => Ok(target),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(e)
+        }
+    }
+}
+
+fn partition_info(name: &str, options: &PartitionOptions) -> AppResult {
+    let target = resolve_partition(name, options)?;
+    println!("分区: {}", target.name);
+    println!("路径: {}", target.path.display());
+    match fs::canonicalize(&target.path) {
+        Ok(real_path) if real_path != target.path => println!("设备: {}", real_path.display()),
+        _ => {}
+    }
+    println!("大小: {} 字节", target.size);
+    match &options.slot_suffix {
+        Some(suffix) => println!("当前槽位: {}", suffix),
+        None => println!("当前槽位: 无 (非 A/B 设备)"),
+    }
+    Ok(())
+}
+
+fn partition_backup(target: &partition::Partition, options: &PartitionOptions) -> AppResult<partition::Backup> {
+    println!("正在备份 {} ({} 字节)", target.name, target.size);
+    match partition::create_backup(target, &options.backup_dir) {
+        Ok(backup) => {
+            println!("已备份到 {}", backup.image.display());
+            println!("sha256: {}", backup.meta.sha256);
+            Ok(backup)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(e)
+        }
+    }
+}
+
+// 写入前必定完整备份当前分区，写入后读回校验
+fn write_partition_image(target: &partition::Partition, data: &[u8], options: &PartitionOptions) -> AppResult {
+    let backup = partition_backup(target, options)?;
+
+    println!("正在写入 {}", target.path.display());
+    match partition::write_image(target, data) {
+        Ok(direct) => {
+            if !direct {
+                println!("设备不支持 O_DIRECT，已使用普通写入并同步");
+            }
+            println!("已写入 {} 字节并通过读回校验", data.len());
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("写入 {} 失败: {}", target.name, e);
+            eprintln!("可使用 rshy partition restore {} 恢复", backup.id);
+            Err(e)
+        }
+    }
+}
+
+fn partition_write(name: &str, image_path: &str, options: &PartitionOptions) -> AppResult {
+    let target = resolve_partition(name, options)?;
+    let data = match fs::read(image_path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("读取 {} 失败: {}", image_path, e);
+            return Err(e.into());
+        }
+    };
+
+    let base_name = target.name.trim_end_matches("_a").trim_end_matches("_b");
+    let boot_like = matches!(base_name, "boot" | "init_boot" | "vendor_boot");
+    match bootimg::BootImage::parse(&data) {
+        Err(e) if boot_like && !options.force => {
+            eprintln!("{} 不是有效的 {} 镜像: {} (使用 --force 跳过检查)", image_path, base_name, e);
+            return Err("镜像格式错误".into());
+        }
+        _ => {}
+    }
+
+    println!("将把 {} ({} 字节) 写入 {} ({})", image_path, data.len(), target.name, target.path.display());
+    let proceed = prompt::confirm(
+        "confirm_partition_write",
+        "写入分区存在变砖风险，是否继续？(y/N): ",
+        |input| input.eq_ignore_ascii_case("y"),
+    )?;
+    if !proceed {
+        println!("你选择了退出");
+        return Ok(());
+    }
+
+    write_partition_image(&target, &data, options)
+}
+
+fn partition_backups(options: &PartitionOptions) -> AppResult {
+    let backups = partition::list_backups(&options.backup_dir);
+    if backups.is_empty() {
+        println!("{} 中没有分区备份", options.backup_dir.display());
+        return Ok(());
+    }
+
+    for backup in backups {
+        println!("{}  {} 字节  {}  sha256={}", backup.id, backup.meta.size, backup.meta.device, backup.meta.sha256);
+    }
+    Ok(())
+}
+
+fn partition_restore(reference: &str, options: &PartitionOptions) -> AppResult {
+    let backup = match partition::find_backup(&options.backup_dir, reference) {
+        Ok(backup) => backup,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = backup.verify() {
+        eprintln!("备份校验失败: {}", e);
+        return Err(e);
+    }
+
+    let target = match partition::Partition::at_path(&backup.meta.partition, Path::new(&backup.meta.device)) {
+        Ok(target) => target,
+        Err(e) => {
+            eprintln!("无法打开备份对应的分区: {}", e);
+            return Err(e);
+        }
+    };
+
+    let data = fs::read(&backup.image)?;
+    println!("将用备份 {} 恢复 {} ({})", backup.id, target.name, target.path.display());
+    let proceed = prompt::confirm(
+        "confirm_partition_write",
+        "恢复会覆盖分区当前的内容，是否继续？(y/N): ",
+        |input| input.eq_ignore_ascii_case("y"),
+    )?;
+    if !proceed {
+        println!("你选择了退出");
+        return Ok(());
+    }
+
+    write_partition_image(&target, &data, options)
+}
+
+struct KpmOptions<'a> {
+    superkey_file: Option<&'a str>,
+    catalog: Option<&'a str>,
+    load_args: Option<&'a str>,
+    image: Option<&'a str>,
+    modules: Vec<&'a str>,
+    nohello: bool,
+}
+
+async fn handle_kpm(args: &[String]) -> AppResult {
+    if args.len() < 3 {
+        print_help();
+        return Err("参数不足".into());
+    }
+
+    let (partition_options, rest) = parse_partition_options(&args[3..])?;
+    let mut options = KpmOptions {
+        superkey_file: None,
+        catalog: None,
+        load_args: None,
+        image: None,
+        modules: Vec::new(),
+        nohello: false,
+    };
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < rest.len() {
+        match rest[i] {
+            "--superkey-file" | "--catalog" | "--args" | "--image" | "--module" => {
+                let Some(value) = rest.get(i + 1).copied() else {
+                    eprintln!("{} 需要指定参数", rest[i]);
+                    return Err("缺少参数".into());
+                };
+                match rest[i] {
+                    "--superkey-file" => options.superkey_file = Some(value),
+                    "--catalog" => options.catalog = Some(value),
+                    "--args" => options.load_args = Some(value),
+                    "--image" => options.image = Some(value),
+                    _ => options.modules.push(value),
+                }
+                i += 1;
+            }
+            "--nohello" => options.nohello = true,
+            _ => positional.push(rest[i]),
+        }
+        i += 1;
+    }
+
+    match (args[2].as_str(), positional.as_slice()) {
+        ("list", []) => kpm_list(&pm::system(), &options),
+        ("load", [file]) => kpm_load(&pm::system(), file, &options),
+        ("unload", [name]) => kpm_unload(&pm::system(), name, &options),
+        ("catalog", []) => kpm_catalog(&options),
+        ("embed", []) => kpm_embed(&pm::system(), &options, &partition_options).await,
+        ("load" | "unload", _) => {
+            print_help();
+            Err("参数不足".into())
+        },
+        _ => {
+            eprintln!("未知的 kpm 子命令: {}", args[2]);
+            print_help();
+            Err("未知的 kpm 子命令".into())
+        }
+    }
+}
+
+struct ApatchInstall {
+    version: u32,
+    package: &'static str,
+    apk_path: PathBuf,
+    lib_dir: PathBuf,
+}
+
+fn locate_apatch(pm: &dyn pm::PackageManager) -> AppResult<ApatchInstall> {
+    let Some(version) = kpm::read_apatch_version() else {
+        eprintln!("未检测到 APatch ({} 不存在)", kpm::APATCH_VERSION_FILE);
+        return Err("未检测到 APatch".into());
+    };
+
+    let mut installed = Vec::new();
+    for package in kpm::APATCH_PACKAGES {
+        // 未安装的包在 cmd package path 中返回失败，不视为错误
+        let apk_path = pm.package_paths(package)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|path| path.ends_with("base.apk"));
+        if let Some(apk_path) = apk_path {
+            installed.push((package, apk_path));
+        }
+    }
+    if installed.len() > 1 {
+        println!("同时安装了 {} 和 {}，使用 {}", installed[0].0, installed[1].0, installed[0].0);
+    }
+    let Some((package, apk_path)) = installed.into_iter().next() else {
+        eprintln!("未找到 APatch 管理器 ({})，请确认是否安装了 APatch 管理器", kpm::APATCH_PACKAGES.join(" / "));
+        return Err("未找到 APatch 管理器".into());
+    };
+    let lib_dir = apk_path.parent().unwrap_or(Path::new("/")).join("lib/arm64");
+
+    Ok(ApatchInstall { version, package, apk_path, lib_dir })
+}
+
+fn read_superkey(options: &KpmOptions) -> AppResult<String> {
+    let result = match options.superkey_file {
+        Some(path) => kpm::read_superkey_file(Path::new(path)),
+        None => kpm::prompt_superkey(),
+    };
+    result.inspect_err(|e| eprintln!("{}", e))
+}
+
+fn open_kpatch(apatch: &ApatchInstall, options: &KpmOptions) -> AppResult<kpm::Kpatch> {
+    let path = apatch.lib_dir.join("libkpatch.so");
+    if !path.exists() {
+        eprintln!("未找到 {}", path.display());
+        return Err("未找到 kpatch".into());
+    }
+    Ok(kpm::Kpatch::new(path, read_superkey(options)?))
+}
+
+fn kpm_list(pm: &dyn pm::PackageManager, options: &KpmOptions) -> AppResult {
+    let apatch = locate_apatch(pm)?;
+    let kpatch = open_kpatch(&apatch, options)?;
+
+    let modules = kpatch.list().inspect_err(|e| eprintln!("{}", e))?;
+    if modules.is_empty() {
+        println!("没有已加载的内核模块");
+        return Ok(());
+    }
+    println!("已加载 {} 个内核模块:", modules.len());
+    for name in &modules {
+        println!("{}", name);
+        if let Ok(info) = kpatch.info(name) {
+            for line in info.lines().filter(|line| !line.trim().is_empty()) {
+                println!("    {}", line.trim());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn kpm_load(pm: &dyn pm::PackageManager, file: &str, options: &KpmOptions) -> AppResult {
+    let path = fs::canonicalize(file).inspect_err(|e| eprintln!("无法读取 {}: {}", file, e))?;
+    let apatch = locate_apatch(pm)?;
+    let kpatch = open_kpatch(&apatch, options)?;
+
+    let output = kpatch.load(&path, options.load_args).inspect_err(|e| eprintln!("{}", e))?;
+    if !output.trim().is_empty() {
+        println!("{}", output.trim());
+    }
+    println!("已加载 {}", path.display());
+    Ok(())
+}
+
+fn kpm_unload(pm: &dyn pm::PackageManager, name: &str, options: &KpmOptions) -> AppResult {
+    let apatch = locate_apatch(pm)?;
+    let kpatch = open_kpatch(&apatch, options)?;
+
+    kpatch.unload(name).inspect_err(|e| eprintln!("{}", e))?;
+    println!("已卸载 {}", name);
+    Ok(())
+}
+
+fn kpm_catalog(options: &KpmOptions) -> AppResult {
+    let catalog = kpm::load_catalog(options.catalog).inspect_err(|e| eprintln!("{}", e))?;
+    let version = kpm::read_apatch_version();
+
+    for entry in &catalog {
+        let mark = match version {
+            Some(version) if kpm::select(&catalog, &entry.module, version).is_some_and(|selected| std::ptr::eq(selected, entry)) => "*",
+            _ => " ",
+        };
+        println!(
+            "{} {:<10} {:<28} APatch {}{}",
+            mark, entry.module, entry.file, entry.version_range(), if entry.optional { " (可选)" } else { "" }
+        );
+    }
+    match version {
+        Some(version) => println!("当前 APatch 版本: {}，标记 * 的条目将被使用", version),
+        None => println!("未检测到 APatch"),
+    }
+    Ok(())
+}
+
+async fn download_kpm(entry: &kpm::CatalogEntry, work_dir: &Path) -> AppResult<PathBuf> {
+    let path = work_dir.join(&entry.file);
+    println!("正在下载 {}", entry.file);
+    if let Err(e) = download_file(entry.download_url(), true, Some(path.clone()), None, &DownloadLimits::default()).await {
+        eprintln!("{} 下载失败: {}", entry.file, e);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+fn extract_kpimg(apk_path: &Path, output: &Path) -> AppResult {
+    let mut archive = zip::ZipArchive::new(File::open(apk_path)?)?;
+    let mut entry = archive.by_name("assets/kpimg").map_err(|_| format!("{} 中没有 assets/kpimg", apk_path.display()))?;
+    let mut file = File::create(output)?;
+    io::copy(&mut entry, &mut file)?;
+    Ok(())
+}
+
+// 替代原来脚本中的 peekaboo 嵌入流程：修补内核后重新打包 boot，并通过分区模块备份、写入和校验
+async fn kpm_embed(pm: &dyn pm::PackageManager, options: &KpmOptions<'_>, partition_options: &PartitionOptions) -> AppResult {
+    let apatch = locate_apatch(pm)?;
+    println!("检测到 APatch ({})", apatch.version);
+
+    let kptools = kpm::Kptools { path: apatch.lib_dir.join("libkptools.so") };
+    let magiskboot = apatch.lib_dir.join("libmagiskboot.so");
+    for tool in [&kptools.path, &magiskboot] {
+        if !tool.exists() {
+            eprintln!("未找到 {}", tool.display());
+            return Err("APatch 管理器文件不完整".into());
+        }
+    }
+    let kpatch = open_kpatch(&apatch, options)?;
+
+    let work_dir = PathBuf::from("/data/cache/recovery/yshell/kpm");
+    if let Err(e) = fs::create_dir_all(&work_dir) {
+        eprintln!("创建临时目录失败: {}", e);
+        return Err(e.into());
+    }
+    let result = kpm_embed_in(&apatch, &kpatch, &kptools, &magiskboot, &work_dir, options, partition_options).await;
+    let _ = fs::remove_dir_all(&work_dir);
+    result
+}
+
+async fn kpm_embed_in(
+    apatch: &ApatchInstall,
+    kpatch: &kpm::Kpatch,
+    kptools: &kpm::Kptools,
+    magiskboot: &Path,
+    work_dir: &Path,
+    options: &KpmOptions<'_>,
+    partition_options: &PartitionOptions,
+) -> AppResult {
+    let mut modules = Vec::new();
+    if options.modules.is_empty() {
+        let catalog = kpm::load_catalog(options.catalog).inspect_err(|e| eprintln!("{}", e))?;
+        let Some(peekaboo) = kpm::select(&catalog, "peekaboo", apatch.version) else {
+            eprintln!("KPM 目录中没有适用于 APatch {} 的 peekaboo", apatch.version);
+            return Err("没有适用的 peekaboo".into());
+        };
+        println!("推荐使用 {}", peekaboo.file);
+        modules.push(download_kpm(peekaboo, work_dir).await?);
+
+        if options.nohello {
+            match kpm::select(&catalog, "nohello", apatch.version) {
+                Some(nohello) => modules.push(download_kpm(nohello, work_dir).await?),
+                None => println!("您的 APatch 版本 ({}) 不建议嵌入 NoHello，已跳过", apatch.version),
+            }
+        }
+    } else {
+        for module in &options.modules {
+            let path = fs::canonicalize(module).inspect_err(|e| eprintln!("无法读取 {}: {}", module, e))?;
+            modules.push(path);
+        }
+    }
+
+    if kpatch.count().inspect_err(|e| eprintln!("{}", e))? > 0 {
+        println!("检测到已经装有内核模块，继续安装会把已经嵌入的内核模块删除");
+        let proceed = prompt::confirm(
+            "kpm_replace_existing",
+            "是否仍要安装？(1.仍要安装  2.不安装): ",
+            |input| input == "1",
+        )?;
+        if !proceed {
+            println!("{}", prompt::Aborted);
+            return Err(prompt::Aborted.into());
+        }
+    }
+
+    let target = resolve_partition("boot", partition_options)?;
+    let patched_boot = format!("/data/data/{}/new-boot.img", apatch.package);
+    let source = match options.image {
+        Some(image) => PathBuf::from(image),
+        None if Path::new(&patched_boot).exists() => PathBuf::from(&patched_boot),
+        None => {
+            println!("未找到 APatch 修补后的 boot.img，将使用 {} 分区中的镜像", target.name);
+            target.path.clone()
+        }
+    };
+    let mut image = bootimg::BootImage::load(&source.to_string_lossy()).inspect_err(|e| eprintln!("{}", e))?;
+    let original = image.kernel().unwrap_or_default().to_vec();
+    let format = bootimg::KernelFormat::detect(&original);
+
+    let kernel_path = work_dir.join("kernel");
+    let raw_kernel = bootimg::decompress_kernel(magiskboot, &original, work_dir).inspect_err(|e| eprintln!("解压内核失败: {}", e))?;
+    fs::write(&kernel_path, &raw_kernel).inspect_err(|e| eprintln!("写入 {} 失败: {}", kernel_path.display(), e))?;
+
+    // 已被 APatch 修补过的内核需要先还原，再重新嵌入模块
+    let clean_kernel = if kptools.is_patched(&kernel_path).inspect_err(|e| eprintln!("{}", e))? {
+        let clean_kernel = work_dir.join("rekernel");
+        kptools.unpatch(&kernel_path, &clean_kernel).inspect_err(|e| eprintln!("还原内核失败: {}", e))?;
+        clean_kernel
+    } else {
+        kernel_path
+    };
+
+    let kpimg = work_dir.join("kpimg");
+    extract_kpimg(&apatch.apk_path, &kpimg).inspect_err(|e| eprintln!("提取 kpimg 失败: {}", e))?;
+
+    let patched_kernel = work_dir.join("kernel.patched");
+    kptools.patch(&clean_kernel, &kpimg, kpatch.superkey(), &modules, &patched_kernel)
+        .inspect_err(|e| eprintln!("修补内核失败: {}", e))?;
+    let patched = fs::read(&patched_kernel)
+        .map_err(|e| e.into())
+        .and_then(|kernel| bootimg::compress_kernel(magiskboot, &kernel, format, work_dir))
+        .inspect_err(|e| eprintln!("压缩内核失败: {}", e))?;
+
+    image.replace_kernel(patched).inspect_err(|e| eprintln!("{}", e))?;
+    let data = image.repack_checked().inspect_err(|e| eprintln!("{}", e))?;
+
+    println!("将把修补后的 boot ({} 字节) 写入 {} ({})", data.len(), target.name, target.path.display());
+    let proceed = prompt::confirm(
+        "confirm_partition_write",
+        "这是一个高危操作，请确保手机自备救砖能力。是否继续？(y/N): ",
+        |input| input.eq_ignore_ascii_case("y"),
+    )?;
+    if !proceed {
+        println!("{}", prompt::Aborted);
+        return Err(prompt::Aborted.into());
+    }
+
+    write_partition_image(&target, &data, partition_options)?;
+    println!("已成功嵌入 {} 个内核模块，重启后生效", modules.len());
+    Ok(())
+}
+
+fn handle_find_app(args: &[String]) -> AppResult {
+    if args.len() < 3 {
+        print_help();
+        return Err("参数不足".into());
+    }
+
+    let signatures_file = match args.iter().position(|arg| arg == "--signatures") {
+        Some(index) => match args.get(index + 1) {
+            Some(path) => Some(path.as_str()),
+            None => {
+                eprintln!("--signatures 需要指定签名库文件");
+                return Err("缺少签名库文件".into());
+            }
+        },
+        None => None,
+    };
+
+    find_app(&pm::system(), &args[2], signatures_file)
+}
+
+async fn handle_download(args: &[String]) -> AppResult {
+    if args.len() < 3 {
+        return Err("Download URL is required".into());
+    }
+
+    let url = &args[2];
+    let mut use_cdn = true;
+    let mut save_path = None;
+    let mut expected_hash = None;
+    let mut limits = DownloadLimits::default();
+    
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--no-cdn" => {
+                use_cdn = false;
+                i += 1;
+            }
+            "--limit-rate" => {
+                let value = args.get(i + 1).ok_or("--limit-rate 需要指定速率")?;
+                limits.rate = Some(parse_size(value)?);
+                i += 2;
+            }
+            "--metered-threshold" => {
+                let value = args.get(i + 1).ok_or("--metered-threshold 需要指定大小")?;
+                limits.metered_threshold = parse_size(value)?;
+                i += 2;
+            }
+            _ if save_path.is_none() => {
+                save_path = Some(std::path::PathBuf::from(&args[i]));
+                i += 1;
+            }
+            _ if expected_hash.is_none() => {
+                expected_hash = Some(args[i].clone());
+                i += 1;
+            }
+            _ => {
+                eprintln!("未知参数: {}", args[i]);
+                print_help();
+                return Err("未知参数".into());
+            }
+        }
+    }
+
+    download_file(url.clone(), use_cdn, save_path, expected_hash, &limits).await?;
+    Ok(())
+}
+
+fn parse_size(value: &str) -> AppResult<u64> {
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches('B');
+    let (digits, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1024),
+        Some('M') => (&number[..number.len() - 1], 1024 * 1024),
+        Some('G') => (&number[..number.len() - 1], 1024 * 1024 * 1024),
+        _ => (number, 1),
+    };
+
+    match digits.trim().parse::<f64>() {
+        Ok(size) if size > 0.0 => Ok((size * multiplier as f64) as u64),
+        _ => {
+            eprintln!("无效的大小: {}", value);
+            Err(format!("无效的大小: {}", value).into())
+        }
+    }
+}
+
+fn handle_tools(args: &[String]) -> AppResult {
+    if args.len() < 3 {
+        return Err("Tool name is required".into());
+    }
+    let tool_name = &args[2];
+    run_useful_tool(tool_name, &args[3..])?;
+    println!("工具执行完成");
+    Ok(())
+}
+
+fn handle_integrity_check(args: &[String]) -> AppResult {
+    if args.len() < 4 {
+        return Err("File path and expected hash value are required".into());
+    }
+    let file_path = &args[2];
+    let expected_hash = &args[3];
+    integrity_check(file_path, expected_hash)
+}
+
+fn print_help() {
+    eprintln!("rshy - EHoRY Cli");
+    eprintln!();
+    eprintln!("Usage: rshy <Arguments> <Extra arguments>");
+    eprintln!();
+    eprintln!("Arguments:");
+    eprintln!("  hidemyapplist");
+    eprintln!("  recoverapplist");
+    eprintln!("  hma <list> / <merge [template_file]> / <scope [package...] [--template <name>]> / <validate [config_file]> / <backups> / <restore <id>>");
+    eprintln!("  lsplog");
+    eprintln!("  magisklog");
+    eprintln!("  shamiko_pattern");
+    eprintln!("  updatetarget");
+    eprintln!("  tricky target <list> / <add <package...> [--mode <auto|generate|leaf>]> / <remove <package...>>");
+    eprintln!("                / <mode <package> <auto|generate|leaf>> / <sync [--mode <mode>] [--include <pattern>] [--exclude <pattern>] [--user|--system]>");
+    eprintln!("  tricky keybox <check [file]> / <install <file> [--force]> / <backup> [--revocation <json>]");
+    eprintln!("  tricky patch [--date <YYYY-MM-DD>] [--dry-run]");
+    eprintln!("  tricky validate");
+    eprintln!("  vbmeta audit");
+    eprintln!("  partition <info <name>> / <backup <name>> / <write <name> <image> [--force]> / <backups> / <restore <backup>>");
+    eprintln!("            [--slot <_a|_b>] [--by-name <dir>] [--backup-dir <dir>]");
+    eprintln!("  kpm <list> / <load <file.kpm> [--args <args>]> / <unload <name>> / <catalog [--catalog <file>]> [--superkey-file <file>]");
+    eprintln!("      <embed [--nohello] [--module <file.kpm>...] [--image <boot.img>] [--catalog <file>]> [--slot <_a|_b>] [--by-name <dir>] [--backup-dir <dir>]");
+    eprintln!("  assets <list> / <extract <dir> [name...]>");
+    eprintln!("         内置工具目前只提供 arm64-v8a 版本");
+    eprintln!("  bootimg <info <image>> / <unpack <image> [out_dir]> / <repack <image> <output> [--kernel <file>]> [--magiskboot <path>]");
+    eprintln!("  awjclean");
+    eprintln!("  rurudelete");
+    eprintln!("  aptroot");
+    eprintln!("  initrc");
+    eprintln!("  momo [Extra <tee> / <systemmount> / <development> / <sdk> / <addon> ]");
+    eprintln!("  nativetest [Extra <futile10> / <boothash> ]");
+    eprintln!("  holmes [Extra <somethingwrong> / <9ff> ]");
+    eprintln!("  hunter [Extra <shizuku> / <manager> ]");
+    eprintln!("  nativedetector [Extra <vbmeta> / <magicmount> / <lsp5> ]");
+    eprintln!("  find-app <hma|lsposed|magisk|apatch|kernelsu|shizuku|all> [--signatures <file>]");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  -h, --help");
+    eprintln!("  -V, --version");
+    eprintln!("  -i, --integritycheck <file_path> <expected_hash>");
+    eprintln!("  -d, --delete <file|dir|files_in_dir> <path>");
+    eprintln!("  -o, --download <URL> [save_path] [expected_hash] [--no-cdn] [--limit-rate <rate>] [--metered-threshold <size>]");
+    eprintln!("      URL: http(s)://... / github://owner/repo/path@ref / lanzou://<share_key> / file://<path>");
+    eprintln!("  -t, --tools <tool_name>  (仅 arm64-v8a)");
+    eprintln!("  --color");
+    eprintln!("  --yiyan");
+    eprintln!("  --zygiskcheck");
+    eprintln!("  --update");
+    eprintln!("  --cleanmodules");
+    eprintln!();
+    eprintln!("Global options (before arguments):");
+    eprintln!("  -y, --yes");
+    eprintln!("  --no-input");
+    eprintln!("  --answers <preset_file>");
+    eprintln!();
+    eprintln!("Exit status: 0 成功 / 1 失败 / 2 在确认提示中选择退出");
+}
+
+// 当前 UTC 时间，格式为 YYYYMMDD-HHMMSS
+fn utc_timestamp() -> String {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+fn compute_sha256(file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut file = File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 4096];
+    
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    
+    let result = hasher.finalize();
+    Ok(format!("{:x}", result))
+}
+
+async fn handle_update() -> AppResult {
+    let url = "https://textdb.online/y13140".to_string();
+    let temp_file = std::env::temp_dir().join("y13140");
+    download_small_file_silent(&url, &temp_file).await?;
+    let content = fs::read_to_string(&temp_file)?;
+    let _ = fs::remove_file(&temp_file);
+
+    let version = content.trim().replace(|c: char| c.is_whitespace(), "");
+    println!("{}", version);
+    
+    Ok(())
+}
+
+async fn download_small_file_silent(url: &str, file_path: &std::path::Path) -> AppResult {
+    let client = Client::new();
+
+    let response = client.get(url)
+        .send()
+        .await
+        .map_err(|e| format!("下载失败: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("HTTP错误: {}", response.status()).into());
+    }
+
+    let content = response.bytes()
+        .await
+        .map_err(|e| format!("读取响应失败: {}", e))?;
+
+    let mut file = File::create(file_path)
+        .map_err(|e| format!("创建文件失败: {}", e))?;
+    
+    file.write_all(&content)
+        .map_err(|e| format!("写入文件失败: {}", e))?;
+    
+    Ok(())
+}
+
+fn integrity_check(file_path: &str, expected_hash: &str) -> AppResult {
+    if !Path::new(file_path).exists() {
+        return Err("未检测到文件，请确认是否下载完毕".into());
+    }
+
+    let computed_hash = compute_sha256(file_path)?;
+    if computed_hash == expected_hash {
+        println!("sha256完整性校验通过");
+        Ok(())
+    } else {
+        eprintln!("sha256完整性校验未通过");
+        eprintln!("请检查文件是否已经下载100%");
+        eprintln!("如果未发现其他问题，请私信@yu13140报告此错误");
+        std::process::exit(1);
+    }
+}
+
+fn check_zygisk() -> Result<(), Box<dyn std::error::Error>> {
+    let db_path = "/data/adb/magisk.db";
+
+    if !Path::new(db_path).exists() {
+        println!("Magisk 数据库文件未找到");
+        return Ok(());
+    }
+
+    let conn = match Connection::open(db_path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("无法打开 Magisk 数据库: {}", e);
+            return Ok(());
+        }
+    };
+
+    let mut stmt = match conn.prepare("SELECT value FROM settings WHERE key='zygisk'") {
+        Ok(stmt) => stmt,
+        Err(e) => {
+            println!("无法准备 SQL 查询: {}", e);
+            return Ok(());
+        }
+    };
+    
+    let current_state: Result<i32, rusqlite::Error> = stmt.query_row([], |row| row.get(0));
+    
+    match current_state {
+        Ok(state) => {
+            println!("{}", state);
+        },
+        Err(e) => {
+            println!("无法查询 Zygisk 状态: {}", e);
+        }
+    }
+    
+    Ok(())
+}
+
+fn show_startup_animation() {
+    print!("\x1B[?25l");
+    io::stdout().flush().unwrap();
+
+    print!("\x1B[2J\x1B[H");
+    io::stdout().flush().unwrap();
+
+    let title_lines = [
+        ".########.##.....##..#######..########..##....##...",
+        ".##.......##.....##.##.....##.##.....##..##..##....",
+        ".##.......##.....##.##.....##.##.....##...####.....",
+        ".######...#########.##.....##.########.....##......",
+        ".##.......##.....##.##.....##.##...##......##......",
+        ".##.......##.....##.##.....##.##....##.....##......",
+        ".########.##.....##..#######..##.....##....##......",
+    ];
+
+    let colors = [196, 202, 208, 214, 220, 226, 190, 154, 118, 82, 46, 47, 48, 49, 50, 51, 45, 39, 33, 27, 21];
+    let progress_width = 20;
+    let increment = 2;
+
+    let total_cols = title_lines[0].len();
+
+    println!("\n\n");
+
+    for _ in 0..7 {
+        for _ in 0..total_cols {
+            print!(" ");
+        }
+        println!();
+    }
+
+    fn print_title_by_column(title_lines: &[&str], percentage: u32, colors: &[u32]) {
+        let total_cols = title_lines[0].len();
+        let cols_to_show = (total_cols as u32 * percentage / 100) as usize;
+        
+        let mut output = String::new();
         for col in 0..total_cols {
             if col < cols_to_show {
                 for row in 0..7 {
@@ -711,7 +2018,15 @@ async fn test_single_node(
     let temp_dir = std::env::temp_dir();
     let temp_file = temp_dir.join("cdn_speed_test.tmp");
 
-    let speed_result = download_with_progress(client, cdn_url, Some(temp_file.clone()), None, true).await;
+    let speed_result = download_with_progress(
+        client,
+        cdn_url,
+        &[CdnNode { name: node_name.to_string(), url: cdn_url.to_string() }],
+        Some(temp_file.clone()),
+        true,
+        None,
+        None,
+    ).await;
     
     let speed = match speed_result {
         Ok((file_path, speed_value)) => {
@@ -755,383 +2070,641 @@ async fn test_single_node(
     })
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DownloadMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    expected_size: Option<u64>,
+    expected_hash: Option<String>,
+}
+
+fn download_part_paths(file_path: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let part_path = std::path::PathBuf::from(format!("{}.part", file_path.display()));
+    let meta_path = std::path::PathBuf::from(format!("{}.part.meta", file_path.display()));
+    (part_path, meta_path)
+}
+
+fn load_download_meta(meta_path: &Path) -> Option<DownloadMeta> {
+    let content = fs::read_to_string(meta_path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_download_meta(meta_path: &Path, meta: &DownloadMeta) -> AppResult {
+    fs::write(meta_path, serde_json::to_string(meta)?)?;
+    Ok(())
+}
+
+fn header_value(res: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    res.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn response_total_size(res: &reqwest::Response, downloaded: u64) -> Option<u64> {
+    let range_total = header_value(res, reqwest::header::CONTENT_RANGE)
+        .and_then(|range| range.rsplit('/').next().and_then(|s| s.parse::<u64>().ok()));
+    if res.status() == reqwest::StatusCode::PARTIAL_CONTENT && range_total.is_some() {
+        return range_total;
+    }
+
+    header_value(res, reqwest::header::CONTENT_LENGTH)
+        .and_then(|len| len.parse::<u64>().ok())
+        .map(|len| if res.status() == reqwest::StatusCode::PARTIAL_CONTENT { len + downloaded } else { len })
+}
+
+fn resume_accepted(res: &reqwest::Response, meta: &DownloadMeta, downloaded: u64) -> bool {
+    if res.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return false;
+    }
+
+    let etag = header_value(res, reqwest::header::ETAG);
+    let last_modified = header_value(res, reqwest::header::LAST_MODIFIED);
+
+    let validators_match = match (&meta.etag, &etag) {
+        (Some(old), Some(new)) => old == new,
+        _ => matches!((&meta.last_modified, &last_modified), (Some(old), Some(new)) if old == new),
+    };
+
+    let size_matches = match meta.expected_size {
+        Some(size) => response_total_size(res, downloaded) == Some(size),
+        None => true,
+    };
+
+    validators_match && size_matches
+}
+
+fn pb_println(pb: Option<&ProgressBar>, message: &str) {
+    match pb {
+        Some(pb) => pb.suspend(|| println!("{}", message)),
+        None => println!("{}", message),
+    }
+}
+
+async fn open_download_stream(
+    client: &Client,
+    url: &str,
+    meta: &mut DownloadMeta,
+    part_path: &Path,
+    meta_path: &Path,
+    downloaded: &mut u64,
+    pb: Option<&ProgressBar>,
+) -> AppResult<(reqwest::Response, File)> {
+    if *downloaded > 0 {
+        let mut request = client.get(url).header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
+        if let Some(validator) = meta.etag.as_ref().or(meta.last_modified.as_ref()) {
+            request = request.header(reqwest::header::IF_RANGE, validator);
+        }
+        pb_println(pb, &format!("尝试断点续传，从字节 {} 开始", downloaded));
+
+        let res = request.send().await?;
+        if resume_accepted(&res, meta, *downloaded) {
+            pb_println(pb, "服务器文件未变化，继续下载 (状态码 206)");
+            let file = std::fs::OpenOptions::new().append(true).open(part_path)?;
+            return Ok((res, file));
+        }
+
+        pb_println(pb, &format!("无法校验服务器文件是否一致 (状态码: {}), 重新开始下载", res.status()));
+        *downloaded = 0;
+
+        if res.status().is_success() && res.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            return start_fresh_download(res, meta, part_path, meta_path);
+        }
+    }
+
+    let res = client.get(url).send().await?;
+    if !res.status().is_success() {
+        return Err(format!("HTTP错误: {}", res.status()).into());
+    }
+    start_fresh_download(res, meta, part_path, meta_path)
+}
+
+fn start_fresh_download(
+    res: reqwest::Response,
+    meta: &mut DownloadMeta,
+    part_path: &Path,
+    meta_path: &Path,
+) -> AppResult<(reqwest::Response, File)> {
+    meta.etag = header_value(&res, reqwest::header::ETAG);
+    meta.last_modified = header_value(&res, reqwest::header::LAST_MODIFIED);
+    meta.expected_size = response_total_size(&res, 0);
+    save_download_meta(meta_path, meta)?;
+
+    let file = File::create(part_path)?;
+    Ok((res, file))
+}
+
 async fn download_with_progress(
     client: &Client,
     url: &str,
+    mirrors: &[CdnNode],
     save_path: Option<std::path::PathBuf>,
-    max_size: Option<usize>,
     silent: bool,
+    expected_hash: Option<&str>,
+    rate_limit: Option<u64>,
 ) -> Result<(String, f64), Box<dyn std::error::Error>> {
+    if mirrors.is_empty() {
+        return Err("没有可用的下载节点".into());
+    }
+
     let file_path = save_path.unwrap_or_else(|| {
-        let file_name = url.split('/').last().unwrap_or("download");
+        let file_name = url.split('/').next_back().unwrap_or("download");
         std::path::PathBuf::from(file_name)
     });
+    let (part_path, meta_path) = download_part_paths(&file_path);
 
-    let mut downloaded: u64 = 0;
-    let mut file = if file_path.exists() {
-        let metadata = fs::metadata(&file_path)?;
-        downloaded = metadata.len();
-        println!("发现已下载部分文件: {} bytes", downloaded);
-        std::fs::OpenOptions::new().append(true).open(&file_path)?
-    } else {
-        File::create(&file_path)?
-    };
-
-    let mut request = client.get(url);
-    if downloaded > 0 {
-        request = request.header("Range", format!("bytes={}-", downloaded));
-        println!("尝试断点续传，从字节 {} 开始", downloaded);
-    }
-
-    let res = match request.send().await {
-        Ok(res) => res,
-        Err(e) => {
-            eprintln!("请求失败: {}", e);
-            return Err(Box::new(e));
+    let mut meta = match load_download_meta(&meta_path) {
+        Some(meta) if part_path.exists()
+            && meta.url == url
+            && meta.expected_hash.as_deref() == expected_hash => meta,
+        _ => {
+            if part_path.exists() {
+                println!("发现来源不一致的部分下载文件，将重新下载");
+                let _ = fs::remove_file(&part_path);
+            }
+            let _ = fs::remove_file(&meta_path);
+            DownloadMeta {
+                url: url.to_string(),
+                expected_hash: expected_hash.map(|hash| hash.to_string()),
+                ..Default::default()
+            }
         }
     };
 
-    let (total_size, supports_resume) = if downloaded > 0 && res.status() == 206 {
-        println!("服务器支持断点续传 (状态码 206)");
-        let content_range = res
-            .headers()
-            .get(reqwest::header::CONTENT_RANGE)
-            .and_then(|ct_range| ct_range.to_str().ok());
-        
-        let total = if let Some(range) = content_range {
-            match range.split('/').last().and_then(|s| s.parse::<u64>().ok()) {
-                Some(t) => {
-                    println!("从Content-Range获取总大小: {}", t);
-                    t
-                }
-                None => {
-                    println!("无法从Content-Range解析总大小");
-                    res.headers()
-                        .get(reqwest::header::CONTENT_LENGTH)
-                        .and_then(|ct_len| ct_len.to_str().ok())
-                        .and_then(|ct_len| ct_len.parse::<u64>().ok())
-                        .unwrap_or(0) + downloaded
-                }
-            }
-        } else {
-            println!("没有Content-Range头，使用Content-Length");
-            res.headers()
-                .get(reqwest::header::CONTENT_LENGTH)
-                .and_then(|ct_len| ct_len.to_str().ok())
-                .and_then(|ct_len| ct_len.parse::<u64>().ok())
-                .unwrap_or(0) + downloaded
-        };
-        
-        (total, true)
-    } else if downloaded > 0 {
-        println!("服务器不支持断点续传 (状态码: {}), 重新开始下载", res.status());
-        downloaded = 0;
-        file = File::create(&file_path)?;
-
-        let res = client.get(url).send().await?;
-        let total = res
-            .headers()
-            .get(reqwest::header::CONTENT_LENGTH)
-            .and_then(|ct_len| ct_len.to_str().ok())
-            .and_then(|ct_len| ct_len.parse::<u64>().ok())
-            .unwrap_or(0);
-        
-        (total, false)
+    let mut downloaded: u64 = if part_path.exists() {
+        fs::metadata(&part_path)?.len()
     } else {
-        let total = res
-            .headers()
-            .get(reqwest::header::CONTENT_LENGTH)
-            .and_then(|ct_len| ct_len.to_str().ok())
-            .and_then(|ct_len| ct_len.parse::<u64>().ok())
-            .unwrap_or(0);
-        
-        (total, false)
+        0
     };
+    if downloaded > 0 {
+        println!("发现已下载部分文件: {} bytes", downloaded);
+    }
 
     let pb = if !silent {
-        let pb = ProgressBar::new(total_size);
+        let pb = ProgressBar::new(0);
         pb.set_style(ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
             .unwrap()
             .progress_chars("#>-"));
-        pb.set_position(downloaded);
         Some(pb)
     } else {
         None
     };
 
-    let mut stream = res.bytes_stream();
+    const MAX_RETRIES_PER_NODE: u32 = 3;
+    let mut node_index = 0;
+    let mut node_retries = vec![0u32; mirrors.len()];
+
+    if !silent {
+        pb_println(pb.as_ref(), &format!("使用节点: {}", mirrors[node_index].name));
+    }
+    let mut stream = None;
+    let mut file = None;
+    match open_download_stream(
+        client, &mirrors[node_index].url, &mut meta, &part_path, &meta_path, &mut downloaded, pb.as_ref(),
+    ).await {
+        Ok((res, opened)) => {
+            stream = Some(res.bytes_stream());
+            file = Some(opened);
+        }
+        Err(e) => {
+            eprintln!("请求失败: {}", e);
+        }
+    }
+
+    if let Some(ref pb) = pb {
+        pb.set_length(meta.expected_size.unwrap_or(0));
+        pb.set_position(downloaded);
+    }
+
     let start_time = std::time::Instant::now();
-    let mut retry_count = 0;
-    const MAX_RETRIES: u32 = 10;
+    let started_at = downloaded;
     let mut last_speed_check = std::time::Instant::now();
     let mut downloaded_since_last_check = 0;
-    let mut estimated_total_time = 0.0;
+    let mut limiter = rate_limit.map(RateLimiter::new);
 
-    while retry_count < MAX_RETRIES {
-        let mut attempt_failed = false;
-        
-        while let Some(item) = stream.next().await {
-            match item {
-                Ok(chunk) => {
-                    if let Err(e) = file.write_all(&chunk) {
-                        eprintln!("写入文件失败: {}", e);
+    if let Some(limit) = rate_limit {
+        pb_println(pb.as_ref(), &format!("已限制下载速度: {:.2} KB/s", limit as f64 / 1024.0));
+    }
+
+    loop {
+        let total_size = meta.expected_size.unwrap_or(0);
+        let mut attempt_failed = stream.is_none();
+        let mut too_slow = false;
+
+        if let (Some(stream), Some(file)) = (stream.as_mut(), file.as_mut()) {
+            loop {
+                let item = match timeout(Duration::from_secs(30), stream.next()).await {
+                    Ok(Some(item)) => item,
+                    Ok(None) => break,
+                    Err(_) => {
+                        pb_println(pb.as_ref(), "下载数据超时");
                         attempt_failed = true;
                         break;
                     }
-                    
-                    let new = downloaded + (chunk.len() as u64);
-                    downloaded = new;
-                    downloaded_since_last_check += chunk.len() as u64;
+                };
 
-                    if let Some(ref pb) = pb {
-                        pb.set_position(downloaded);
-                    }
+                match item {
+                    Ok(chunk) => {
+                        if let Err(e) = file.write_all(&chunk) {
+                            eprintln!("写入文件失败: {}", e);
+                            attempt_failed = true;
+                            break;
+                        }
 
-                    let now = std::time::Instant::now();
-                    if now.duration_since(last_speed_check).as_secs() >= 5 {
-                        let time_elapsed = now.duration_since(last_speed_check).as_secs_f64();
-                        let current_speed = downloaded_since_last_check as f64 / time_elapsed;
-                        
-                        if current_speed > 0.0 && total_size > downloaded {
-                            let remaining_bytes = total_size - downloaded;
-                            estimated_total_time = remaining_bytes as f64 / current_speed;
-                            
-                            if let Some(ref pb) = pb {
-                                pb.set_message(format!("速度: {:.2} KB/s, 剩余: {:.0}秒", 
-                                    current_speed / 1024.0, estimated_total_time));
-                            }
+                        downloaded += chunk.len() as u64;
+                        downloaded_since_last_check += chunk.len() as u64;
+
+                        if let Some(limiter) = limiter.as_mut() {
+                            limiter.throttle(chunk.len() as u64).await;
+                        }
+
+                        if let Some(ref pb) = pb {
+                            pb.set_position(downloaded);
+                        }
+
+                        let now = std::time::Instant::now();
+                        if now.duration_since(last_speed_check).as_secs() >= 5 {
+                            let time_elapsed = now.duration_since(last_speed_check).as_secs_f64();
+                            let current_speed = downloaded_since_last_check as f64 / time_elapsed;
+
+                            if current_speed > 0.0 && total_size > downloaded {
+                                let remaining_bytes = total_size - downloaded;
+                                let estimated_total_time = remaining_bytes as f64 / current_speed;
 
-                            if estimated_total_time > 600.0 {
                                 if let Some(ref pb) = pb {
-                                    pb.suspend(|| {
-                                        println!("预计下载时间超过10分钟，将重新测试CDN节点");
-                                    });
-                                } else {
-                                    println!("预计下载时间超过10分钟，将重新测试CDN节点");
+                                    pb.set_message(format!("速度: {:.2} KB/s, 剩余: {:.0}秒",
+                                        current_speed / 1024.0, estimated_total_time));
+                                }
+
+                                if estimated_total_time > 600.0
+                                    && rate_limit.is_none()
+                                    && node_index + 1 < mirrors.len()
+                                {
+                                    pb_println(pb.as_ref(), "预计下载时间超过10分钟，将切换到下一个节点");
+                                    attempt_failed = true;
+                                    too_slow = true;
+                                    break;
                                 }
-                                attempt_failed = true;
-                                break;
                             }
-                        }
-                        
-                        last_speed_check = now;
-                        downloaded_since_last_check = 0;
-                    }
 
-                    if let Some(max) = max_size {
-                        if downloaded >= max as u64 {
-                            break;
+                            last_speed_check = now;
+                            downloaded_since_last_check = 0;
                         }
                     }
-                }
-                Err(e) => {
-                    eprintln!("下载数据出错: {}", e);
-                    attempt_failed = true;
-                    break;
+                    Err(e) => {
+                        eprintln!("下载数据出错: {}", e);
+                        attempt_failed = true;
+                        break;
+                    }
                 }
             }
         }
 
-        if downloaded >= total_size {
+        if !attempt_failed || (total_size > 0 && downloaded >= total_size) {
             break;
         }
-        
-        if !attempt_failed {
-            break;
+
+        let failed_node = node_index;
+        if too_slow {
+            node_index += 1;
+        } else {
+            node_retries[node_index] += 1;
+            if node_retries[node_index] > MAX_RETRIES_PER_NODE {
+                node_index += 1;
+            }
         }
 
-        if estimated_total_time > 600.0 {
+        if node_index >= mirrors.len() {
             return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::TimedOut,
-                "下载速度过慢，预计完成时间超过10分钟",
+                std::io::ErrorKind::ConnectionAborted,
+                format!("所有 {} 个节点均多次重试失败，无法完成下载", mirrors.len()),
             )));
         }
-        
-        retry_count += 1;
+
+        if node_index != failed_node {
+            pb_println(pb.as_ref(), &format!(
+                "节点 {} 下载失败，切换到节点 {} 继续下载",
+                mirrors[failed_node].name, mirrors[node_index].name
+            ));
+        } else {
+            pb_println(pb.as_ref(), &format!(
+                "网络连接出现问题，正在尝试重新连接... (节点 {}, 尝试 {}/{})",
+                mirrors[node_index].name, node_retries[node_index], MAX_RETRIES_PER_NODE
+            ));
+            tokio::time::sleep(Duration::from_secs((2 * node_retries[node_index]) as u64)).await;
+        }
         if let Some(ref pb) = pb {
-            pb.suspend(|| {
-                println!("网络连接出现问题，正在尝试重新连接... (尝试 {}/{})", retry_count, MAX_RETRIES);
-            });
             pb.set_message("重新连接中...");
-        } else {
-            println!("网络连接出现问题，正在尝试重新连接... (尝试 {}/{})", retry_count, MAX_RETRIES);
         }
 
-        tokio::time::sleep(Duration::from_secs((2 * retry_count) as u64)).await;
-
-        file = std::fs::OpenOptions::new().append(true).open(&file_path)?;
+        if let Some(mut old_file) = file.take() {
+            old_file.flush()?;
+        }
+        stream = None;
+        downloaded = if part_path.exists() { fs::metadata(&part_path)?.len() } else { 0 };
 
-        let current_size = fs::metadata(&file_path)?.len();
-        downloaded = current_size;
+        match open_download_stream(
+            client, &mirrors[node_index].url, &mut meta, &part_path, &meta_path, &mut downloaded, pb.as_ref(),
+        ).await {
+            Ok((new_res, new_file)) => {
+                file = Some(new_file);
+                stream = Some(new_res.bytes_stream());
 
-        let mut request = client.get(url);
-        if supports_resume {
-            request = request.header("Range", format!("bytes={}-", downloaded));
-            if let Some(ref pb) = pb {
-                pb.suspend(|| {
-                    println!("尝试从字节 {} 继续下载", downloaded);
-                });
-            } else {
-                println!("尝试从字节 {} 继续下载", downloaded);
-            }
-        } else {
-            if let Some(ref pb) = pb {
-                pb.suspend(|| {
-                    println!("服务器不支持断点续传，重新开始下载");
-                });
-            } else {
-                println!("服务器不支持断点续传，重新开始下载");
-            }
-            downloaded = 0;
-            file = File::create(&file_path)?;
-        }
-        
-        match request.send().await {
-            Ok(new_res) => {
-                stream = new_res.bytes_stream();
-                
                 if let Some(ref pb) = pb {
                     pb.set_message("下载中");
+                    pb.set_length(meta.expected_size.unwrap_or(0));
                     pb.set_position(downloaded);
                 }
             }
             Err(e) => {
                 eprintln!("重新连接失败: {}", e);
-                if retry_count == MAX_RETRIES {
-                    return Err(Box::new(std::io::Error::new(
-                        std::io::ErrorKind::ConnectionAborted,
-                        "多次重试后仍无法恢复下载",
-                    )));
-                }
             }
         }
     }
 
-    if retry_count == MAX_RETRIES {
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::ConnectionAborted,
-            "多次重试后仍无法完成下载",
-        )));
+    if let Some(mut file) = file.take() {
+        file.flush()?;
+    }
+
+    let actual_size = fs::metadata(&part_path)?.len();
+    match meta.expected_size {
+        Some(expected_size) if actual_size != expected_size => {
+            return Err(format!("文件大小不正确: 期望 {} 字节, 实际 {} 字节", expected_size, actual_size).into());
+        }
+        _ => {}
+    }
+
+    if let Some(expected_hash) = expected_hash {
+        let computed_hash = compute_sha256(&part_path.to_string_lossy())?;
+        if computed_hash != expected_hash {
+            let _ = fs::remove_file(&part_path);
+            let _ = fs::remove_file(&meta_path);
+            eprintln!("sha256完整性校验未通过");
+            return Err("sha256完整性校验未通过".into());
+        }
+        if !silent {
+            println!("sha256完整性校验通过");
+        }
+    }
+
+    fs::rename(&part_path, &file_path)?;
+    let _ = fs::remove_file(&meta_path);
+
+    if let Some(pb) = pb {
+        pb.finish_with_message("下载完成");
+    }
+
+    let elapsed = start_time.elapsed().as_secs_f64();
+    let speed = if elapsed > 0.0 {
+        (downloaded.saturating_sub(started_at) as f64 / 1024.0 / 1024.0) / elapsed
+    } else {
+        0.0
+    };
+    
+    if !silent {
+        println!("下载速度: {:.2}MB/s", speed);
+    }
+    
+    Ok((file_path.to_string_lossy().into_owned(), speed))
+}
+
+async fn download_file(
+    url: String,
+    mut use_cdn: bool,
+    save_path: Option<std::path::PathBuf>,
+    expected_hash: Option<String>,
+    limits: &DownloadLimits,
+) -> AppResult<String> {
+    let source = source::DownloadSource::parse(&url)?;
+
+    if let source::DownloadSource::File { path } = &source {
+        return copy_local_file(path, save_path, expected_hash.as_deref());
+    }
+
+    if !source.uses_github_cdn() {
+        use_cdn = false;
+    } else if use_cdn && is_vpn_active() {
+        let keep_cdn = prompt::confirm(
+            "vpn_use_cdn",
+            "检测到VPN可能已被开启，是否继续使用CDN加速？(y/N): ",
+            |input| input.eq_ignore_ascii_case("y"),
+        )?;
+        
+        if keep_cdn {
+            println!("将继续使用CDN加速");
+        } else {
+            println!("将不使用CDN加速");
+            use_cdn = false;
+        }
+    }
+
+    let client = Client::new();
+
+    let cdn_nodes: Vec<CdnNode> = if use_cdn {
+        test_cdn_speed(get_cdn_nodes()).await
+            .iter()
+            .filter(|r| r.success)
+            .map(|r| CdnNode {
+                name: r.node.name.clone(),
+                url: r.node.url
+                    .replace("https://github.com/yu13140/yuhideroot/raw/refs/heads/main/check.sh", ""),
+            })
+            .collect()
+    } else {
+        if source.uses_github_cdn() {
+            println!("不使用 CDN 加速，直接下载");
+        }
+        Vec::new()
+    };
+    let mirrors = source.mirrors(&cdn_nodes);
+
+    if detect_metered_network() == Some(true) {
+        confirm_metered_download(&client, &mirrors[0].url, limits.metered_threshold).await?;
+    }
+
+    println!("开始下载文件... (可用节点 {} 个)", mirrors.len());
+
+    match download_with_progress(
+        &client, &source.canonical_url(), &mirrors, save_path, false, expected_hash.as_deref(), limits.rate,
+    ).await {
+        Ok((file_path, _)) => Ok(file_path),
+        Err(e) => {
+            println!("下载失败: {}", e);
+            Err(format!("下载失败，使用 {} 个节点后仍无法完成", mirrors.len()).into())
+        }
+    }
+}
+
+fn copy_local_file(
+    source_path: &Path,
+    save_path: Option<std::path::PathBuf>,
+    expected_hash: Option<&str>,
+) -> AppResult<String> {
+    let file_path = match save_path {
+        Some(path) => path,
+        None => match source_path.file_name() {
+            Some(name) => std::path::PathBuf::from(name),
+            None => return Err(format!("无效的本地文件: {}", source_path.display()).into()),
+        },
+    };
+    let (part_path, _) = download_part_paths(&file_path);
+
+    println!("从本地文件复制: {}", source_path.display());
+    if let Err(e) = fs::copy(source_path, &part_path) {
+        eprintln!("复制本地文件失败: {}", e);
+        return Err(e.into());
+    }
+
+    if let Some(expected_hash) = expected_hash {
+        if compute_sha256(&part_path.to_string_lossy())? != expected_hash {
+            let _ = fs::remove_file(&part_path);
+            eprintln!("sha256完整性校验未通过");
+            return Err("sha256完整性校验未通过".into());
+        }
+        println!("sha256完整性校验通过");
+    }
+
+    fs::rename(&part_path, &file_path)?;
+    Ok(file_path.to_string_lossy().into_owned())
+}
+
+#[derive(Debug, Clone)]
+struct DownloadLimits {
+    rate: Option<u64>,
+    metered_threshold: u64,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        DownloadLimits {
+            rate: None,
+            metered_threshold: 50 * 1024 * 1024,
+        }
+    }
+}
+
+struct RateLimiter {
+    limit: u64,
+    started: std::time::Instant,
+    consumed: u64,
+}
+
+impl RateLimiter {
+    fn new(limit: u64) -> Self {
+        RateLimiter {
+            limit,
+            started: std::time::Instant::now(),
+            consumed: 0,
+        }
     }
 
-    if let Some(pb) = pb {
-        pb.finish_with_message("下载完成");
+    async fn throttle(&mut self, bytes: u64) {
+        self.consumed += bytes;
+        let expected = Duration::from_secs_f64(self.consumed as f64 / self.limit as f64);
+        let elapsed = self.started.elapsed();
+        if expected > elapsed {
+            tokio::time::sleep(expected - elapsed).await;
+        }
+
+        // 每秒重新计时，避免长时间空闲后突发
+        if self.started.elapsed() >= Duration::from_secs(1) {
+            self.started = std::time::Instant::now();
+            self.consumed = 0;
+        }
     }
+}
 
-    let elapsed = start_time.elapsed().as_secs_f64();
-    let speed = if elapsed > 0.0 {
-        (downloaded as f64 / 1024.0 / 1024.0) / elapsed
-    } else {
-        0.0
+async fn confirm_metered_download(client: &Client, url: &str, threshold: u64) -> AppResult {
+    let size = match timeout(Duration::from_secs(10), client.head(url).send()).await {
+        Ok(Ok(res)) if res.status().is_success() => res.content_length(),
+        _ => None,
     };
-    
-    if !silent {
-        println!("下载速度: {:.2}MB/s", speed);
+
+    match size {
+        Some(size) if size > threshold => {
+            let question = format!(
+                "当前处于按流量计费的网络，文件大小 {:.2} MB，是否继续下载？(y/N): ",
+                size as f64 / 1024.0 / 1024.0
+            );
+            if prompt::confirm("metered_download", &question, |input| input.eq_ignore_ascii_case("y"))? {
+                Ok(())
+            } else {
+                println!("已取消下载");
+                Err("已取消在计费网络下的下载".into())
+            }
+        }
+        Some(_) => Ok(()),
+        None => {
+            println!("当前处于按流量计费的网络，无法获取文件大小，请注意流量消耗");
+            Ok(())
+        }
     }
-    
-    Ok((file_path.to_string_lossy().into_owned(), speed))
 }
 
-async fn download_file(
-    url: String,
-    mut use_cdn: bool,
-    save_path: Option<std::path::PathBuf>,
-    expected_hash: Option<String>,
-) -> AppResult<String> {
-    let vpn_detected = is_vpn_active();
-    
-    if vpn_detected {
-        println!("检测到VPN可能已被开启，是否继续使用CDN加速？(y/N): ");
-        io::stdout().flush()?;
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        
-        if input.trim().eq_ignore_ascii_case("y") {
-            println!("将继续使用CDN加速");
-        } else {
-            println!("将不使用CDN加速");
-            use_cdn = false;
+fn detect_metered_network() -> Option<bool> {
+    let dump = match Command::new("dumpsys").arg("connectivity").output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout).into_owned(),
+        _ => String::new(),
+    };
+
+    if let Some(metered) = parse_connectivity_dump(&dump) {
+        if metered {
+            println!("检测到当前网络为移动数据(按流量计费)");
         }
+        return Some(metered);
     }
 
-    let client = Client::new();
-    let mut retry_count = 0;
-    const MAX_RETRIES: u32 = 5;
-
-    if !use_cdn {
-        println!("不使用 CDN 加速，直接下载");
-        let (file_path, _) = timeout(
-            Duration::from_secs(600),
-            download_with_progress(&client, &url, save_path.clone(), None, false)
-        ).await??;
-        
-        if let Some(expected_hash) = expected_hash {
-            integrity_check(&file_path, &expected_hash)?;
-        }
-        return Ok(file_path);
+    let metered = detect_metered_from_sysfs("/sys/class/net");
+    if metered == Some(true) {
+        println!("检测到移动数据网络接口处于活动状态");
     }
+    metered
+}
 
-    let cdn_results = test_cdn_speed(get_cdn_nodes()).await;
-    let mut cdn_nodes: Vec<CdnNode> = cdn_results.iter()
-        .filter(|r| r.success)
-        .map(|r| r.node.clone())
-        .collect();
-    
-    cdn_nodes.push(CdnNode {
-        name: "原始URL".to_string(),
-        url: "".to_string(),
-    });
+fn parse_connectivity_dump(dump: &str) -> Option<bool> {
+    let network_id = dump.lines()
+        .find_map(|line| line.trim().strip_prefix("Active default network:"))
+        .map(|id| id.trim().to_string())?;
 
-    for (i, node) in cdn_nodes.iter().enumerate() {
-        if retry_count >= MAX_RETRIES {
-            break;
-        }
+    let new_format = format!("network{{{}}}", network_id);
+    let old_format = format!("- {}]", network_id);
 
-        println!("开始下载文件... (尝试 {}/{}, 使用节点: {})", 
-                 retry_count + 1, MAX_RETRIES, node.name);
+    let agent_line = dump.lines().find(|line| {
+        line.contains("NetworkAgentInfo") && (line.contains(&new_format) || line.contains(&old_format))
+    })?;
 
-        let final_url = if node.url.is_empty() {
-            url.clone()
-        } else {
-            let cdn_base_url = node.url
-                .replace("https://github.com/yu13140/yuhideroot/raw/refs/heads/main/check.sh", "");
-            format!("{}{}", cdn_base_url, url)
-        };
+    if agent_line.contains("NOT_METERED") {
+        Some(false)
+    } else if agent_line.contains("Capabilities:") || agent_line.contains("CELLULAR") || agent_line.contains("MOBILE") {
+        Some(true)
+    } else {
+        None
+    }
+}
 
-        match timeout(
-            Duration::from_secs(600),
-            download_with_progress(&client, &final_url, save_path.clone(), None, false)
-        ).await {
-            Ok(Ok((file_path, _))) => {
-                if let Some(expected_hash) = &expected_hash {
-                    integrity_check(&file_path, expected_hash)?;
-                }
-                return Ok(file_path);
-            },
-            Ok(Err(e)) => {
-                println!("下载失败: {}", e);
-                if i < cdn_nodes.len() - 1 {
-                    println!("CDN 下载失败，尝试使用下一个节点");
-                    continue;
-                } else {
-                    retry_count += 1;
-                }
-            },
-            Err(_) => {
-                println!("下载超时，正在重试...");
-                retry_count += 1;
-                
-                if retry_count < MAX_RETRIES {
-                    let wait_time = 5 * retry_count;
-                    println!("等待 {} 秒后重试...", wait_time);
-                    tokio::time::sleep(Duration::from_secs(wait_time as u64)).await;
-                }
-            }
+fn detect_metered_from_sysfs(net_dir: &str) -> Option<bool> {
+    let mut cellular_up = false;
+
+    for entry in fs::read_dir(net_dir).ok()?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let operstate = fs::read_to_string(entry.path().join("operstate")).unwrap_or_default();
+        let is_up = matches!(operstate.trim(), "up" | "unknown")
+            && fs::read_to_string(entry.path().join("carrier")).map(|c| c.trim() == "1").unwrap_or(false);
+        if !is_up {
+            continue;
+        }
+
+        if name.starts_with("wlan") || name.starts_with("eth") {
+            return Some(false);
+        }
+        if ["rmnet", "ccmni", "seth", "pdp", "v4-rmnet"].iter().any(|prefix| name.starts_with(prefix)) {
+            cellular_up = true;
         }
     }
 
-    Err(format!("下载失败，经过 {} 次尝试和使用 {} 个节点后仍无法完成", retry_count, cdn_nodes.len()).into())
+    if cellular_up { Some(true) } else { None }
 }
 
 fn is_vpn_active() -> bool {
@@ -1198,326 +2771,423 @@ struct DownloadResult {
     success: bool,
 }
 
-fn get_all_apk_paths() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let output = run_useful_tool_with_args("cmd", &[
-        "package", "list", "packages", "-f", "-3"
-    ])?;
-    
-    let mut apk_paths = Vec::new();
-    
-    if output.status.success() {
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        
-        for line in output_str.lines() {
-            if let Some(stripped) = line.strip_prefix("package:") {
-                if let Some(equal_pos) = stripped.rfind('=') {
-                    let full_apk_path = &stripped[..equal_pos];
+pub fn get_all_apk_paths(pm: &dyn pm::PackageManager) -> AppResult<Vec<String>> {
+    let packages = pm.list_packages(pm::PackageFilter::ThirdParty)
+        .inspect_err(|e| eprintln!("获取第三方应用列表失败: {}", e))?;
+
+    let apk_paths: Vec<String> = packages.iter()
+        .filter_map(|package| package.base_apk())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
 
-                    if full_apk_path.ends_with(".apk") {
-                        apk_paths.push(full_apk_path.to_string());
-                    } else {
-                        let full_path = format!("{}/base.apk", full_apk_path);
-                        apk_paths.push(full_path);
-                    }
-                }
-            }
-        }
-    } else {
-        println!("命令失败");
-        let error_str = String::from_utf8_lossy(&output.stderr);
-        println!("错误信息: {}", error_str);
-    }
-    
     println!("找到 {} 个 APK 文件路径", apk_paths.len());
     Ok(apk_paths)
 }
 
-fn check_apk_for_hma(apk_path: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    if !Path::new(apk_path).exists() {
-        return Ok(None);
+fn find_hma_package_in_apks(pm: &dyn pm::PackageManager) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let signatures: Vec<appscan::AppSignature> = appscan::builtin_signatures()
+        .into_iter()
+        .filter(|signature| signature.id == "hma")
+        .collect();
+
+    let mut packages = Vec::new();
+    for found in appscan::scan_installed_apps(pm, &signatures)? {
+        println!("找到疑似隐藏应用列表的 APK: {}", found.apk_path);
+        if found.package.is_empty() {
+            println!("解析清单成功但无法提取包名: {}", found.apk_path);
+        } else if !packages.contains(&found.package) {
+            packages.push(found.package);
+        }
     }
 
-    match fs::metadata(apk_path) {
-        Ok(metadata) => {
-            if metadata.len() == 0 {
-                return Ok(None);
+    println!("找到 {} 个疑似隐藏应用列表的应用", packages.len());
+    Ok(packages)
+}
+
+fn find_app(pm: &dyn pm::PackageManager, target: &str, signatures_file: Option<&str>) -> AppResult {
+    let signatures: Vec<appscan::AppSignature> = appscan::load_signatures(signatures_file)?
+        .into_iter()
+        .filter(|signature| target == "all" || signature.id == target)
+        .collect();
+
+    if signatures.is_empty() {
+        eprintln!("未知的查找目标: {}", target);
+        return Err("未知的查找目标".into());
+    }
+
+    let matches = appscan::scan_installed_apps(pm, &signatures)?;
+
+    for signature in &signatures {
+        let found: Vec<&appscan::AppMatch> = matches.iter()
+            .filter(|m| m.signature_id == signature.id)
+            .collect();
+
+        println!();
+        if found.is_empty() {
+            println!("[{}] 未发现已安装的应用", signature.name);
+            continue;
+        }
+
+        println!("[{}] 发现 {} 个疑似应用:", signature.name, found.len());
+        for m in found {
+            let package = if m.package.is_empty() { "未知包名" } else { &m.package };
+            println!("  {} (可信度: {})", package, m.confidence.label());
+            println!("    路径: {}", m.apk_path);
+            for evidence in &m.evidence {
+                println!("    - {}", evidence);
             }
-        },
-        Err(_) => {
-            return Ok(None);
         }
     }
 
-    match File::open(apk_path) {
-        Ok(_) => {
-            // 文件可访问，继续执行
-        },
+    Ok(())
+}
+
+async fn hidemyapplist(pm: &dyn pm::PackageManager) -> AppResult {
+    println!("开始查找隐藏应用列表...");
+
+    if let Some(package_name) = locate_hma_package(pm)? {
+        println!("找到的 HMA 包名: {}", package_name);
+        configure_hma(&package_name).await
+    } else {
+        println!("无法找到HMA包名");
+        download_config_to_sdcard().await
+    }
+}
+
+fn locate_hma_package(pm: &dyn pm::PackageManager) -> AppResult<Option<String>> {
+    let entries = match packages::read_packages_list() {
+        Ok(entries) => entries,
         Err(_) => {
-            return Ok(None);
+            println!("无法读取 packages.list，尝试通过日志中的 UID 获取包名...");
+            return match read_hma_log_uid().and_then(|uid| get_package_name_from_uid(pm, uid)) {
+                Some(package_name) => Ok(Some(package_name)),
+                None => {
+                    println!("无法通过 UID 获取包名，尝试扫描APK清单...");
+                    scan_hma_package(pm)
+                }
+            };
         }
+    };
+
+    if let Some(identity) = hma::load_cached_identity(&entries) {
+        println!("使用缓存的 HMA 信息: {} (UID {})", identity.package, identity.uid);
+        return Ok(Some(identity.package));
     }
 
-    let output = match run_useful_tool_with_args("aapt", &[
-        "dump",
-        "xmltree",
-        apk_path,
-        "AndroidManifest.xml"
-    ]) {
-        Ok(output) => output,
-        Err(e) => {
-            if e.to_string().contains("Text file busy") {
-                thread::sleep(Duration::from_millis(100));
-
-                match run_useful_tool_with_args("aapt", &[
-                    "dump",
-                    "xmltree",
-                    apk_path,
-                    "AndroidManifest.xml"
-                ]) {
-                    Ok(output) => output,
-                    Err(_) => {
-                        return Ok(None);
-                    }
-                }
-            } else {
+    let candidates = hma::rank_candidates(
+        &entries,
+        read_hma_log_uid(),
+        |entry| hma::looks_like_config(&format!("{}/files/config.json", entry.data_dir)),
+        |entry| packages::data_dir_owner(&entry.data_dir),
+        packages::dumpsys_user_id,
+    );
+
+    for candidate in &candidates {
+        println!("候选 HMA: {} (UID {}) 依据: {}", candidate.package, candidate.uid, candidate.sources.join(", "));
+    }
+
+    let best = match candidates.as_slice() {
+        [] => {
+            println!("未找到 HMA 的安装信息，尝试扫描APK清单...");
+            return scan_hma_package(pm);
+        }
+        [only] => only,
+        [first, second, ..] if first.sources.len() > second.sources.len() => first,
+        _ => {
+            let tied: Vec<String> = candidates.iter()
+                .filter(|candidate| candidate.sources.len() == candidates[0].sources.len())
+                .map(|candidate| candidate.package.clone())
+                .collect();
+            let Some(package_name) = select_package_from_list(&tied)? else {
                 return Ok(None);
+            };
+            match candidates.iter().find(|candidate| candidate.package == package_name) {
+                Some(candidate) => candidate,
+                None => return Ok(Some(package_name)),
             }
         }
     };
-    
-    let output_str = String::from_utf8_lossy(&output.stdout);
 
-    if output_str.contains("icu.nullptr.hidemyapplist") {
-        println!("找到疑似隐藏应用列表的 APK: {}", apk_path);
+    hma::save_cached_identity(&hma::HmaIdentity {
+        package: best.package.clone(),
+        uid: best.uid,
+    });
+    Ok(Some(best.package.clone()))
+}
 
-        let package_pattern = regex::Regex::new(r#"package="([^"]*)""#)?;
-        if let Some(caps) = package_pattern.captures(&output_str) {
-            if let Some(package_name) = caps.get(1) {
-                return Ok(Some(package_name.as_str().to_string()));
-            }
+fn scan_hma_package(pm: &dyn pm::PackageManager) -> AppResult<Option<String>> {
+    match find_hma_package_in_apks(pm) {
+        Ok(packages) => select_package_from_list(&packages),
+        Err(_) => Ok(None),
+    }
+}
+
+fn select_package_from_list(packages: &[String]) -> AppResult<Option<String>> {
+    if packages.is_empty() {
+        Ok(None)
+    } else if packages.len() == 1 {
+        Ok(Some(packages[0].clone()))
+    } else {
+        println!("找到多个疑似隐藏应用列表的应用，请选择：");
+        for (i, pkg) in packages.iter().enumerate() {
+            println!("{}. {}", i + 1, pkg);
         }
 
-        for line in output_str.lines() {
-            if line.contains("package=") {
-                if let Some(start) = line.find("package=") {
-                    let rest = &line[start + 8..];
-                    if let Some(quote_start) = rest.find('"') {
-                        let rest = &rest[quote_start + 1..];
-                        if let Some(quote_end) = rest.find('"') {
-                            return Ok(Some(rest[..quote_end].to_string()));
-                        }
-                    }
-                }
+        match prompt::choose("hma_package", packages)? {
+            Some(index) => Ok(Some(packages[index].clone())),
+            None => {
+                println!("无效选择，使用第一个应用");
+                Ok(Some(packages[0].clone()))
             }
         }
-        
-        println!("执行命令成功无法提取包名: {}", apk_path);
     }
-    
-    Ok(None)
 }
 
-fn find_hma_package_with_aapt() -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let apk_paths = get_all_apk_paths()?;
-    
-    println!("找到 {} 个第三方应用APK文件路径", apk_paths.len());
-    
-    if apk_paths.is_empty() {
-        println!("未找到任何第三方应用APK文件");
-        return Ok(Vec::new());
+const HMA_TEMPLATE_URL: &str = "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/config.json";
+const HMA_TEMPLATE_HASH: &str = "4c8cf66c0f3d6359ab28562b04697440f78fc96db5043191fb9e28d083860a9c";
+
+fn hma_config_path(package_name: &str) -> String {
+    format!("/data/data/{}/files/config.json", package_name)
+}
+
+async fn fetch_recommended_hma_config() -> AppResult<hma::HmaConfig> {
+    let file1 = "/data/cache/recovery/yshell/config.json";
+
+    if let Err(e) = fs::create_dir_all("/data/cache/recovery/yshell/") {
+        eprintln!("创建目录失败: {}", e);
+        return Err(e.into());
     }
 
-    let existing_apk_paths: Vec<String> = apk_paths.into_iter()
-        .filter(|path| Path::new(path).exists())
-        .collect();
-    
-    println!("实际可访问的 APK 文件: {} 个", existing_apk_paths.len());
-    
-    if existing_apk_paths.is_empty() {
-        println!("没有可访问的APK文件");
-        return Ok(Vec::new());
+    if let Err(e) = download_file(
+        HMA_TEMPLATE_URL.to_string(),
+        true,
+        Some(std::path::PathBuf::from(file1)),
+        Some(HMA_TEMPLATE_HASH.to_string()),
+        &DownloadLimits::default(),
+    ).await {
+        eprintln!("下载配置文件失败: {}", e);
+        return Err(e);
     }
 
-    let pb = ProgressBar::new(existing_apk_paths.len() as u64);
-    pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-        .unwrap()
-        .progress_chars("#>-"));
-    
-    let found_packages = Arc::new(Mutex::new(Vec::new()));
-    let processed = Arc::new(AtomicU64::new(0));
-    
-    let chunk_size = (existing_apk_paths.len() / num_cpus::get()).max(1);
-    let mut handles = Vec::new();
-    
-    for chunk in existing_apk_paths.chunks(chunk_size) {
-        let chunk = chunk.to_vec();
-        let found_packages = Arc::clone(&found_packages);
-        let processed = Arc::clone(&processed);
-        
-        handles.push(thread::spawn(move || {
-            for apk_path in chunk {
-                let mut retries = 0;
-                let max_retries = 3;
-                
-                while retries < max_retries {
-                    match check_apk_for_hma(&apk_path) {
-                        Ok(Some(package_name)) => {
-                            let mut packages = found_packages.lock().unwrap();
-                            packages.push(package_name);
-                            break;
-                        },
-                        Ok(None) => {
-                            // 正常情况，跳出重试循环
-                            break;
-                        },
-                        Err(_) => {
-                            retries += 1;
-                            if retries >= max_retries {
-                                eprintln!("检查某个APK失败");
-                            } else {
-                                thread::sleep(Duration::from_millis(200 * retries as u64));
-                            }
-                        }
-                    }
-                }
-                processed.fetch_add(1, Ordering::Relaxed);
-            }
-        }));
+    let config = hma::HmaConfig::load(file1);
+
+    if let Err(e) = fs::remove_file(file1) {
+        eprintln!("删除临时文件失败: {}", e);
     }
 
-    let mut last_processed = 0;
-    while last_processed < existing_apk_paths.len() as u64 {
-        let current = processed.load(Ordering::Relaxed);
-        pb.set_position(current);
-        thread::sleep(Duration::from_millis(100));
-        
-        if current == last_processed {
-            let mut all_done = true;
-            for handle in &handles {
-                if !handle.is_finished() {
-                    all_done = false;
-                    break;
-                }
-            }
-            if all_done {
-                break;
-            }
+    config
+}
+
+fn write_hma_config(package_name: &str, config: &hma::HmaConfig) -> AppResult {
+    let problems = config.validate();
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("配置校验失败: {}", problem);
         }
-        
-        last_processed = current;
+        return Err("配置校验失败，未写入".into());
     }
-    
-    for handle in handles {
-        handle.join().unwrap();
+
+    replace_hma_config(package_name, &config.to_json()?)
+}
+
+// 所有对 HMA 配置的写入都经过这里，写入前先备份原配置
+fn replace_hma_config(package_name: &str, content: &str) -> AppResult {
+    let config_path = hma_config_path(package_name);
+
+    match hma::create_backup(&config_path)? {
+        Some(backup) => println!("已备份原配置: {} (SHA-256: {})", backup.id, backup.sha256.as_deref().unwrap_or("")),
+        None => println!("原配置文件不存在，跳过备份"),
     }
-    
-    pb.finish_with_message("扫描完成");
-    
-    let packages = Arc::try_unwrap(found_packages).unwrap().into_inner().unwrap();
-    println!("找到 {} 个疑似隐藏应用列表的应用", packages.len());
-    Ok(packages)
+
+    if let Err(e) = appdata::write_app_file(&config_path, content.as_bytes()) {
+        eprintln!("写入配置文件 {} 失败: {}", config_path, e);
+        return Err(e);
+    }
+
+    if let Err(e) = appdata::restart_app(package_name) {
+        eprintln!("重启隐藏应用列表失败，请手动重启使配置生效: {}", e);
+    }
+
+    Ok(())
 }
 
-async fn hidemyapplist() -> AppResult {
-    println!("开始查找隐藏应用列表...");
+fn print_merge_report(report: &hma::MergeReport) {
+    for name in &report.added_templates {
+        println!("新增模板: {}", name);
+    }
+    for name in &report.extended_templates {
+        println!("补充模板应用: {}", name);
+    }
+    for name in &report.skipped_templates {
+        println!("跳过模板 {}: 与已有同名模板的黑白名单类型不同", name);
+    }
+    for package in &report.added_scopes {
+        println!("新增作用域: {}", package);
+    }
+    for package in &report.extended_scopes {
+        println!("补充作用域规则: {}", package);
+    }
+}
 
-    let hma_package = if let Some(path) = find_hide_my_applist_dir() {
-        if let Some(uid) = get_hma_uid(&path) {
-            println!("找到 HMA UID: {}", uid);
-            get_package_name_from_uid(&uid)
-                .or_else(|| {
-                    println!("无法通过 UID 获取包名，尝试使用 aapt 方法...");
-                    find_hma_package_with_aapt().ok().and_then(|packages| select_package_from_list(&packages))
-                })
-        } else {
-            println!("未找到 HMA UID，尝试使用 aapt 方法...");
-            find_hma_package_with_aapt().ok().and_then(|packages| select_package_from_list(&packages))
+async fn configure_hma(package_name: &str) -> AppResult {
+    let config_path = hma_config_path(package_name);
+
+    if !Path::new(&config_path).exists() {
+        println!("未找到原配置文件，将配置文件下载到/sdcard/Download目录");
+        return download_config_to_sdcard().await;
+    }
+
+    let mut config = match hma::HmaConfig::load(&config_path) {
+        Ok(config) => config,
+        Err(_) => {
+            println!("原配置文件无法解析，为避免覆盖用户设置，将配置文件下载到/sdcard/Download目录");
+            return download_config_to_sdcard().await;
         }
-    } else {
-        println!("未找到 HMA 目录，尝试使用 aapt 方法...");
-        find_hma_package_with_aapt().ok().and_then(|packages| select_package_from_list(&packages))
     };
 
-    if let Some(package_name) = hma_package {
-        println!("找到的 HMA 包名: {}", package_name);
-        configure_hma(&package_name).await
-    } else {
-        println!("无法找到HMA包名");
-        download_config_to_sdcard().await
+    let recommended = match fetch_recommended_hma_config().await {
+        Ok(recommended) => recommended,
+        Err(_) => return download_config_to_sdcard().await,
+    };
+
+    let report = config.merge(&recommended);
+    print_merge_report(&report);
+
+    if let Err(e) = write_hma_config(package_name, &config) {
+        eprintln!("合并配置失败: {}", e);
+        return download_config_to_sdcard().await;
     }
+
+    println!("配置文件已成功合并");
+    Ok(())
 }
 
-fn select_package_from_list(packages: &[String]) -> Option<String> {
-    if packages.is_empty() {
-        None
-    } else if packages.len() == 1 {
-        Some(packages[0].clone())
-    } else {
-        println!("找到多个疑似隐藏应用列表的应用，请选择：");
-        for (i, pkg) in packages.iter().enumerate() {
-            println!("{}. {}", i + 1, pkg);
+fn require_hma_package(pm: &dyn pm::PackageManager) -> AppResult<String> {
+    match locate_hma_package(pm)? {
+        Some(package_name) => {
+            println!("找到的 HMA 包名: {}", package_name);
+            Ok(package_name)
+        }
+        None => {
+            eprintln!("无法找到HMA包名");
+            Err("无法找到HMA包名".into())
+        }
+    }
+}
+
+fn hma_list(pm: &dyn pm::PackageManager) -> AppResult {
+    let package_name = require_hma_package(pm)?;
+    let config = hma::HmaConfig::load(&hma_config_path(&package_name))?;
+
+    println!("配置版本: {}", config.config_version);
+    println!();
+    println!("模板 ({} 个):", config.templates.len());
+    for (name, template) in &config.templates {
+        let kind = if template.is_whitelist { "白名单" } else { "黑名单" };
+        println!("  {} [{}] {} 个应用", name, kind, template.app_list.len());
+    }
+
+    println!();
+    println!("作用域 ({} 个):", config.scope.len());
+    for (package, scope) in &config.scope {
+        let mode = if scope.use_whitelist { "白名单" } else { "黑名单" };
+        println!("  {} [{}]", package, mode);
+        if !scope.apply_templates.is_empty() {
+            println!("    模板: {}", scope.apply_templates.join(", "));
+        }
+        if !scope.extra_app_list.is_empty() {
+            println!("    额外应用: {}", scope.extra_app_list.join(", "));
         }
+    }
 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).expect("读取输入失败");
+    Ok(())
+}
 
-        match input.trim().parse::<usize>() {
-            Ok(choice) if choice > 0 && choice <= packages.len() => {
-                Some(packages[choice - 1].clone())
-            },
-            _ => {
-                println!("无效选择，使用第一个应用");
-                Some(packages[0].clone())
-            }
-        }
-    }
+async fn hma_merge(pm: &dyn pm::PackageManager, template_file: Option<&str>) -> AppResult {
+    let package_name = require_hma_package(pm)?;
+    let mut config = hma::HmaConfig::load(&hma_config_path(&package_name))?;
+
+    let recommended = match template_file {
+        Some(path) => hma::HmaConfig::load(path)?,
+        None => fetch_recommended_hma_config().await?,
+    };
+
+    let report = config.merge(&recommended);
+    print_merge_report(&report);
+    write_hma_config(&package_name, &config)?;
+
+    println!("配置文件已成功合并");
+    Ok(())
 }
 
-async fn configure_hma(package_name: &str) -> AppResult {
-    let file1 = "/data/cache/recovery/yshell/config.json";
-    let file2 = format!("/data/data/{}/files/config.json", package_name);
+fn installed_packages(pm: &dyn pm::PackageManager, filter: pm::PackageFilter) -> AppResult<Vec<String>> {
+    let packages = pm.list_packages(filter)
+        .inspect_err(|e| eprintln!("获取已安装应用失败: {}", e))?;
+    Ok(packages.into_iter().map(|package| package.name).collect())
+}
 
-    if let Err(e) = fs::create_dir_all("/data/cache/recovery/yshell/") {
-        eprintln!("创建目录失败: {}", e);
-        return download_config_to_sdcard().await;
+fn hma_scope(pm: &dyn pm::PackageManager, packages: &[String], templates: &[String]) -> AppResult {
+    let package_name = require_hma_package(pm)?;
+    let mut config = hma::HmaConfig::load(&hma_config_path(&package_name))?;
+
+    let packages = if packages.is_empty() {
+        let installed = installed_packages(pm, pm::PackageFilter::All)?;
+        hma::KNOWN_DETECTORS.iter()
+            .filter(|detector| installed.iter().any(|package| package == *detector))
+            .map(|detector| detector.to_string())
+            .collect()
+    } else {
+        packages.to_vec()
+    };
+
+    if packages.is_empty() {
+        println!("未发现已安装的检测应用");
+        return Ok(());
     }
 
-    if let Err(e) = download_file(
-        "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/config.json".to_string(),
-        true,
-        Some(std::path::PathBuf::from(file1)),
-        Some("4c8cf66c0f3d6359ab28562b04697440f78fc96db5043191fb9e28d083860a9c".to_string()),
-    ).await {
-        eprintln!("下载配置文件失败: {}", e);
-        return download_config_to_sdcard().await;
+    let templates = if templates.is_empty() {
+        config.blacklist_templates()
+    } else {
+        templates.to_vec()
+    };
+
+    let added = config.add_to_scope(&packages, &templates);
+    if added.is_empty() {
+        println!("这些应用已在作用域中");
+        return Ok(());
     }
 
-    if !Path::new(&file2).exists() {
-        println!("未找到原配置文件，将配置文件下载到/sdcard/Download目录");
-        return download_config_to_sdcard().await;
+    write_hma_config(&package_name, &config)?;
+    for package in &added {
+        println!("已添加到作用域: {}", package);
     }
+    Ok(())
+}
 
-    let new_config_content = match fs::read_to_string(file1) {
+fn hma_validate(pm: &dyn pm::PackageManager, config_file: Option<&str>) -> AppResult {
+    let config_path = match config_file {
+        Some(path) => path.to_string(),
+        None => hma_config_path(&require_hma_package(pm)?),
+    };
+
+    let content = match fs::read_to_string(&config_path) {
         Ok(content) => content,
         Err(e) => {
-            eprintln!("读取下载的配置文件失败: {}", e);
-            return download_config_to_sdcard().await;
+            eprintln!("读取配置文件 {} 失败: {}", config_path, e);
+            return Err(e.into());
         }
     };
 
-    if let Err(e) = fs::write(&file2, new_config_content) {
-        eprintln!("写入原配置文件失败: {}", e);
-        return download_config_to_sdcard().await;
-    }
-
-    println!("配置文件已成功更新");
-
-    if let Err(e) = fs::remove_file(file1) {
-        eprintln!("删除临时文件失败: {}", e);
+    let problems = hma::validate_json(&content);
+    if problems.is_empty() {
+        println!("配置文件校验通过: {}", config_path);
+        Ok(())
+    } else {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        Err("配置文件校验失败".into())
     }
-    
-    Ok(())
 }
 
 async fn download_config_to_sdcard() -> AppResult {
@@ -1528,34 +3198,64 @@ async fn download_config_to_sdcard() -> AppResult {
     let config_hash = "b97c517369300d1c073cc4f49a0117912ee540f24161b2df306ed0e9f88fd426";
     
     download_file(
-        "https://github.com/yu13140/yuhideroot/raw/refs/heads/main/module/config.json".to_string(),
+        HMA_TEMPLATE_URL.to_string(),
         true,
         Some(std::path::PathBuf::from(file1)),
         Some(config_hash.to_string()),
+        &DownloadLimits::default(),
     ).await?;
     
     println!("已将配置文件保存在 /sdcard/Download/隐藏应用列表配置.json 中");
     Ok(())
 }
 
-fn recoverapplist() -> AppResult {
-    let path_hma = match find_hide_my_applist_dir() {
-        Some(path) => path,
-        None => {
-            eprintln!("未找到手机上的隐藏应用列表");
-            return Err("未找到手机上的隐藏应用列表".into());
-        }
+fn recoverapplist(pm: &dyn pm::PackageManager) -> AppResult {
+    hma_restore(pm, None)
+}
+
+fn hma_backups() -> AppResult {
+    let backups = hma::list_backups()?;
+    if backups.is_empty() {
+        println!("没有可用的备份，备份目录: {}", hma::BACKUP_DIR);
+        return Ok(());
+    }
+
+    println!("可用的备份 ({} 个，从新到旧):", backups.len());
+    for backup in &backups {
+        println!("  {}  {} 字节  SHA-256: {}", backup.id, backup.size, backup.sha256.as_deref().unwrap_or("未记录"));
+    }
+    Ok(())
+}
+
+// 未指定 ID 时恢复最新的备份，恢复后备份仍然保留
+fn hma_restore(pm: &dyn pm::PackageManager, id: Option<&str>) -> AppResult {
+    let backups = hma::list_backups()?;
+    let backup = match id {
+        Some(id) => backups.iter().find(|backup| backup.id == id),
+        None => backups.first(),
     };
 
-    let hma_uid = match get_hma_uid(&path_hma) {
-        Some(uid) => uid,
+    let backup = match backup {
+        Some(backup) => backup,
         None => {
-            eprintln!("无法获取HMA UID");
-            return Err("无法获取HMA UID".into());
+            match id {
+                Some(id) => eprintln!("错误：备份 {} 不存在！", id),
+                None => eprintln!("错误：备份文件不存在！"),
+            }
+            return Err("备份文件不存在".into());
         }
     };
 
-    let hma_package = match get_package_name_from_uid(&hma_uid) {
+    let content = backup.read_verified()?;
+    let problems = hma::validate_json(&content);
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("备份校验失败: {}", problem);
+        }
+        return Err("备份文件不是有效的 HMA 配置".into());
+    }
+
+    let hma_package = match locate_hma_package(pm)? {
         Some(pkg) => pkg,
         None => {
             eprintln!("无法找到HMA包名");
@@ -1563,26 +3263,14 @@ fn recoverapplist() -> AppResult {
         }
     };
 
-    let backup_dir = "/sdcard/一键解决隐藏问题/";
-    let file2 = format!("/data/data/{}/files/config.json", hma_package);
-    let backup_file = format!("{}{}", backup_dir, std::path::Path::new(&file2).file_name().unwrap().to_string_lossy());
-
-    if Path::new(&backup_file).exists() {
-        if let Err(e) = fs::copy(&backup_file, &file2) {
-            eprintln!("恢复备份失败: {}", e);
-            return Err(e.into());
-        }
-        
-        if let Err(e) = fs::remove_file(&backup_file) {
-            eprintln!("删除备份文件失败: {}", e);
-        }
-        
-        println!("恢复备份成功");
-        Ok(())
-    } else {
-        eprintln!("错误：备份文件不存在！");
-        Err("备份文件不存在".into())
+    // 要恢复的备份已在写入前选定，恢复前的配置同样会被备份
+    if let Err(e) = replace_hma_config(&hma_package, &content) {
+        eprintln!("恢复备份失败: {}", e);
+        return Err(e);
     }
+
+    println!("已恢复备份 {}", backup.id);
+    Ok(())
 }
 
 fn find_hide_my_applist_dir() -> Option<String> {
@@ -1601,19 +3289,11 @@ fn find_hide_my_applist_dir() -> Option<String> {
     None
 }
 
-fn get_hma_uid(path_hma: &str) -> Option<String> {
+fn read_hma_log_uid() -> Option<u32> {
+    let path_hma = find_hide_my_applist_dir()?;
     let log_path = format!("{}/log/runtime.log", path_hma);
 
-    if !Path::new(&log_path).exists() {
-        eprintln!("日志文件不存在: {}", log_path);
-        return None;
-    }
-
-    extract_uid_from_log(&log_path)
-}
-
-fn extract_uid_from_log(log_path: &str) -> Option<String> {
-    let content = match fs::read_to_string(log_path) {
+    let content = match fs::read_to_string(&log_path) {
         Ok(content) => content,
         Err(e) => {
             eprintln!("无法读取日志文件 {}: {}", log_path, e);
@@ -1621,96 +3301,41 @@ fn extract_uid_from_log(log_path: &str) -> Option<String> {
         }
     };
 
-    for line in content.lines() {
-        if line.contains("Client uid") {
-            println!("找到包含UID的行: {}", line);
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if let Some(uid) = parts.last() {
-                if uid.chars().all(|c| c.is_ascii_digit()) {
-                    println!("提取到 UID: {}", uid);
-                    return Some(uid.to_string());
-                } else {
-                    eprintln!("找到的 UID 不是数字: {}", uid);
-                }
-            }
+    match hma::parse_log_uid(&content) {
+        Some(uid) => {
+            println!("从日志中提取到 UID: {}", uid);
+            Some(uid)
+        }
+        None => {
+            eprintln!("在日志文件中未找到隐藏应用列表的UID");
+            None
         }
     }
-      
-    eprintln!("在日志文件中未找到隐藏应用列表的UID");
-    None
 }
 
-fn get_package_name_from_uid(uid: &str) -> Option<String> {
+fn get_package_name_from_uid(pm: &dyn pm::PackageManager, uid: u32) -> Option<String> {
     println!("尝试通过 UID {} 获取包名", uid);
-    
-    match run_useful_tool_with_args("cmd", &["package", "list", "packages", "--uid", uid]) {
-        Ok(output) => {
-            if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
-                println!("uid输出: {}", output_str);
-                
-                for line in output_str.lines() {
-                    if line.starts_with("package:") {
-                        let package_line = line.replace("package:", "");
-                        let package_name = package_line.split_whitespace().next().unwrap_or("").to_string();
-                        if !package_name.is_empty() {
-                            println!("找到包名: {}", package_name);
-                            return Some(package_name);
-                        }
-                    }
-                }
-                
-                eprintln!("输出中没有找到有效的包名");
-            } else {
-                let error_msg = String::from_utf8_lossy(&output.stderr);
-                eprintln!("执行命令失败: {}", error_msg);
+
+    match pm.list_packages(pm::PackageFilter::Uid(uid)) {
+        Ok(packages) => match packages.into_iter().next() {
+            Some(package) => {
+                println!("找到包名: {}", package.name);
+                Some(package.name)
+            }
+            None => {
+                eprintln!("没有 UID 为 {} 的应用", uid);
+                None
             }
-            None
         },
         Err(e) => {
-            eprintln!("执行命令失败: {}", e);
+            eprintln!("获取包名失败: {}", e);
             None
-        },
+        }
     }
 }
 
 fn run_useful_tool_with_args(tool_name: &str, args: &[&str]) -> AppResult<std::process::Output> {
-    let tool_data = match tool_name {
-        "cmd" => include_bytes!("binaries/cmd").as_slice(),
-        "aapt" => include_bytes!("binaries/aapt").as_slice(),
-        _ => return Err(format!("未知的工具名: {}", tool_name).into()),
-    };
-
-    let unique_id: u64 = rand::thread_rng().gen_range(0..1000000);
-    let temp_file_name = format!("{}_{}", tool_name, unique_id);
-    let temp_file = create_temp_tool(&temp_file_name, tool_data)?;
-    
-    let output = Command::new(&temp_file)
-        .args(args)
-        .output()?;
-
-    fs::remove_file(&temp_file)?;
-    
-    Ok(output)
-}
-
-fn create_temp_tool(tool_name: &str, data: &[u8]) -> AppResult<std::path::PathBuf> {
-    let temp_dir = std::env::temp_dir();
-    let temp_file = temp_dir.join(tool_name);
-
-    let mut file = File::create(&temp_file)?;
-    file.write_all(data)?;
-    file.flush()?;
-
-    #[cfg(unix)]
-    {
-        let mut perms = fs::metadata(&temp_file)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&temp_file, perms)?;
-    }
-
-    thread::sleep(Duration::from_millis(10));
-    Ok(temp_file)
+    tools::output(tool_name, args)
 }
 
 fn clean_modules_dirs() -> AppResult {
@@ -1846,53 +3471,9 @@ fn deleter(delete_type: &str, path: &str, recursive: bool) -> AppResult {
     Ok(())
 }
 
-fn run_useful_tool(tool_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    let tool_args: Vec<&str> = if args.len() > 3 {
-        args[3..].iter().map(|s| s.as_str()).collect()
-    } else {
-        Vec::new()
-    };
-
-    let tool_data = match tool_name {
-        "cmd" => include_bytes!("binaries/cmd").as_slice(),
-        _ => {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                format!("未知的工具名: {}", tool_name),
-            )));
-        }
-    };
-
-    let temp_dir = std::env::temp_dir();
-    let temp_file = temp_dir.join(tool_name);
-
-    {
-        let mut file = File::create(&temp_file)?;
-        file.write_all(tool_data)?;
-        file.flush()?;
-    }
-
-    #[cfg(unix)]
-    {        
-        let mut perms = fs::metadata(&temp_file)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&temp_file, perms)?;
-    }
-
-    std::thread::sleep(std::time::Duration::from_millis(100));
+fn run_useful_tool(tool_name: &str, tool_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = tools::status(tool_name, tool_args).inspect_err(|e| eprintln!("{}", e))?;
 
-    let mut command = Command::new(&temp_file);
-    command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
-
-    for arg in tool_args {
-        command.arg(arg);
-    }
-    
-    let status = command.status()?;
-
-    let _ = fs::remove_file(&temp_file);
-    
     if status.success() {
         Ok(())
     } else {
@@ -2147,7 +3728,69 @@ async fn boothash() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn load_vbmeta_chain() -> AppResult<avb::VbmetaChain> {
+    let slot_suffix = get_system_prop("ro.boot.slot_suffix").unwrap_or_default();
+    let chain = avb::load_chain(Path::new(avb::BY_NAME_DIR), &slot_suffix)?;
+
+    for image in &chain.images {
+        println!(
+            "vbmeta: {}{} ({} 字节, libavb {}.{}, flags {}, {})",
+            image.partition, slot_suffix, image.data.len(),
+            image.required_version.0, image.required_version.1, image.flags, image.release
+        );
+    }
+    Ok(chain)
+}
+
+fn audit_vbmeta() -> AppResult {
+    let chain = load_vbmeta_chain()?;
+    let live_avb_version = get_system_prop("ro.boot.vbmeta.avb_version");
+    let expected = chain.expected_props(live_avb_version.as_deref());
+
+    let problems = chain.image_problems();
+    for problem in &problems {
+        eprintln!("vbmeta 异常: {}", problem);
+    }
+
+    let mismatches = avb::audit_props(&expected, get_system_prop);
+    for (name, value) in &expected {
+        match mismatches.iter().find(|mismatch| mismatch.name == *name) {
+            Some(mismatch) => println!(
+                "[不一致] {} 当前: {} 应为: {}",
+                name, mismatch.live.as_deref().unwrap_or("(未设置)"), mismatch.expected
+            ),
+            None => println!("[一致] {} = {}", name, value),
+        }
+    }
+
+    if mismatches.is_empty() && problems.is_empty() {
+        println!("所有 vbmeta 相关属性与分区内容一致");
+        Ok(())
+    } else {
+        println!("发现 {} 处不一致，可使用 rshy nativedetector vbmeta 生成修复模块", mismatches.len() + problems.len());
+        Err("vbmeta 属性与分区内容不一致".into())
+    }
+}
+
+// 优先从 vbmeta 分区计算，失败时才安装辅助 APK 读取
 async fn get_boot_hash() -> Result<String, Box<dyn std::error::Error>> {
+    println!("正在从 vbmeta 分区计算 verifiedBootHash...");
+    match load_vbmeta_chain() {
+        Ok(chain) => {
+            let digest = chain.digest();
+            println!("verifiedBootHash: {}", digest);
+            return Ok(digest);
+        },
+        Err(e) => {
+            eprintln!("本地计算 verifiedBootHash 失败: {}", e);
+            println!("改为通过辅助应用获取");
+        }
+    }
+
+    get_boot_hash_from_apk(&pm::system()).await
+}
+
+async fn get_boot_hash_from_apk(pm: &dyn PackageManager) -> Result<String, Box<dyn std::error::Error>> {
     println!("正在下载service.apk...");
     let apk_path = "/data/cache/recovery/yshell/service.apk";
 
@@ -2156,6 +3799,7 @@ async fn get_boot_hash() -> Result<String, Box<dyn std::error::Error>> {
         true,
         Some(std::path::PathBuf::from(apk_path)),
         None,
+        &DownloadLimits::default(),
     ).await {
         Ok(_) => {
             println!("下载完成");
@@ -2167,41 +3811,15 @@ async fn get_boot_hash() -> Result<String, Box<dyn std::error::Error>> {
     }
 
     println!("正在安装service.apk...");
-    match run_useful_tool_with_args("cmd", &[
-        "package", 
-        "install", 
-        "-r", 
-        apk_path
-    ]) {
-        Ok(output) => {
-            if !output.status.success() {
-                let error_msg = String::from_utf8_lossy(&output.stderr);
-                eprintln!("安装失败: {}", error_msg);
-                return Err(format!("APK安装失败: {}", error_msg).into());
-            }
-            println!("安装完成");
-        },
-        Err(e) => {
-            eprintln!("安装失败: {}", e);
-            return Err(e);
-        }
+    if let Err(e) = pm.install(Path::new(apk_path)) {
+        eprintln!("安装失败: {}", e);
+        return Err(format!("APK安装失败: {}", e).into());
     }
+    println!("安装完成");
 
     println!("正在启动服务...");
-    match run_useful_tool_with_args("cmd", &[
-        "activity", 
-        "start-foreground-service", 
-        "-n", 
-        "com.yu13140.verifiedboothash/.GetHashService"
-    ]) {
-        Ok(output) => {
-            if !output.status.success() {
-                eprintln!("启动服务失败");
-            }
-        },
-        Err(e) => {
-            eprintln!("启动服务失败: {}", e);
-        }
+    if let Err(e) = pm.start_service("com.yu13140.verifiedboothash/.GetHashService") {
+        eprintln!("启动服务失败: {}", e);
     }
 
     println!("等待服务完成工作...");
@@ -2217,22 +3835,9 @@ async fn get_boot_hash() -> Result<String, Box<dyn std::error::Error>> {
     };
 
     println!("正在卸载应用...");
-    match run_useful_tool_with_args("cmd", &[
-        "package", 
-        "uninstall", 
-        "com.yu13140.verifiedboothash"
-    ]) {
-        Ok(output) => {
-            if !output.status.success() {
-                let error_msg = String::from_utf8_lossy(&output.stderr);
-                eprintln!("卸载应用失败: {}", error_msg);
-            } else {
-                println!("应用卸载完成");
-            }
-        },
-        Err(e) => {
-            eprintln!("卸载应用失败: {}", e);
-        }
+    match pm.uninstall("com.yu13140.verifiedboothash") {
+        Ok(()) => println!("应用卸载完成"),
+        Err(e) => eprintln!("卸载应用失败: {}", e),
     }
     
     Ok(boot_hash)
@@ -2247,27 +3852,45 @@ async fn nd_vbmeta() -> Result<(), Box<dyn std::error::Error>> {
         return Err(e.into());
     }
 
-    let mut rng = rand::thread_rng();
-    let random_value = rng.gen_range(1..=15);
-    let vbmeta_size = 5504 + random_value * 1024;
+    let vbmeta_props = match load_vbmeta_chain() {
+        Ok(chain) => {
+            let live_avb_version = get_system_prop("ro.boot.vbmeta.avb_version");
+            chain.expected_props(live_avb_version.as_deref())
+                .into_iter()
+                .filter(|(name, _)| name.starts_with("ro.boot.vbmeta."))
+                .collect()
+        },
+        Err(e) => {
+            eprintln!("无法读取 vbmeta 分区: {}", e);
+            println!("将使用估算的 vbmeta 大小");
 
-    let boot_hash = get_boot_hash().await?;
+            let mut rng = rand::thread_rng();
+            let random_value = rng.gen_range(1..=15);
+            let vbmeta_size = 5504 + random_value * 1024;
+
+            let boot_hash = get_boot_hash_from_apk(&pm::system()).await?;
+            if boot_hash.is_empty() {
+                eprintln!("无法获取boot哈希值");
+                return Err("无法获取boot哈希值".into());
+            }
+
+            vec![
+                ("ro.boot.vbmeta.invalidate_on_error", "yes".to_string()),
+                ("ro.boot.vbmeta.hash_alg", "sha256".to_string()),
+                ("ro.boot.vbmeta.size", vbmeta_size.to_string()),
+                ("ro.boot.vbmeta.device_state", "locked".to_string()),
+                ("ro.boot.vbmeta.avb_version", "1.2".to_string()),
+                ("ro.boot.vbmeta.digest", boot_hash),
+            ]
+        }
+    };
 
     let mut service_content = String::new();
     service_content.push_str("#!/system/bin/sh\n\n");
     service_content.push_str("# 解决Native Detector提示检测到Boot状态异常问题\n");
     service_content.push_str("sleep 10\n\n");
-    service_content.push_str("resetprop -n ro.boot.vbmeta.invalidate_on_error yes\n");
-    service_content.push_str("resetprop -n ro.boot.vbmeta.hash_alg sha256\n");
-    service_content.push_str(&format!("resetprop -n ro.boot.vbmeta.size {}\n", vbmeta_size));
-    service_content.push_str("resetprop -n ro.boot.vbmeta.device_state locked\n");
-    service_content.push_str("resetprop -n ro.boot.vbmeta.avb_version 1.2\n");
-    
-    if !boot_hash.is_empty() {
-        service_content.push_str(&format!("resetprop -n ro.boot.vbmeta.digest {}\n", boot_hash));
-    } else {
-        eprintln!("无法获取boot哈希值");
-        return Err("无法获取boot哈希值".into());
+    for (name, value) in &vbmeta_props {
+        service_content.push_str(&format!("resetprop -n {} {}\n", name, value));
     }
 
     let service_path = format!("{}/service.sh", temp_dir);
@@ -2320,13 +3943,13 @@ async fn nd_vbmeta() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
     }
 }
-fn momo_tee() {
+fn momo_tee(pm: &dyn pm::PackageManager) {
     let module_path = "/data/adb/modules/tricky_store";
     if !Path::new(module_path).exists() {
         eprintln!("你没有安装Tricky Store，是否安装此模块？");
     }
     
-    let target_dir = "/data/adb/tricky_store";
+    let target_dir = tricky::TRICKY_STORE_DIR;
     if !Path::new(target_dir).exists() {
         if let Err(e) = std::fs::create_dir_all(target_dir) {
             eprintln!("创建目录失败: {}", e);
@@ -2334,72 +3957,37 @@ fn momo_tee() {
         }
     }
     
-    match run_useful_tool_with_args("cmd", &["package", "list", "packages"]) {
-        Ok(output) => {
-            if output.status.success() {
-                let packages_output = String::from_utf8_lossy(&output.stdout);
-                
-                let processed_packages: String = packages_output
-                    .lines()
-                    .filter(|line| !line.is_empty())
-                    .map(|line| line.replace("package:", ""))
-                    .map(|line| if !line.is_empty() { format!("{}!\n", line) } else { line })
-                    .collect();
-                
-                let target_file = format!("{}/target.txt", target_dir);
-                if let Err(e) = std::fs::write(&target_file, processed_packages) {
-                    eprintln!("写入文件失败: {}", e);
-                    std::process::exit(1);
-                }
-                
-                let tee_file = format!("{}/tee_status", target_dir);
-                if let Err(e) = std::fs::write(&tee_file, "teeBroken=true") {
-                    eprintln!("创建tee状态文件失败: {}", e);
-                    std::process::exit(1);
-                }
-                
-                println!("命令执行完成");
-            } else {
-                eprintln!("cmd package list packages 命令执行失败");
-                std::process::exit(1);
-            }
-        },
-        Err(e) => {
-            eprintln!("执行cmd package list packages命令失败: {}", e);
-            std::process::exit(1);
-        }
+    let Ok(installed) = installed_packages(pm, pm::PackageFilter::All) else {
+        std::process::exit(1);
+    };
+
+    if tricky_target_sync(&installed, &[], &[], tricky::TargetMode::Generate).is_err() {
+        std::process::exit(1);
+    }
+
+    let tee_file = format!("{}/tee_status", target_dir);
+    if let Err(e) = std::fs::write(&tee_file, "teeBroken=true") {
+        eprintln!("创建tee状态文件失败: {}", e);
+        std::process::exit(1);
     }
+
+    println!("命令执行完成");
 }
 
 fn update_target_file() -> Result<(), Box<dyn std::error::Error>> {
-    let target_file_path = "/data/adb/tricky_store/target.txt";
+    let target_file_path = tricky::TARGET_FILE;
 
     if !Path::new(target_file_path).exists() {
         eprintln!("目标文件不存在: {}", target_file_path);
         return Err("目标文件不存在".into());
     }
 
-    let content = fs::read_to_string(target_file_path)?;
-    let mut lines: Vec<&str> = content.lines().collect();
-
-    lines.retain(|&line| line.trim() != "luna.safe.luna");
-
-    let has_hunter = lines.iter().any(|&line| line.trim() == "com.zhenxi.hunter");
-
-    if !has_hunter {
-        while let Some(last_line) = lines.last() {
-            if last_line.trim().is_empty() {
-                lines.pop();
-            } else {
-                break;
-            }
-        }
-        lines.push("");
-        lines.push("com.zhenxi.hunter");
+    let mut targets = tricky::TargetList::load(target_file_path)?;
+    targets.remove("luna.safe.luna");
+    if !targets.contains("com.zhenxi.hunter") {
+        targets.add("com.zhenxi.hunter", tricky::TargetMode::Auto);
     }
-
-    let new_content = lines.join("\n");
-    fs::write(target_file_path, new_content)?;
+    targets.save(target_file_path)?;
 
     println!("成功更新目标文件: {}", target_file_path);
     Ok(())
@@ -2480,13 +4068,13 @@ fn init_rc() -> Result<(), Box<dyn std::error::Error>> {
 fn momo_addon() -> Result<(), Box<dyn std::error::Error>> {
     println!("高危选项！操作需要删除system分区里的addon.d文件夹");
     println!("删除这个文件夹，可能会使设备开机后不能写入system分区");
-    println!("你确定要继续吗？(1.继续  2.退出): ");
-
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
-    let input = input.trim();
+    let proceed = prompt::confirm(
+        "confirm_addon",
+        "你确定要继续吗？(1.继续  2.退出): ",
+        |input| input != "2",
+    )?;
 
-    if input == "2" {
+    if !proceed {
         println!("你选择了退出");
         return Ok(());
     }
@@ -2742,58 +4330,40 @@ fn holmes_9ff() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn clean_package_dex(package_name: &str) {
-    match run_useful_tool_with_args("cmd", &["package", "list", "packages", "-f"]) {
+fn clean_package_dex(pm: &dyn pm::PackageManager, package_name: &str) {
+    let base_apk = match pm.package_paths(package_name) {
+        Ok(paths) => paths.into_iter().find(|path| path.ends_with("base.apk")),
+        Err(e) => {
+            eprintln!("获取 {} 的安装路径失败: {}", package_name, e);
+            return;
+        }
+    };
+    let Some(path) = base_apk.as_deref().and_then(Path::parent) else {
+        eprintln!("未找到包名为 {} 的应用", package_name);
+        return;
+    };
+
+    if !path.exists() {
+        eprintln!("路径不存在: {}", path.display());
+        return;
+    }
+
+    match Command::new("find")
+        .arg(path)
+        .args(&["-type", "f", "-name", "*.*dex"])
+        .args(&["-exec", "rm", "{}", ";"])
+        .output()
+    {
         Ok(output) => {
             if output.status.success() {
-                let output_str = String::from_utf8_lossy(&output.stdout);
-                let mut found = false;
-                
-                for line in output_str.lines() {
-                    if line.contains(package_name) {
-                        found = true;
-                        let cleaned_line = line.replace("package:", "");
-                        
-                        let parts: Vec<&str> = cleaned_line.split("base").collect();
-                        if !parts.is_empty() {
-                            let path = parts[0].trim();
-                            
-                            if Path::new(path).exists() {
-                                match Command::new("find")
-                                    .arg(path)
-                                    .args(&["-type", "f", "-name", "*.*dex"])
-                                    .args(&["-exec", "rm", "{}", ";"])
-                                    .output()
-                                {
-                                    Ok(output) => {
-                                        if output.status.success() {
-                                            println!("已清理 {} 目录中的 dex 文件", path);
-                                        } else {
-                                            let error_output = String::from_utf8_lossy(&output.stderr);
-                                            eprintln!("清理 dex 文件失败: {}", error_output);
-                                        }
-                                    },
-                                    Err(e) => {
-                                        eprintln!("执行 find 命令失败: {}", e);
-                                    }
-                                }
-                            } else {
-                                eprintln!("路径不存在: {}", path);
-                            }
-                        }
-                        break;
-                    }
-                }
-                
-                if !found {
-                    eprintln!("未找到包名为 {} 的应用", package_name);
-                }
+                println!("已清理 {} 目录中的 dex 文件", path.display());
             } else {
-                eprintln!("cmd package list packages -f 命令执行失败");
+                let error_output = String::from_utf8_lossy(&output.stderr);
+                eprintln!("清理 dex 文件失败: {}", error_output);
             }
         },
         Err(e) => {
-            eprintln!("执行 cmd package list packages -f 命令失败: {}", e);
+            eprintln!("执行 find 命令失败: {}", e);
         }
     }
 }
@@ -2830,20 +4400,12 @@ fn cts_fix() {
     std::thread::sleep(std::time::Duration::from_millis(1400));
 }
 
-fn hunter_miui() {
+fn hunter_miui(pm: &dyn pm::PackageManager) {
     println!("目前仅适用于米系手机(小米，红米)");
     
-    match run_useful_tool_with_args("cmd", &["package", "disable", "--user", "0", "com.miui.securitycenter/com.xiaomi.security.xsof.MiSafetyDetectService"]) {
-        Ok(output) => {
-            if output.status.success() {
-                println!("已禁用MIUI安全检测服务");
-            } else {
-                eprintln!("禁用服务失败");
-            }
-        },
-        Err(e) => {
-            eprintln!("执行cmd package disable命令失败: {}", e);
-        }
+    match pm.disable_component("com.miui.securitycenter/com.xiaomi.security.xsof.MiSafetyDetectService") {
+        Ok(()) => println!("已禁用MIUI安全检测服务"),
+        Err(e) => eprintln!("禁用服务失败: {}", e),
     }
     
     std::thread::sleep(std::time::Duration::from_millis(1400));
@@ -2921,7 +4483,7 @@ fn nd_magicmount() {
 fn momo(profile_name: &str) -> Result<(), Box<dyn std::error::Error>> {
     match profile_name {
         "tee" => {
-            momo_tee();
+            momo_tee(&pm::system());
             Ok(())
         },
         "systemmount" => {
@@ -2951,7 +4513,7 @@ fn momo(profile_name: &str) -> Result<(), Box<dyn std::error::Error>> {
 async fn nativetest(profile_name: &str) -> Result<(), Box<dyn std::error::Error>> {
     match profile_name {
         "futile10" => {
-            clean_package_dex("icu.nullptr.nativetest");
+            clean_package_dex(&pm::system(), "icu.nullptr.nativetest");
             Ok(())
         }
         "boothash" => {
@@ -2993,7 +4555,7 @@ fn hunter(profile_name: &str) -> Result<(), Box<dyn std::error::Error>> {
             Ok(())
         }
         "manager" => {
-            hunter_miui();
+            hunter_miui(&pm::system());
             Ok(())
         }
         _ => {
@@ -3015,7 +4577,7 @@ async fn nativedetector(profile_name: &str) -> Result<(), Box<dyn std::error::Er
             Ok(())
         }
         "lsp5" => {
-            clean_package_dex("com.reveny.nativecheck");
+            clean_package_dex(&pm::system(), "com.reveny.nativecheck");
             Ok(())
         }
         _ => {
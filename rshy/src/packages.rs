@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+
+use crate::{der, xml, AppResult};
+
+pub const PACKAGES_LIST: &str = "/data/system/packages.list";
+pub const PACKAGES_XML: &str = "/data/system/packages.xml";
+pub const PACKAGE_RESTRICTIONS_XML: &str = "/data/system/users/0/package-restrictions.xml";
+
+// ApplicationInfo.FLAG_SYSTEM，系统应用更新到 /data/app 后仍保留此标志
+const FLAG_SYSTEM: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageEntry {
+    pub name: String,
+    pub uid: u32,
+    pub data_dir: String,
+}
+
+// 每行格式: <包名> <uid> <debuggable> <数据目录> <seinfo> <gids>
+pub fn parse_packages_list(content: &str) -> Vec<PackageEntry> {
+    content.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let uid = fields.next()?.parse().ok()?;
+            let data_dir = fields.nth(1)?;
+            Some(PackageEntry {
+                name: name.to_string(),
+                uid,
+                data_dir: data_dir.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn read_packages_list() -> AppResult<Vec<PackageEntry>> {
+    match fs::read_to_string(PACKAGES_LIST) {
+        Ok(content) => Ok(parse_packages_list(&content)),
+        Err(e) => {
+            eprintln!("读取 {} 失败: {}", PACKAGES_LIST, e);
+            Err(e.into())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub code_path: String,
+    pub uid: Option<u32>,
+    pub installer: Option<String>,
+    pub flags: u32,
+    pub private_flags: u32,
+    // 签名证书 DER 的 SHA-256，与 apksigner 输出的证书摘要一致
+    pub cert_digests: Vec<String>,
+    pub enabled_components: Vec<String>,
+    pub disabled_components: Vec<String>,
+    // 来自 package-restrictions.xml：应用对 0 号用户是否已卸载或被隐藏
+    pub installed: bool,
+    pub hidden: bool,
+}
+
+impl InstalledPackage {
+    fn from_element(element: &xml::Element) -> Self {
+        // 整数属性在 ABX 中按有符号数写出，标志位最高位可能为 1
+        let flags = |name: &str| element.attribute(name)
+            .and_then(|value| value.parse::<i64>().ok())
+            .map(|value| value as u32);
+
+        InstalledPackage {
+            name: element.attribute("name").unwrap_or_default().to_string(),
+            code_path: element.attribute("codePath").unwrap_or_default().to_string(),
+            uid: element.attribute("userId")
+                .or_else(|| element.attribute("sharedUserId"))
+                .and_then(|uid| uid.parse().ok()),
+            installer: element.attribute("installer").filter(|installer| !installer.is_empty()).map(|s| s.to_string()),
+            flags: flags("publicFlags").or_else(|| flags("flags")).unwrap_or(0),
+            private_flags: flags("privateFlags").unwrap_or(0),
+            installed: element.attribute("inst") != Some("false"),
+            hidden: element.attribute("hidden") == Some("true"),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.flags & FLAG_SYSTEM != 0
+    }
+
+    // 与 cmd package list packages 一致，不包含对当前用户卸载或隐藏的应用
+    pub fn is_visible(&self) -> bool {
+        self.installed && !self.hidden
+    }
+}
+
+// 证书完整内容只在第一次出现时写出，之后的 <cert> 只有序号，因此读完整个文件后再统一解析
+fn package_elements(events: &[xml::Event], tag: &str) -> AppResult<Vec<InstalledPackage>> {
+    let mut packages = Vec::new();
+    let mut cert_indexes = Vec::new();
+    let mut cert_digests = HashMap::new();
+    let mut current: Option<(InstalledPackage, Vec<usize>)> = None;
+    let mut stack: Vec<&str> = Vec::new();
+
+    for event in events {
+        match event {
+            xml::Event::Start(element) => {
+                match (element.name.as_str(), stack.last().copied()) {
+                    (name, _) if name == tag && stack.len() == 1 => {
+                        current = Some((InstalledPackage::from_element(element), Vec::new()));
+                    }
+                    ("cert", Some("sigs")) => {
+                        let index = element.attribute("index")
+                            .and_then(|index| index.parse::<usize>().ok())
+                            .ok_or("packages.xml 中的证书缺少序号")?;
+                        if let Some(key) = element.attribute("key").and_then(der::from_hex) {
+                            cert_digests.entry(index).or_insert_with(|| der::to_hex(&Sha256::digest(&key)));
+                        }
+                        if let (Some((_, indexes)), 3) = (current.as_mut(), stack.len()) {
+                            indexes.push(index);
+                        }
+                    }
+                    ("item", Some(list @ ("enabled-components" | "disabled-components"))) if stack.len() == 3 => {
+                        if let (Some((package, _)), Some(name)) = (current.as_mut(), element.attribute("name")) {
+                            let components = if list == "enabled-components" {
+                                &mut package.enabled_components
+                            } else {
+                                &mut package.disabled_components
+                            };
+                            components.push(name.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+                stack.push(&element.name);
+            }
+            xml::Event::End(name) => {
+                if stack.pop() != Some(name.as_str()) {
+                    return Err(format!("XML 标签 {} 没有正确闭合", name).into());
+                }
+                if name == tag && stack.len() == 1 && let Some((package, indexes)) = current.take() {
+                    packages.push(package);
+                    cert_indexes.push(indexes);
+                }
+            }
+        }
+    }
+
+    for (package, indexes) in packages.iter_mut().zip(cert_indexes) {
+        package.cert_digests = indexes.iter()
+            .filter_map(|index| cert_digests.get(index).cloned())
+            .collect();
+    }
+    Ok(packages)
+}
+
+// 同时支持文本 XML 和 Android 12 起的 ABX 格式
+pub fn parse_packages_xml(data: &[u8]) -> AppResult<Vec<InstalledPackage>> {
+    package_elements(&xml::parse(data)?, "package")
+}
+
+// package-restrictions.xml 记录每个用户的安装状态以及单独启用或禁用的组件
+pub fn apply_package_restrictions(packages: &mut [InstalledPackage], data: &[u8]) -> AppResult {
+    for restriction in package_elements(&xml::parse(data)?, "pkg")? {
+        let Some(package) = packages.iter_mut().find(|package| package.name == restriction.name) else {
+            continue;
+        };
+        package.installed = restriction.installed;
+        package.hidden = restriction.hidden;
+        for component in restriction.enabled_components {
+            if !package.enabled_components.contains(&component) {
+                package.enabled_components.push(component);
+            }
+        }
+        for component in restriction.disabled_components {
+            if !package.disabled_components.contains(&component) {
+                package.disabled_components.push(component);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 组件状态文件缺失或损坏时只影响组件信息，不影响包列表
+pub fn load_packages_xml(packages_xml: &Path, package_restrictions: &Path) -> AppResult<Vec<InstalledPackage>> {
+    let data = fs::read(packages_xml).map_err(|e| format!("读取 {} 失败: {}", packages_xml.display(), e))?;
+    let mut packages = parse_packages_xml(&data)
+        .map_err(|e| format!("解析 {} 失败: {}", packages_xml.display(), e))?;
+
+    if let Ok(restrictions) = fs::read(package_restrictions)
+        && let Err(e) = apply_package_restrictions(&mut packages, &restrictions)
+    {
+        eprintln!("解析 {} 失败: {}", package_restrictions.display(), e);
+    }
+    Ok(packages)
+}
+
+// 解析 dumpsys package 输出中每个 "Package [包名]" 段落的 userId
+pub fn parse_dumpsys_user_ids(output: &str) -> Vec<(String, u32)> {
+    let mut result = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in output.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Package [") {
+            current = rest.split(']').next().map(|name| name.to_string());
+            continue;
+        }
+
+        let Some(value) = line.strip_prefix("userId=").or_else(|| line.strip_prefix("appId=")) else {
+            continue;
+        };
+        let uid = value.split_whitespace().next().and_then(|uid| uid.parse().ok());
+        if let (Some(package), Some(uid)) = (current.take(), uid) {
+            result.push((package, uid));
+        }
+    }
+
+    result
+}
+
+pub fn dumpsys_user_id(package_name: &str) -> Option<u32> {
+    let output = Command::new("dumpsys").arg("package").arg(package_name).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    parse_dumpsys_user_ids(&String::from_utf8_lossy(&output.stdout))
+        .into_iter()
+        .find(|(package, _)| package == package_name)
+        .map(|(_, uid)| uid)
+}
+
+pub fn data_dir_owner(data_dir: &str) -> Option<u32> {
+    fs::metadata(data_dir).ok().map(|metadata| metadata.uid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_bytes(name: &str) -> Vec<u8> {
+        fs::read(format!("{}/tests/fixtures/packages/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn fixture(name: &str) -> String {
+        String::from_utf8(fixture_bytes(name)).unwrap()
+    }
+
+    const USER_CERT: &str = "5faa4eec3611556812c2d74b437c8c49add3f910f10063d801441f7d75cd5e3b";
+    const PLATFORM_CERT: &str = "25a36b76473db166d18e320e410a69e20aac49c38f4f6ee2972c359f64479761";
+
+    #[test]
+    fn parses_packages_list() {
+        let entries = parse_packages_list(&fixture("packages.list"));
+        let parsed: Vec<_> = entries.iter().map(|entry| (entry.name.as_str(), entry.uid, entry.data_dir.as_str())).collect();
+        assert_eq!(parsed, [
+            ("com.android.shell", 2000, "/data/user_de/0/com.android.shell"),
+            ("icu.nullptr.hidemyapplist", 10213, "/data/user/0/icu.nullptr.hidemyapplist"),
+            ("com.example.renamed", 10245, "/data/user/0/com.example.renamed"),
+            ("io.github.vvb2060.mahoshojo", 10250, "/data/user/0/io.github.vvb2060.mahoshojo"),
+        ]);
+    }
+
+    #[test]
+    fn parses_dumpsys_user_ids() {
+        let ids = parse_dumpsys_user_ids(&fixture("dumpsys_package.txt"));
+        assert_eq!(ids, [
+            ("icu.nullptr.hidemyapplist".to_string(), 10213),
+            ("com.example.renamed".to_string(), 10245),
+            ("com.android.shell".to_string(), 2000),
+        ]);
+    }
+
+    #[test]
+    fn parses_text_packages_xml() {
+        let packages = parse_packages_xml(&fixture_bytes("packages.xml")).unwrap();
+        let names: Vec<_> = packages.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(names, ["com.foo", "android", "com.bar&baz", "com.removed", "com.hidden"]);
+
+        let foo = &packages[0];
+        assert_eq!(foo.code_path, "/data/app/~~kQ2i==/com.foo-Xb1s==");
+        assert_eq!(foo.uid, Some(10123));
+        assert_eq!(foo.installer.as_deref(), Some("com.android.vending"));
+        assert_eq!(foo.cert_digests, [USER_CERT]);
+        assert_eq!(foo.disabled_components, ["com.foo.Tracker"]);
+        assert!(!foo.is_system());
+        assert!(foo.is_visible());
+
+        // publicFlags 最高位为 1 时以负数写出
+        let android = &packages[1];
+        assert_eq!(android.flags, 0xc008_4209);
+        assert_eq!(android.private_flags, 8);
+        assert_eq!(android.uid, Some(1000));
+        assert!(android.is_system());
+
+        // 只写了序号的证书引用前面或后面出现的完整证书
+        assert_eq!(android.cert_digests, [PLATFORM_CERT]);
+        assert_eq!(packages[2].cert_digests, [USER_CERT]);
+        assert_eq!(packages[4].cert_digests, [PLATFORM_CERT]);
+    }
+
+    #[test]
+    fn parses_abx_packages_xml() {
+        let data = fixture_bytes("packages.abx");
+        assert!(xml::is_abx(&data));
+
+        let abx = parse_packages_xml(&data).unwrap();
+        let text = parse_packages_xml(&fixture_bytes("packages.xml")).unwrap();
+        assert_eq!(abx, text[..3]);
+    }
+
+    #[test]
+    fn applies_package_restrictions() {
+        let mut packages = parse_packages_xml(&fixture_bytes("packages.xml")).unwrap();
+        apply_package_restrictions(&mut packages, &fixture_bytes("package-restrictions.xml")).unwrap();
+
+        let foo = &packages[0];
+        assert_eq!(foo.enabled_components, ["com.foo.Main"]);
+        assert_eq!(foo.disabled_components, ["com.foo.Tracker", "com.foo.Ads"]);
+
+        let visible: Vec<_> = packages.iter()
+            .filter(|package| package.is_visible())
+            .map(|package| package.name.as_str())
+            .collect();
+        assert_eq!(visible, ["com.foo", "android", "com.bar&baz"]);
+        assert!(!packages[3].installed);
+        assert!(packages[4].installed && packages[4].hidden);
+    }
+}
@@ -0,0 +1,388 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{der, utc_timestamp, AppResult};
+
+pub const BY_NAME_DIRS: [&str; 2] = ["/dev/block/by-name", "/dev/block/bootdevice/by-name"];
+pub const BACKUP_DIR: &str = "/sdcard/一键解决隐藏问题/partition_backups";
+
+// O_DIRECT 要求缓冲区地址、偏移和长度都按逻辑块对齐，4096 覆盖常见的块大小
+const IO_ALIGN: usize = 4096;
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+// 非 A/B 设备或名称已带槽位后缀时原样返回
+pub fn slotted_name(name: &str, slot_suffix: Option<&str>) -> String {
+    match slot_suffix {
+        Some(suffix) if !suffix.is_empty() && !name.ends_with("_a") && !name.ends_with("_b") => {
+            format!("{}{}", name, suffix)
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl Partition {
+    pub fn at_path(name: &str, path: &Path) -> AppResult<Self> {
+        let size = File::open(path)
+            .and_then(|mut file| file.seek(SeekFrom::End(0)))
+            .map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+        Ok(Partition {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            size,
+        })
+    }
+}
+
+// 先查找带槽位后缀的分区，找不到时再查找不分槽位的同名分区（如 persist）
+pub fn resolve(dirs: &[PathBuf], name: &str, slot_suffix: Option<&str>) -> AppResult<Partition> {
+    let slotted = slotted_name(name, slot_suffix);
+    let candidates = if slotted == name { vec![slotted] } else { vec![slotted, name.to_string()] };
+
+    for candidate in &candidates {
+        for dir in dirs {
+            let path = dir.join(candidate);
+            if !path.exists() {
+                continue;
+            }
+            return Partition::at_path(candidate, &path);
+        }
+    }
+
+    Err(format!("未找到分区 {}", candidates.join(" / ")).into())
+}
+
+fn hash_prefix(path: &Path, length: u64) -> AppResult<String> {
+    let file = File::open(path)?;
+    let mut reader = file.take(length);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut total = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+    }
+    if total != length {
+        return Err(format!("{} 只读取到 {} 字节，应为 {} 字节", path.display(), total, length).into());
+    }
+    Ok(der::to_hex(&hasher.finalize()))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupMeta {
+    pub partition: String,
+    pub device: String,
+    pub size: u64,
+    pub sha256: String,
+    pub created: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub id: String,
+    pub image: PathBuf,
+    pub meta: BackupMeta,
+}
+
+impl Backup {
+    fn load(meta_path: &Path) -> AppResult<Self> {
+        let content = fs::read_to_string(meta_path)?;
+        let meta: BackupMeta = serde_json::from_str(&content)
+            .map_err(|e| format!("备份信息 {} 无效: {}", meta_path.display(), e))?;
+        let id = meta_path.file_stem().ok_or("无效的备份路径")?.to_string_lossy().into_owned();
+        Ok(Backup {
+            id,
+            image: meta_path.with_extension("img"),
+            meta,
+        })
+    }
+
+    pub fn verify(&self) -> AppResult {
+        let size = fs::metadata(&self.image)
+            .map_err(|e| format!("无法读取备份镜像 {}: {}", self.image.display(), e))?
+            .len();
+        if size != self.meta.size {
+            return Err(format!("备份镜像大小为 {} 字节，应为 {} 字节", size, self.meta.size).into());
+        }
+        if hash_prefix(&self.image, size)? != self.meta.sha256 {
+            return Err(format!("备份镜像 {} 的哈希值不匹配", self.image.display()).into());
+        }
+        Ok(())
+    }
+}
+
+// 完整复制分区到 <分区名>-<时间>.img，写入后重新计算哈希确认备份可用
+pub fn create_backup(partition: &Partition, backup_dir: &Path) -> AppResult<Backup> {
+    fs::create_dir_all(backup_dir).map_err(|e| format!("创建备份目录失败: {}", e))?;
+
+    let timestamp = utc_timestamp();
+    let mut id = format!("{}-{}", partition.name, timestamp);
+    let mut suffix = 1;
+    while backup_dir.join(format!("{}.json", id)).exists() {
+        suffix += 1;
+        id = format!("{}-{}-{}", partition.name, timestamp, suffix);
+    }
+    let image = backup_dir.join(format!("{}.img", id));
+
+    let result = (|| -> AppResult<String> {
+        let mut source = File::open(&partition.path)?.take(partition.size);
+        let mut target = File::create(&image)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; CHUNK_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let read = source.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            target.write_all(&buffer[..read])?;
+            copied += read as u64;
+        }
+        target.sync_all()?;
+        if copied != partition.size {
+            return Err(format!("只读取到 {} 字节，分区大小为 {} 字节", copied, partition.size).into());
+        }
+        Ok(der::to_hex(&hasher.finalize()))
+    })();
+
+    let sha256 = match result {
+        Ok(sha256) => sha256,
+        Err(e) => {
+            let _ = fs::remove_file(&image);
+            return Err(format!("备份 {} 失败: {}", partition.name, e).into());
+        }
+    };
+
+    let backup = Backup {
+        id,
+        image: image.clone(),
+        meta: BackupMeta {
+            partition: partition.name.clone(),
+            device: partition.path.to_string_lossy().into_owned(),
+            size: partition.size,
+            sha256,
+            created: timestamp,
+        },
+    };
+
+    let meta_path = image.with_extension("json");
+    let written = serde_json::to_string_pretty(&backup.meta)
+        .map_err(|e| e.into())
+        .and_then(|content| fs::write(&meta_path, content).map_err(|e| e.into()))
+        .and_then(|_| backup.verify());
+    if let Err(e) = written {
+        let _ = fs::remove_file(&image);
+        let _ = fs::remove_file(&meta_path);
+        return Err(format!("备份 {} 校验失败: {}", partition.name, e).into());
+    }
+
+    Ok(backup)
+}
+
+pub fn list_backups(backup_dir: &Path) -> Vec<Backup> {
+    let mut backups: Vec<Backup> = fs::read_dir(backup_dir)
+        .map(|entries| {
+            entries.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .filter_map(|path| Backup::load(&path).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort_by(|a, b| b.meta.created.cmp(&a.meta.created).then_with(|| b.id.cmp(&a.id)));
+    backups
+}
+
+// 既可以是备份 id，也可以是 .img / .json 文件路径
+pub fn find_backup(backup_dir: &Path, reference: &str) -> AppResult<Backup> {
+    let path = Path::new(reference);
+    let meta_path = if path.is_file() {
+        path.with_extension("json")
+    } else {
+        backup_dir.join(format!("{}.json", reference))
+    };
+    if !meta_path.is_file() {
+        return Err(format!("未找到备份 {}", reference).into());
+    }
+    Backup::load(&meta_path)
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EINVAL)
+}
+
+// 对齐部分使用 O_DIRECT 写入，设备或文件系统不支持时退回普通写入；返回是否使用了 O_DIRECT
+fn write_direct(path: &Path, data: &[u8]) -> AppResult<bool> {
+    let aligned_length = data.len() / IO_ALIGN * IO_ALIGN;
+    if aligned_length == 0 {
+        return Ok(false);
+    }
+
+    let mut file = match OpenOptions::new().write(true).custom_flags(libc::O_DIRECT | libc::O_SYNC).open(path) {
+        Ok(file) => file,
+        Err(e) if is_unsupported(&e) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Vec 本身不保证对齐，多分配一块后从对齐的位置开始使用
+    let mut storage = vec![0u8; CHUNK_SIZE + IO_ALIGN];
+    let start = storage.as_ptr().align_offset(IO_ALIGN);
+    let buffer = &mut storage[start..start + CHUNK_SIZE];
+
+    let mut offset = 0;
+    while offset < aligned_length {
+        let length = (aligned_length - offset).min(CHUNK_SIZE);
+        buffer[..length].copy_from_slice(&data[offset..offset + length]);
+        match file.write_all(&buffer[..length]) {
+            Ok(()) => {}
+            Err(e) if offset == 0 && is_unsupported(&e) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        offset += length;
+    }
+    file.sync_all()?;
+    Ok(true)
+}
+
+fn drop_page_cache(file: &File) {
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+// 写入后丢弃页缓存再读回比较，确保校验的是设备上的数据
+pub fn write_image(partition: &Partition, data: &[u8]) -> AppResult<bool> {
+    if data.len() as u64 > partition.size {
+        return Err(format!("镜像大小 {} 字节超过分区 {} 的 {} 字节", data.len(), partition.name, partition.size).into());
+    }
+
+    let direct = write_direct(&partition.path, data)?;
+    let written = if direct { data.len() / IO_ALIGN * IO_ALIGN } else { 0 };
+
+    let mut file = OpenOptions::new().write(true).open(&partition.path)?;
+    if written < data.len() {
+        file.seek(SeekFrom::Start(written as u64))?;
+        file.write_all(&data[written..])?;
+    }
+    file.sync_all()?;
+    drop_page_cache(&file);
+
+    let expected = der::to_hex(&Sha256::digest(data));
+    let actual = hash_prefix(&partition.path, data.len() as u64)?;
+    if actual != expected {
+        return Err(format!("{} 写入后读回的哈希值不匹配", partition.name).into());
+    }
+    Ok(direct)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每个测试使用独立的目录，避免并行运行时互相覆盖
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rshy-partition-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pattern(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+    }
+
+    #[test]
+    fn backs_up_writes_and_restores() {
+        let dir = scratch_dir("roundtrip");
+        let device = dir.join("boot_a");
+        let original = pattern(3 * IO_ALIGN + 123, 7);
+        fs::write(&device, &original).unwrap();
+
+        let partition = Partition::at_path("boot_a", &device).unwrap();
+        assert_eq!(partition.size, original.len() as u64);
+
+        let backup = create_backup(&partition, &dir.join("backups")).unwrap();
+        assert_eq!(backup.meta.sha256, der::to_hex(&Sha256::digest(&original)));
+        assert_eq!(fs::read(&backup.image).unwrap(), original);
+
+        // 比分区小的镜像只覆盖开头，其余内容保持不变
+        let image = pattern(2 * IO_ALIGN + 10, 99);
+        write_image(&partition, &image).unwrap();
+        let written = fs::read(&device).unwrap();
+        assert_eq!(&written[..image.len()], image.as_slice());
+        assert_eq!(&written[image.len()..], &original[image.len()..]);
+
+        let found = find_backup(&dir.join("backups"), &backup.id).unwrap();
+        assert_eq!(found.id, backup.id);
+        found.verify().unwrap();
+        let target = Partition::at_path(&found.meta.partition, Path::new(&found.meta.device)).unwrap();
+        write_image(&target, &fs::read(&found.image).unwrap()).unwrap();
+        assert_eq!(fs::read(&device).unwrap(), original);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn detects_damaged_backup() {
+        let dir = scratch_dir("damaged");
+        let device = dir.join("persist");
+        fs::write(&device, pattern(IO_ALIGN, 1)).unwrap();
+
+        let partition = Partition::at_path("persist", &device).unwrap();
+        let backup = create_backup(&partition, &dir).unwrap();
+        let mut image = fs::read(&backup.image).unwrap();
+        image[10] ^= 0xff;
+        fs::write(&backup.image, image).unwrap();
+
+        let listed = list_backups(&dir);
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].verify().unwrap_err().to_string().contains("哈希值不匹配"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rejects_oversized_image() {
+        let dir = scratch_dir("oversized");
+        let device = dir.join("dtbo_b");
+        fs::write(&device, [0u8; 512]).unwrap();
+
+        let partition = Partition::at_path("dtbo_b", &device).unwrap();
+        assert!(write_image(&partition, &[1u8; 513]).is_err());
+        assert_eq!(fs::read(&device).unwrap(), [0u8; 512]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resolves_slotted_partitions() {
+        let dir = scratch_dir("resolve");
+        fs::write(dir.join("boot_b"), [0u8; 16]).unwrap();
+        fs::write(dir.join("persist"), [0u8; 8]).unwrap();
+        let dirs = [dir.clone()];
+
+        assert_eq!(resolve(&dirs, "boot", Some("_b")).unwrap().name, "boot_b");
+        assert_eq!(resolve(&dirs, "persist", Some("_b")).unwrap().size, 8);
+        assert!(resolve(&dirs, "boot", Some("_a")).is_err());
+        assert_eq!(slotted_name("boot_a", Some("_b")), "boot_a");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
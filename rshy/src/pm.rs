@@ -0,0 +1,388 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{packages, tools, AppResult};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PackageFilter {
+    All,
+    ThirdParty,
+    System,
+    Uid(u32),
+}
+
+impl PackageFilter {
+    fn cmd_args(&self) -> Vec<String> {
+        match self {
+            PackageFilter::All => Vec::new(),
+            PackageFilter::ThirdParty => vec!["-3".to_string()],
+            PackageFilter::System => vec!["-s".to_string()],
+            PackageFilter::Uid(uid) => vec!["--uid".to_string(), uid.to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageInfo {
+    pub name: String,
+    pub uid: Option<u32>,
+    // 安装目录或单个 APK 的路径，仅来自 packages.list 时未知
+    pub code_path: Option<PathBuf>,
+}
+
+impl From<&packages::InstalledPackage> for PackageInfo {
+    fn from(package: &packages::InstalledPackage) -> Self {
+        PackageInfo {
+            name: package.name.clone(),
+            uid: package.uid,
+            code_path: (!package.code_path.is_empty()).then(|| PathBuf::from(&package.code_path)),
+        }
+    }
+}
+
+impl PackageInfo {
+    pub fn base_apk(&self) -> Option<PathBuf> {
+        let code_path = self.code_path.as_ref()?;
+        if code_path.extension().is_some_and(|ext| ext == "apk") {
+            Some(code_path.clone())
+        } else {
+            Some(code_path.join("base.apk"))
+        }
+    }
+}
+
+// 所有需要修改系统状态的操作都通过它执行，测试时可以替换为记录参数的实现
+pub trait CommandExecutor {
+    fn execute(&self, args: &[&str]) -> AppResult<String>;
+}
+
+// 使用内置的 cmd 工具，等同于 shell 中的 cmd <service> ...
+pub struct CmdExecutor;
+
+impl CommandExecutor for CmdExecutor {
+    fn execute(&self, args: &[&str]) -> AppResult<String> {
+        let output = tools::output("cmd", args)?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+            return Err(format!("cmd {} 执行失败: {}", args.join(" "), message).into());
+        }
+        Ok(stdout)
+    }
+}
+
+pub trait PackageManager {
+    fn list_packages(&self, filter: PackageFilter) -> AppResult<Vec<PackageInfo>>;
+    // 应用的所有 APK（base 与 split）
+    fn package_paths(&self, package: &str) -> AppResult<Vec<PathBuf>>;
+    fn uid_of(&self, package: &str) -> AppResult<Option<u32>>;
+    fn install(&self, apk: &Path) -> AppResult;
+    fn uninstall(&self, package: &str) -> AppResult;
+    // component 为 包名/类名 格式
+    fn disable_component(&self, component: &str) -> AppResult;
+    fn start_service(&self, component: &str) -> AppResult;
+}
+
+// 读取优先使用 packages.xml，其次 packages.list，都不可用时才调用 cmd
+pub struct SystemPackageManager<E: CommandExecutor = CmdExecutor> {
+    executor: E,
+    packages_list: PathBuf,
+    packages_xml: PathBuf,
+    package_restrictions: PathBuf,
+}
+
+pub fn system() -> SystemPackageManager {
+    SystemPackageManager::new(
+        CmdExecutor,
+        Path::new(packages::PACKAGES_LIST),
+        Path::new(packages::PACKAGES_XML),
+        Path::new(packages::PACKAGE_RESTRICTIONS_XML),
+    )
+}
+
+impl<E: CommandExecutor> SystemPackageManager<E> {
+    pub fn new(executor: E, packages_list: &Path, packages_xml: &Path, package_restrictions: &Path) -> Self {
+        SystemPackageManager {
+            executor,
+            packages_list: packages_list.to_path_buf(),
+            packages_xml: packages_xml.to_path_buf(),
+            package_restrictions: package_restrictions.to_path_buf(),
+        }
+    }
+
+    fn read_packages_list(&self) -> Option<Vec<packages::PackageEntry>> {
+        fs::read_to_string(&self.packages_list)
+            .ok()
+            .map(|content| packages::parse_packages_list(&content))
+    }
+
+    fn read_packages_xml(&self) -> Option<Vec<packages::InstalledPackage>> {
+        packages::load_packages_xml(&self.packages_xml, &self.package_restrictions)
+            .ok()
+            .map(|installed| installed.into_iter().filter(|package| package.is_visible()).collect())
+    }
+
+    // pm 在失败时也可能返回 0，只能通过输出中的 Failure 判断
+    fn write(&self, args: &[&str]) -> AppResult {
+        let output = self.executor.execute(args)?;
+        if let Some(line) = output.lines().find(|line| line.contains("Failure")) {
+            return Err(format!("cmd {} 执行失败: {}", args.join(" "), line.trim()).into());
+        }
+        Ok(())
+    }
+}
+
+// 每行格式: package:<路径>=<包名> uid:<uid>
+pub fn parse_package_list_output(output: &str) -> Vec<PackageInfo> {
+    output.lines()
+        .filter_map(|line| {
+            let line = line.trim().strip_prefix("package:")?;
+            let (line, uid) = match line.rsplit_once(" uid:") {
+                Some((rest, uid)) => (rest, uid.trim().parse().ok()),
+                None => (line, None),
+            };
+            let (code_path, name) = match line.rsplit_once('=') {
+                Some((path, name)) => (Some(PathBuf::from(path)), name),
+                None => (None, line),
+            };
+            let name = name.trim();
+            (!name.is_empty()).then(|| PackageInfo {
+                name: name.to_string(),
+                uid,
+                code_path,
+            })
+        })
+        .collect()
+}
+
+// 与 cmd package path 的顺序一致：base.apk 在前，split 按名称排序
+fn apks_in(code_path: &Path) -> Vec<PathBuf> {
+    if !code_path.is_dir() {
+        return vec![code_path.to_path_buf()];
+    }
+
+    let mut splits: Vec<PathBuf> = fs::read_dir(code_path)
+        .map(|entries| {
+            entries.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "apk") && !path.ends_with("base.apk"))
+                .collect()
+        })
+        .unwrap_or_default();
+    splits.sort();
+
+    let base = code_path.join("base.apk");
+    if base.exists() {
+        splits.insert(0, base);
+    }
+    splits
+}
+
+impl<E: CommandExecutor> PackageManager for SystemPackageManager<E> {
+    fn list_packages(&self, filter: PackageFilter) -> AppResult<Vec<PackageInfo>> {
+        if let Some(installed) = self.read_packages_xml() {
+            return Ok(installed.iter()
+                .filter(|package| match filter {
+                    PackageFilter::All => true,
+                    PackageFilter::ThirdParty => !package.is_system(),
+                    PackageFilter::System => package.is_system(),
+                    PackageFilter::Uid(uid) => package.uid == Some(uid),
+                })
+                .map(PackageInfo::from)
+                .collect());
+        }
+        if let (PackageFilter::Uid(uid), Some(entries)) = (filter, self.read_packages_list()) {
+            return Ok(entries.into_iter()
+                .filter(|entry| entry.uid == uid)
+                .map(|entry| PackageInfo {
+                    name: entry.name,
+                    uid: Some(entry.uid),
+                    code_path: None,
+                })
+                .collect());
+        }
+
+        let filter_args = filter.cmd_args();
+        let mut args = vec!["package", "list", "packages", "-f", "-U"];
+        args.extend(filter_args.iter().map(|arg| arg.as_str()));
+        Ok(parse_package_list_output(&self.executor.execute(&args)?))
+    }
+
+    fn package_paths(&self, package: &str) -> AppResult<Vec<PathBuf>> {
+        let code_path = self.read_packages_xml()
+            .and_then(|installed| installed.into_iter().find(|installed| installed.name == package))
+            .map(|installed| PathBuf::from(installed.code_path));
+        if let Some(code_path) = code_path.filter(|path| path.exists()) {
+            return Ok(apks_in(&code_path));
+        }
+
+        Ok(self.executor.execute(&["package", "path", package])?
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("package:"))
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    fn uid_of(&self, package: &str) -> AppResult<Option<u32>> {
+        if let Some(installed) = self.read_packages_xml() {
+            return Ok(installed.into_iter().find(|installed| installed.name == package).and_then(|installed| installed.uid));
+        }
+        if let Some(entries) = self.read_packages_list() {
+            return Ok(entries.into_iter().find(|entry| entry.name == package).map(|entry| entry.uid));
+        }
+        Ok(self.list_packages(PackageFilter::All)?
+            .into_iter()
+            .find(|info| info.name == package)
+            .and_then(|info| info.uid))
+    }
+
+    fn install(&self, apk: &Path) -> AppResult {
+        self.write(&["package", "install", "-r", &apk.to_string_lossy()])
+    }
+
+    fn uninstall(&self, package: &str) -> AppResult {
+        self.write(&["package", "uninstall", package])
+    }
+
+    fn disable_component(&self, component: &str) -> AppResult {
+        self.write(&["package", "disable", "--user", "0", component])
+    }
+
+    fn start_service(&self, component: &str) -> AppResult {
+        self.write(&["activity", "start-foreground-service", "-n", component])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // 记录每次调用的参数并返回固定输出
+    struct RecordingExecutor {
+        output: &'static str,
+        calls: RefCell<Vec<String>>,
+    }
+
+    impl RecordingExecutor {
+        fn new(output: &'static str) -> Self {
+            RecordingExecutor { output, calls: RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl CommandExecutor for &RecordingExecutor {
+        fn execute(&self, args: &[&str]) -> AppResult<String> {
+            self.calls.borrow_mut().push(args.join(" "));
+            Ok(self.output.to_string())
+        }
+    }
+
+    const MISSING: &str = "/nonexistent/rshy";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/packages").join(name)
+    }
+
+    fn manager<'a>(executor: &'a RecordingExecutor, packages_list: &Path, packages_xml: &Path) -> SystemPackageManager<&'a RecordingExecutor> {
+        SystemPackageManager::new(executor, packages_list, packages_xml, &fixture("package-restrictions.xml"))
+    }
+
+    fn names(packages: &[PackageInfo]) -> Vec<&str> {
+        packages.iter().map(|package| package.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_cmd_package_list() {
+        let output = "package:/data/app/~~kQ2i==/com.foo-Xb1s==/base.apk=com.foo uid:10123\n\
+                      package:/system/framework/framework-res.apk=android uid:1000\n\
+                      package:com.nopath\n\
+                      \n\
+                      Error: unexpected line\n";
+        let packages = parse_package_list_output(output);
+        assert_eq!(packages, [
+            PackageInfo {
+                name: "com.foo".to_string(),
+                uid: Some(10123),
+                code_path: Some(PathBuf::from("/data/app/~~kQ2i==/com.foo-Xb1s==/base.apk")),
+            },
+            PackageInfo {
+                name: "android".to_string(),
+                uid: Some(1000),
+                code_path: Some(PathBuf::from("/system/framework/framework-res.apk")),
+            },
+            PackageInfo { name: "com.nopath".to_string(), uid: None, code_path: None },
+        ]);
+        assert_eq!(packages[0].base_apk(), packages[0].code_path);
+    }
+
+    #[test]
+    fn prefers_packages_xml() {
+        let executor = RecordingExecutor::new("");
+        let pm = manager(&executor, &fixture("packages.list"), &fixture("packages.xml"));
+
+        assert_eq!(names(&pm.list_packages(PackageFilter::ThirdParty).unwrap()), ["com.foo", "com.bar&baz"]);
+        assert_eq!(names(&pm.list_packages(PackageFilter::System).unwrap()), ["android"]);
+        assert_eq!(names(&pm.list_packages(PackageFilter::Uid(1000)).unwrap()), ["android"]);
+        assert_eq!(pm.uid_of("com.foo").unwrap(), Some(10123));
+        // 对 0 号用户卸载或隐藏的应用不再出现
+        assert_eq!(pm.uid_of("com.removed").unwrap(), None);
+        assert_eq!(pm.uid_of("com.hidden").unwrap(), None);
+        assert!(executor.calls.borrow().is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_packages_list_then_cmd() {
+        let executor = RecordingExecutor::new("package:/data/app/com.baz-1/base.apk=com.baz uid:10300\n");
+        let pm = manager(&executor, &fixture("packages.list"), Path::new(MISSING));
+
+        // packages.list 只能回答 uid 查询
+        assert_eq!(names(&pm.list_packages(PackageFilter::Uid(10213)).unwrap()), ["icu.nullptr.hidemyapplist"]);
+        assert_eq!(pm.uid_of("com.example.renamed").unwrap(), Some(10245));
+        assert!(executor.calls.borrow().is_empty());
+
+        assert_eq!(names(&pm.list_packages(PackageFilter::ThirdParty).unwrap()), ["com.baz"]);
+        assert_eq!(*executor.calls.borrow(), ["package list packages -f -U -3"]);
+
+        let executor = RecordingExecutor::new("package:/data/app/com.baz-1/base.apk=com.baz uid:10300\n");
+        let pm = manager(&executor, Path::new(MISSING), Path::new(MISSING));
+        assert_eq!(pm.uid_of("com.baz").unwrap(), Some(10300));
+        assert_eq!(names(&pm.list_packages(PackageFilter::Uid(10300)).unwrap()), ["com.baz"]);
+        assert_eq!(*executor.calls.borrow(), ["package list packages -f -U", "package list packages -f -U --uid 10300"]);
+    }
+
+    #[test]
+    fn asks_cmd_for_paths_missing_on_disk() {
+        let executor = RecordingExecutor::new("package:/data/app/com.foo-1/base.apk\npackage:/data/app/com.foo-1/split_config.arm64_v8a.apk\n");
+        let pm = manager(&executor, Path::new(MISSING), &fixture("packages.xml"));
+
+        let paths = pm.package_paths("com.foo").unwrap();
+        assert_eq!(paths, [
+            PathBuf::from("/data/app/com.foo-1/base.apk"),
+            PathBuf::from("/data/app/com.foo-1/split_config.arm64_v8a.apk"),
+        ]);
+        assert_eq!(*executor.calls.borrow(), ["package path com.foo"]);
+    }
+
+    #[test]
+    fn detects_failure_in_write_output() {
+        let executor = RecordingExecutor::new("Performing Streamed Install\nFailure [INSTALL_FAILED_VERSION_DOWNGRADE]\n");
+        let pm = manager(&executor, Path::new(MISSING), Path::new(MISSING));
+        let error = pm.install(Path::new("/sdcard/app.apk")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "cmd package install -r /sdcard/app.apk 执行失败: Failure [INSTALL_FAILED_VERSION_DOWNGRADE]"
+        );
+
+        let executor = RecordingExecutor::new("Success\n");
+        let pm = manager(&executor, Path::new(MISSING), Path::new(MISSING));
+        pm.uninstall("com.foo").unwrap();
+        pm.disable_component("com.foo/.Tracker").unwrap();
+        pm.start_service("com.foo/.Service").unwrap();
+        assert_eq!(*executor.calls.borrow(), [
+            "package uninstall com.foo",
+            "package disable --user 0 com.foo/.Tracker",
+            "activity start-foreground-service -n com.foo/.Service",
+        ]);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use crate::AppResult;
+
+// 用户在确认提示中选择退出时返回，main 以 EXIT_ABORTED 退出，便于脚本区分取消和失败
+pub const EXIT_ABORTED: i32 = 2;
+
+#[derive(Debug)]
+pub struct Aborted;
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "你选择了退出")
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+#[derive(Debug)]
+struct Answers {
+    interactive: bool,
+    assume_yes: bool,
+    presets: HashMap<String, String>,
+}
+
+impl Default for Answers {
+    fn default() -> Self {
+        Answers {
+            interactive: true,
+            assume_yes: false,
+            presets: HashMap::new(),
+        }
+    }
+}
+
+static ANSWERS: OnceLock<Answers> = OnceLock::new();
+
+fn answers() -> &'static Answers {
+    ANSWERS.get_or_init(Answers::default)
+}
+
+// 解析位于子命令之前的全局选项，返回去掉这些选项后的参数
+pub fn init(args: &[String]) -> AppResult<Vec<String>> {
+    let mut answers = Answers::default();
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-y" | "--yes" => {
+                answers.interactive = false;
+                answers.assume_yes = true;
+            }
+            "--no-input" => {
+                answers.interactive = false;
+            }
+            "--answers" => {
+                let preset_file = match args.get(i + 1) {
+                    Some(path) => path,
+                    None => {
+                        eprintln!("--answers 需要指定预设应答文件");
+                        return Err("缺少预设应答文件".into());
+                    }
+                };
+                load_presets(preset_file, &mut answers.presets)?;
+                i += 1;
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let _ = ANSWERS.set(answers);
+
+    let mut rest = vec![args[0].clone()];
+    rest.extend_from_slice(&args[i..]);
+    Ok(rest)
+}
+
+fn load_presets(path: &str, presets: &mut HashMap<String, String>) -> AppResult {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("读取预设应答文件 {} 失败: {}", path, e);
+            return Err(e.into());
+        }
+    };
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                presets.insert(key.trim().to_string(), value.trim().to_string());
+            }
+            _ => {
+                eprintln!("预设应答文件第 {} 行格式错误: {}", index + 1, line);
+                return Err("预设应答文件格式错误".into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn missing_answer(key: &str) -> Box<dyn std::error::Error> {
+    let message = format!("非交互模式下缺少必需的应答: {}", key);
+    eprintln!("{}", message);
+    eprintln!("请在预设应答文件中添加 {}=<值>", key);
+    message.into()
+}
+
+fn read_input() -> AppResult<String> {
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input.trim().to_string())
+}
+
+fn parse_bool(key: &str, value: &str) -> AppResult<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Ok(true),
+        "false" | "no" | "n" | "0" => Ok(false),
+        _ => {
+            eprintln!("预设应答 {}={} 不是有效的布尔值", key, value);
+            Err(format!("无效的预设应答: {}", key).into())
+        }
+    }
+}
+
+// 供自行读取输入的调用方使用：有预设应答时返回它，非交互模式下缺少应答时报错，返回 None 表示需要交互输入
+pub fn preset(key: &str) -> AppResult<Option<&'static str>> {
+    let answers = answers();
+    if let Some(value) = answers.presets.get(key) {
+        return Ok(Some(value));
+    }
+    if !answers.interactive {
+        return Err(missing_answer(key));
+    }
+    Ok(None)
+}
+
+pub fn confirm(key: &str, question: &str, accept: impl Fn(&str) -> bool) -> AppResult<bool> {
+    let answers = answers();
+    println!("{}", question);
+
+    if let Some(value) = answers.presets.get(key) {
+        println!("使用预设应答: {}={}", key, value);
+        return parse_bool(key, value);
+    }
+
+    if answers.assume_yes {
+        println!("使用预设应答: {}=true", key);
+        return Ok(true);
+    }
+
+    if !answers.interactive {
+        return Err(missing_answer(key));
+    }
+
+    let input = read_input()?;
+    Ok(accept(&input))
+}
+
+fn find_option(options: &[String], answer: &str) -> Option<usize> {
+    match answer.parse::<usize>() {
+        Ok(choice) if choice > 0 && choice <= options.len() => Some(choice - 1),
+        _ => options.iter().position(|option| option == answer),
+    }
+}
+
+// 返回 None 表示交互输入无效，由调用方决定默认选项
+pub fn choose(key: &str, options: &[String]) -> AppResult<Option<usize>> {
+    let answers = answers();
+
+    if let Some(value) = answers.presets.get(key) {
+        return match find_option(options, value) {
+            Some(index) => {
+                println!("使用预设应答: {}={}", key, value);
+                Ok(Some(index))
+            }
+            None => {
+                eprintln!("预设应答 {}={} 不在可选项中", key, value);
+                Err(format!("无效的预设应答: {}", key).into())
+            }
+        };
+    }
+
+    if !answers.interactive {
+        return Err(missing_answer(key));
+    }
+
+    let input = read_input()?;
+    Ok(find_option(options, &input))
+}
@@ -0,0 +1,154 @@
+use std::env;
+use std::path::PathBuf;
+
+use crate::{AppResult, CdnNode};
+
+const DEFAULT_LANZOU_RESOLVERS: &[&str] = &["https://lz.qaiu.top/d/lz/{key}"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadSource {
+    Github {
+        owner: String,
+        repo: String,
+        path: String,
+        git_ref: String,
+    },
+    Lanzou {
+        share_key: String,
+    },
+    File {
+        path: PathBuf,
+    },
+    Http {
+        url: String,
+    },
+}
+
+impl DownloadSource {
+    pub fn parse(url: &str) -> AppResult<Self> {
+        if let Some(rest) = url.strip_prefix("github://") {
+            let (location, git_ref) = match rest.rsplit_once('@') {
+                Some((location, git_ref)) if !git_ref.is_empty() => (location, git_ref),
+                _ => (rest, "main"),
+            };
+
+            let mut parts = location.splitn(3, '/');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(owner), Some(repo), Some(path))
+                    if !owner.is_empty() && !repo.is_empty() && !path.is_empty() =>
+                {
+                    Ok(DownloadSource::Github {
+                        owner: owner.to_string(),
+                        repo: repo.to_string(),
+                        path: path.trim_start_matches('/').to_string(),
+                        git_ref: git_ref.to_string(),
+                    })
+                }
+                _ => {
+                    eprintln!("无效的 GitHub 地址: {}", url);
+                    eprintln!("格式应为 github://owner/repo/path@ref");
+                    Err("无效的 GitHub 地址".into())
+                }
+            }
+        } else if let Some(share_key) = url.strip_prefix("lanzou://") {
+            let share_key = share_key.trim_matches('/');
+            if share_key.is_empty() || share_key.contains('/') {
+                eprintln!("无效的蓝奏云分享码: {}", url);
+                return Err("无效的蓝奏云分享码".into());
+            }
+            Ok(DownloadSource::Lanzou {
+                share_key: share_key.to_string(),
+            })
+        } else if let Some(path) = url.strip_prefix("file://") {
+            if path.is_empty() {
+                eprintln!("无效的本地文件地址: {}", url);
+                return Err("无效的本地文件地址".into());
+            }
+            Ok(DownloadSource::File {
+                path: PathBuf::from(path),
+            })
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            Ok(DownloadSource::Http {
+                url: url.to_string(),
+            })
+        } else {
+            eprintln!("不支持的下载地址: {}", url);
+            Err("不支持的下载地址".into())
+        }
+    }
+
+    // 用于断点续传校验的资源标识，与实际使用的镜像无关
+    pub fn canonical_url(&self) -> String {
+        match self {
+            DownloadSource::Github { owner, repo, path, git_ref } => {
+                format!("https://github.com/{}/{}/raw/{}/{}", owner, repo, git_ref, path)
+            }
+            DownloadSource::Lanzou { share_key } => format!("lanzou://{}", share_key),
+            DownloadSource::File { path } => format!("file://{}", path.display()),
+            DownloadSource::Http { url } => url.clone(),
+        }
+    }
+
+    pub fn uses_github_cdn(&self) -> bool {
+        match self {
+            DownloadSource::Github { .. } => true,
+            DownloadSource::Http { url } => is_github_url(url),
+            _ => false,
+        }
+    }
+
+    pub fn mirrors(&self, cdn_nodes: &[CdnNode]) -> Vec<CdnNode> {
+        let original = CdnNode {
+            name: "原始URL".to_string(),
+            url: self.canonical_url(),
+        };
+
+        if self.uses_github_cdn() {
+            let mut mirrors: Vec<CdnNode> = cdn_nodes.iter()
+                .map(|node| CdnNode {
+                    name: node.name.clone(),
+                    url: format!("{}{}", node.url, original.url),
+                })
+                .collect();
+            mirrors.push(original);
+            return mirrors;
+        }
+
+        match self {
+            DownloadSource::Github { .. } | DownloadSource::Http { .. } => vec![original],
+            DownloadSource::Lanzou { share_key } => lanzou_resolvers()
+                .iter()
+                .enumerate()
+                .map(|(i, resolver)| CdnNode {
+                    name: format!("蓝奏云解析节点 {}", i + 1),
+                    url: resolver.replace("{key}", share_key),
+                })
+                .collect(),
+            DownloadSource::File { .. } => Vec::new(),
+        }
+    }
+}
+
+fn is_github_url(url: &str) -> bool {
+    let host = url.split("://")
+        .nth(1)
+        .and_then(|rest| rest.split('/').next())
+        .unwrap_or("");
+    matches!(host, "github.com" | "raw.githubusercontent.com" | "gist.githubusercontent.com")
+}
+
+// 可通过 RSHY_LANZOU_RESOLVERS 指定解析服务，多个用逗号分隔，{key} 会被替换为分享码
+fn lanzou_resolvers() -> Vec<String> {
+    let configured: Vec<String> = env::var("RSHY_LANZOU_RESOLVERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|resolver| resolver.trim().to_string())
+        .filter(|resolver| resolver.contains("{key}"))
+        .collect();
+
+    if configured.is_empty() {
+        DEFAULT_LANZOU_RESOLVERS.iter().map(|resolver| resolver.to_string()).collect()
+    } else {
+        configured
+    }
+}
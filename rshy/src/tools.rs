@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::bundle::{Asset, Bundle};
+use crate::{der, get_system_prop, AppResult};
+
+// 由 encoder 从 assets 目录生成: cargo run --bin encoder -- assets src/assets.zip
+static ASSET_BUNDLE: &[u8] = include_bytes!("assets.zip");
+
+// 刚写入的文件可能仍被其他线程 fork 出的子进程持有写句柄，exec 会短暂返回 ETXTBSY
+const TEXT_BUSY_RETRIES: u32 = 5;
+
+struct EmbeddedTool {
+    sha256: String,
+}
+
+pub fn bundle() -> AppResult<Bundle<'static>> {
+    Bundle::open(ASSET_BUNDLE).map_err(|e| format!("内置资源包损坏: {}", e).into())
+}
+
+// 读不到属性时（如在电脑上调试）按编译目标的架构选择
+fn host_abi() -> &'static str {
+    match std::env::consts::ARCH {
+        "aarch64" => "arm64-v8a",
+        "arm" => "armeabi-v7a",
+        "x86" => "x86",
+        other => other,
+    }
+}
+
+// abilist 按优先顺序排列，64 位设备通常也包含 32 位 ABI
+pub fn device_abis() -> &'static [String] {
+    static ABIS: OnceLock<Vec<String>> = OnceLock::new();
+    ABIS.get_or_init(|| {
+        let list = get_system_prop("ro.product.cpu.abilist").or_else(|| get_system_prop("ro.product.cpu.abi"));
+        let abis: Vec<String> = list.unwrap_or_default()
+            .split(',')
+            .map(|abi| abi.trim().to_string())
+            .filter(|abi| !abi.is_empty())
+            .collect();
+        if abis.is_empty() { vec![host_abi().to_string()] } else { abis }
+    })
+}
+
+// 优先选择设备最偏好的 ABI，都不匹配时才使用与架构无关的版本
+pub fn select_asset<'a>(assets: &'a [Asset], name: &str, abis: &[String]) -> AppResult<&'a Asset> {
+    let variants: Vec<&Asset> = assets.iter().filter(|asset| asset.name == name).collect();
+    if variants.is_empty() {
+        return Err(format!("未知的工具名: {}", name).into());
+    }
+
+    abis.iter()
+        .find_map(|abi| variants.iter().find(|asset| &asset.abi == abi))
+        .or_else(|| variants.iter().find(|asset| asset.abi == "any"))
+        .copied()
+        .ok_or_else(|| {
+            let available: Vec<&str> = variants.iter().map(|asset| asset.abi.as_str()).collect();
+            format!("工具 {} 没有适用于本机 ABI ({}) 的版本，内置版本: {}", name, abis.join(", "), available.join(", ")).into()
+        })
+}
+
+fn embedded_data(name: &str) -> AppResult<Vec<u8>> {
+    let bundle = bundle()?;
+    let asset = select_asset(&bundle.manifest.assets, name, device_abis())?;
+    bundle.read(asset)
+}
+
+enum Location {
+    // 只读打开的匿名内存文件，通过 /proc/self/fd/N 执行，不在文件系统上留下痕迹
+    Memfd(File),
+    File(PathBuf),
+}
+
+impl Location {
+    fn exec_path(&self) -> PathBuf {
+        match self {
+            Location::Memfd(file) => PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd())),
+            Location::File(path) => path.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ToolCache {
+    dir: Option<PathBuf>,
+    // memfd 无法创建或被 SELinux / noexec 拒绝执行后，本进程内不再尝试
+    memfd_blocked: bool,
+    tools: HashMap<String, (EmbeddedTool, Location)>,
+}
+
+static CACHE: OnceLock<Mutex<ToolCache>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<ToolCache> {
+    CACHE.get_or_init(|| Mutex::new(ToolCache::default()))
+}
+
+extern "C" fn cleanup_at_exit() {
+    cleanup();
+}
+
+// 每个进程一个只有 root 可访问的目录，进程退出时删除
+fn private_dir(cache: &mut ToolCache) -> AppResult<PathBuf> {
+    if let Some(dir) = &cache.dir {
+        return Ok(dir.clone());
+    }
+
+    let base = std::env::temp_dir();
+    let mut rng = rand::thread_rng();
+    for _ in 0..8 {
+        let dir = base.join(format!(".rshy-{}-{:08x}", std::process::id(), rng.gen_range(0..u32::MAX)));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {
+                cache.dir = Some(dir.clone());
+                unsafe { libc::atexit(cleanup_at_exit) };
+                return Ok(dir);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("创建工具目录 {} 失败: {}", dir.display(), e).into()),
+        }
+    }
+    Err("无法创建工具目录".into())
+}
+
+fn file_sha256(path: &Path) -> AppResult<String> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(der::to_hex(&Sha256::digest(&data)))
+}
+
+// 写入后加上封印禁止再修改，并只保留一个只读句柄，避免执行时出现 ETXTBSY
+fn extract_memfd(name: &str, data: &[u8]) -> AppResult<File> {
+    let c_name = CString::new(name)?;
+    let fd = unsafe { libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(data)?;
+
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(File::open(format!("/proc/self/fd/{}", fd))?)
+}
+
+fn extract_file(dir: &Path, name: &str, tool: &EmbeddedTool, data: &[u8]) -> AppResult<PathBuf> {
+    let path = dir.join(format!("{}-{}", name, &tool.sha256[..16]));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(&path)
+        .map_err(|e| format!("释放工具 {} 失败: {}", name, e))?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+fn extract(cache: &mut ToolCache, name: &str, tool: &EmbeddedTool, data: &[u8]) -> AppResult<Location> {
+    if !cache.memfd_blocked {
+        match extract_memfd(name, data) {
+            Ok(file) => return Ok(Location::Memfd(file)),
+            Err(_) => cache.memfd_blocked = true,
+        }
+    }
+    let dir = private_dir(cache)?;
+    Ok(Location::File(extract_file(&dir, name, tool, data)?))
+}
+
+// 返回已释放且通过哈希校验的工具路径，同一进程内只释放一次
+pub fn tool_path(name: &str) -> AppResult<PathBuf> {
+    let mut cache = cache().lock().map_err(|_| "工具缓存已损坏")?;
+    cached_path(&mut cache, name, embedded_data)
+}
+
+fn cached_path(cache: &mut ToolCache, name: &str, load: impl FnOnce(&str) -> AppResult<Vec<u8>>) -> AppResult<PathBuf> {
+    if !cache.tools.contains_key(name) {
+        let data = load(name)?;
+        let tool = EmbeddedTool {
+            sha256: der::to_hex(&Sha256::digest(&data)),
+        };
+        let location = extract(cache, name, &tool, &data)?;
+        cache.tools.insert(name.to_string(), (tool, location));
+    }
+
+    let (tool, location) = &cache.tools[name];
+    let path = location.exec_path();
+    let intact = match location {
+        Location::Memfd(_) => file_sha256(&path)? == tool.sha256,
+        Location::File(_) => {
+            let metadata = fs::symlink_metadata(&path).map_err(|e| format!("工具 {} 已丢失: {}", name, e))?;
+            metadata.is_file() && metadata.mode() & 0o022 == 0 && file_sha256(&path)? == tool.sha256
+        }
+    };
+    if !intact {
+        if let Some((_, Location::File(path))) = cache.tools.remove(name) {
+            let _ = fs::remove_file(path);
+        }
+        return Err(format!("工具 {} 校验失败，已被篡改", name).into());
+    }
+
+    Ok(path)
+}
+
+fn is_memfd(name: &str) -> bool {
+    cache().lock().is_ok_and(|cache| matches!(cache.tools.get(name), Some((_, Location::Memfd(_)))))
+}
+
+// memfd 执行被拒绝时改用私有目录，之后释放的工具也不再使用 memfd
+fn fall_back_to_dir(name: &str) {
+    if let Ok(mut cache) = cache().lock() {
+        cache.memfd_blocked = true;
+        cache.tools.remove(name);
+    }
+}
+
+fn run_with_retry<T>(name: &str, mut run: impl FnMut(&Path) -> std::io::Result<T>) -> AppResult<T> {
+    let mut path = tool_path(name)?;
+    let mut attempt = 0;
+    loop {
+        match run(&path) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EACCES | libc::EPERM)) && is_memfd(name) => {
+                fall_back_to_dir(name);
+                path = tool_path(name)?;
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENOEXEC) => {
+                return Err(format!("工具 {} 与本机 ABI ({}) 不兼容: {}", name, device_abis().join(", "), e).into());
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) && attempt < TEXT_BUSY_RETRIES => {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(10 * attempt as u64));
+            }
+            result => return result.map_err(|e| format!("执行工具 {} 失败: {}", name, e).into()),
+        }
+    }
+}
+
+pub fn output(name: &str, args: &[&str]) -> AppResult<Output> {
+    run_with_retry(name, |path| Command::new(path).args(args).output())
+}
+
+pub fn status(name: &str, args: &[String]) -> AppResult<ExitStatus> {
+    run_with_retry(name, |path| {
+        Command::new(path)
+            .args(args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+    })
+}
+
+pub fn cleanup() {
+    let Some(cache) = CACHE.get() else {
+        return;
+    };
+    // 退出时其他线程可能仍持有锁，此时放弃清理而不是卡住进程
+    let mut cache = match cache.try_lock() {
+        Ok(cache) => cache,
+        Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return,
+    };
+    clear(&mut cache);
+}
+
+fn clear(cache: &mut ToolCache) {
+    cache.tools.clear();
+    if let Some(dir) = cache.dir.take() {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_binary() -> Vec<u8> {
+        let path = ["/bin/true", "/usr/bin/true"].into_iter()
+            .find(|path| Path::new(path).exists())
+            .expect("no host true binary");
+        fs::read(path).unwrap()
+    }
+
+    fn asset(name: &str, abi: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            path: format!("{}/{}", abi, name),
+            abi: abi.to_string(),
+            version: "1".to_string(),
+            size: 0,
+            sha256: String::new(),
+        }
+    }
+
+    fn abis(list: &[&str]) -> Vec<String> {
+        list.iter().map(|abi| abi.to_string()).collect()
+    }
+
+    #[test]
+    fn selects_asset_by_abi_preference() {
+        let assets = [
+            asset("cmd", "armeabi-v7a"),
+            asset("cmd", "arm64-v8a"),
+            asset("service.sh", "any"),
+            asset("busybox", "x86_64"),
+            asset("busybox", "any"),
+        ];
+
+        // 按 abilist 的顺序选择，而不是资源包中的顺序
+        let selected = select_asset(&assets, "cmd", &abis(&["arm64-v8a", "armeabi-v7a"])).unwrap();
+        assert_eq!(selected.abi, "arm64-v8a");
+        let selected = select_asset(&assets, "cmd", &abis(&["armeabi-v7a", "armeabi"])).unwrap();
+        assert_eq!(selected.abi, "armeabi-v7a");
+
+        // 有匹配的 ABI 时优先于 any
+        assert_eq!(select_asset(&assets, "busybox", &abis(&["x86_64", "x86"])).unwrap().abi, "x86_64");
+        assert_eq!(select_asset(&assets, "busybox", &abis(&["arm64-v8a"])).unwrap().abi, "any");
+        assert_eq!(select_asset(&assets, "service.sh", &abis(&["x86"])).unwrap().abi, "any");
+
+        let error = select_asset(&assets, "cmd", &abis(&["x86_64", "x86"])).unwrap_err();
+        assert_eq!(error.to_string(), "工具 cmd 没有适用于本机 ABI (x86_64, x86) 的版本，内置版本: armeabi-v7a, arm64-v8a");
+        let error = select_asset(&assets, "magiskboot", &abis(&["arm64-v8a"])).unwrap_err();
+        assert_eq!(error.to_string(), "未知的工具名: magiskboot");
+    }
+
+    fn asset_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap().flatten() {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                asset_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    // 内置资源包是提交到仓库的生成文件，assets 目录改动后必须重新生成
+    #[test]
+    fn bundle_matches_assets_dir() {
+        let asset_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let hint = "src/assets.zip 与 assets 目录不一致，请执行 cargo run --bin encoder -- assets src/assets.zip";
+
+        let bundle = bundle().unwrap();
+        for asset in &bundle.manifest.assets {
+            let data = fs::read(asset_dir.join(&asset.path)).unwrap_or_else(|e| panic!("{}: {}: {}", hint, asset.path, e));
+            assert_eq!(data.len() as u64, asset.size, "{}: {}", hint, asset.path);
+            assert_eq!(crate::bundle::sha256_hex(&data), asset.sha256, "{}: {}", hint, asset.path);
+        }
+
+        let mut files = Vec::new();
+        asset_files(&asset_dir, &mut files);
+        let mut on_disk: Vec<String> = files.iter()
+            .map(|path| path.strip_prefix(&asset_dir).unwrap().to_string_lossy().into_owned())
+            .collect();
+        let mut bundled: Vec<String> = bundle.manifest.assets.iter().map(|asset| asset.path.clone()).collect();
+        on_disk.sort();
+        bundled.sort();
+        assert_eq!(on_disk, bundled, "{}", hint);
+    }
+
+    fn leftover_dirs() -> Vec<PathBuf> {
+        let prefix = format!(".rshy-{}-", std::process::id());
+        fs::read_dir(std::env::temp_dir()).unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect()
+    }
+
+    // memfd 路径和被拒绝后的私有目录路径都应能执行，清理后不在临时目录留下任何文件
+    #[test]
+    fn runs_host_binary_from_memfd_and_private_dir() {
+        let data = host_binary();
+
+        let mut cache = ToolCache::default();
+        let path = cached_path(&mut cache, "true", |_| Ok(data.clone())).unwrap();
+        assert!(matches!(cache.tools["true"].1, Location::Memfd(_)));
+        assert!(path.starts_with("/proc/self/fd"));
+        assert!(Command::new(&path).status().unwrap().success());
+        assert!(leftover_dirs().is_empty());
+        clear(&mut cache);
+
+        let mut cache = ToolCache { memfd_blocked: true, ..ToolCache::default() };
+        let path = cached_path(&mut cache, "true", |_| Ok(data.clone())).unwrap();
+        assert!(matches!(cache.tools["true"].1, Location::File(_)));
+        assert!(path.starts_with(std::env::temp_dir()));
+        assert_eq!(fs::metadata(path.parent().unwrap()).unwrap().mode() & 0o777, 0o700);
+        assert!(Command::new(&path).status().unwrap().success());
+
+        // 私有目录中的文件被改动后不再使用
+        fs::write(&path, b"#!/bin/sh\n").unwrap();
+        let error = cached_path(&mut cache, "true", |_| unreachable!()).unwrap_err();
+        assert_eq!(error.to_string(), "工具 true 校验失败，已被篡改");
+        assert!(!path.exists());
+
+        clear(&mut cache);
+        assert!(leftover_dirs().is_empty());
+    }
+}
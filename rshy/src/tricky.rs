@@ -0,0 +1,505 @@
+use std::fs;
+use std::path::Path;
+
+use crate::AppResult;
+
+pub const TRICKY_STORE_DIR: &str = "/data/adb/tricky_store";
+pub const TARGET_FILE: &str = "/data/adb/tricky_store/target.txt";
+pub const SECURITY_PATCH_FILE: &str = "/data/adb/tricky_store/security_patch.txt";
+pub const MODULE_PROP: &str = "/data/adb/modules/tricky_store/module.prop";
+
+// 各配置文件开始被 Tricky Store 读取的版本，按更新日志整理
+const TARGET_MODE_SINCE: (u32, u32, u32) = (1, 1, 0);
+const SECURITY_PATCH_SINCE: (u32, u32, u32) = (1, 2, 1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetMode {
+    Auto,
+    Generate,
+    LeafHack,
+}
+
+impl TargetMode {
+    pub fn parse(value: &str) -> AppResult<Self> {
+        match value {
+            "auto" => Ok(TargetMode::Auto),
+            "generate" | "!" => Ok(TargetMode::Generate),
+            "leaf" | "hack" | "?" => Ok(TargetMode::LeafHack),
+            _ => {
+                eprintln!("无效的模式: {} (可选 auto / generate / leaf)", value);
+                Err("无效的模式".into())
+            }
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            TargetMode::Auto => "",
+            TargetMode::Generate => "!",
+            TargetMode::LeafHack => "?",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TargetMode::Auto => "auto",
+            TargetMode::Generate => "generate",
+            TargetMode::LeafHack => "leaf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Entry { package: String, mode: TargetMode },
+    // 注释、空行等原样保留
+    Other(String),
+}
+
+fn parse_line(line: &str) -> Line {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.contains(char::is_whitespace) {
+        return Line::Other(line.to_string());
+    }
+
+    let (package, mode) = if let Some(package) = trimmed.strip_suffix('!') {
+        (package, TargetMode::Generate)
+    } else if let Some(package) = trimmed.strip_suffix('?') {
+        (package, TargetMode::LeafHack)
+    } else {
+        (trimmed, TargetMode::Auto)
+    };
+
+    if package.is_empty() {
+        Line::Other(line.to_string())
+    } else {
+        Line::Entry { package: package.to_string(), mode }
+    }
+}
+
+// 支持 * 通配符的包名匹配
+pub fn pattern_matches(pattern: &str, package: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == package;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !package.starts_with(first) || package.len() < first.len() + last.len() || !package.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &package[first.len()..package.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TargetList {
+    lines: Vec<Line>,
+}
+
+impl TargetList {
+    pub fn parse(content: &str) -> Self {
+        TargetList {
+            lines: content.lines().map(parse_line).collect(),
+        }
+    }
+
+    pub fn load(path: &str) -> AppResult<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                eprintln!("读取 {} 失败: {}", path, e);
+                Err(e.into())
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> AppResult {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Err(e) = fs::write(path, self.render()) {
+            eprintln!("写入 {} 失败: {}", path, e);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    pub fn render(&self) -> String {
+        let mut content: String = self.lines.iter()
+            .map(|line| match line {
+                Line::Entry { package, mode } => format!("{}{}\n", package, mode.suffix()),
+                Line::Other(text) => format!("{}\n", text),
+            })
+            .collect();
+        if content.is_empty() {
+            content.push('\n');
+        }
+        content
+    }
+
+    pub fn entries(&self) -> Vec<(&str, TargetMode)> {
+        self.lines.iter()
+            .filter_map(|line| match line {
+                Line::Entry { package, mode } => Some((package.as_str(), *mode)),
+                Line::Other(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, package: &str) -> bool {
+        self.entries().iter().any(|(existing, _)| *existing == package)
+    }
+
+    // 已存在时只更新模式，返回内容是否发生变化
+    pub fn add(&mut self, package: &str, mode: TargetMode) -> bool {
+        for line in &mut self.lines {
+            match line {
+                Line::Entry { package: existing, mode: existing_mode } if existing == package => {
+                    let changed = *existing_mode != mode;
+                    *existing_mode = mode;
+                    return changed;
+                }
+                _ => {}
+            }
+        }
+
+        while matches!(self.lines.last(), Some(Line::Other(text)) if text.trim().is_empty()) {
+            self.lines.pop();
+        }
+        self.lines.push(Line::Entry { package: package.to_string(), mode });
+        true
+    }
+
+    pub fn remove(&mut self, package: &str) -> bool {
+        let before = self.lines.len();
+        self.lines.retain(|line| !matches!(line, Line::Entry { package: existing, .. } if existing == package));
+        self.lines.len() != before
+    }
+
+    // 只添加缺少的应用，用户已有条目及其模式保持不变
+    pub fn sync(&mut self, installed: &[String], include: &[String], exclude: &[String], mode: TargetMode) -> Vec<String> {
+        let mut added = Vec::new();
+        for package in installed {
+            let included = include.is_empty() || include.iter().any(|pattern| pattern_matches(pattern, package));
+            let excluded = exclude.iter().any(|pattern| pattern_matches(pattern, package));
+            if !included || excluded || self.contains(package) {
+                continue;
+            }
+            self.add(package, mode);
+            added.push(package.clone());
+        }
+        added
+    }
+}
+
+// 接受 YYYY-MM-DD / YYYYMMDD / YYYY-MM / YYYYMM，统一为 YYYY-MM-DD
+pub fn normalize_patch_date(value: &str) -> Option<String> {
+    let digits: String = value.trim().chars().filter(|c| *c != '-').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let (year, month, day) = match digits.len() {
+        8 => (&digits[..4], &digits[4..6], &digits[6..8]),
+        6 => (&digits[..4], &digits[4..6], "01"),
+        _ => return None,
+    };
+    let month_value: u32 = month.parse().ok()?;
+    let day_value: u32 = day.parse().ok()?;
+    if !(1..=12).contains(&month_value) || !(1..=31).contains(&day_value) {
+        return None;
+    }
+    Some(format!("{}-{}-{}", year, month, day))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityPatch {
+    pub system: String,
+    pub boot: String,
+    pub vendor: String,
+}
+
+impl SecurityPatch {
+    // vendor 和 boot 缺失时沿用上一级的补丁日期，保证三者一致
+    pub fn from_props(system: Option<&str>, vendor: Option<&str>, boot: Option<&str>) -> Option<Self> {
+        let system = normalize_patch_date(system?)?;
+        let vendor = vendor.and_then(normalize_patch_date).unwrap_or_else(|| system.clone());
+        let boot = boot.and_then(normalize_patch_date).unwrap_or_else(|| vendor.clone());
+        Some(SecurityPatch { system, boot, vendor })
+    }
+
+    pub fn forced(date: &str) -> Option<Self> {
+        let date = normalize_patch_date(date)?;
+        Some(SecurityPatch {
+            system: date.clone(),
+            boot: date.clone(),
+            vendor: date,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        format!("system={}\nboot={}\nvendor={}\n", self.system, self.boot, self.vendor)
+    }
+}
+
+pub fn validate_security_patch(content: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => ("all", line),
+        };
+        if !matches!(key, "all" | "system" | "boot" | "vendor") {
+            problems.push(format!("security_patch.txt 第 {} 行: 未知的字段 {}", index + 1, key));
+        } else if !matches!(value, "prop" | "no") && normalize_patch_date(value).is_none() {
+            problems.push(format!("security_patch.txt 第 {} 行: 无效的日期 {}", index + 1, value));
+        }
+    }
+    problems
+}
+
+// 从 module.prop 的 version 字段解析出 (主, 次, 修订) 版本号
+pub fn parse_module_version(module_prop: &str) -> Option<(u32, u32, u32)> {
+    let version = module_prop.lines()
+        .find_map(|line| line.trim().strip_prefix("version="))?;
+    let numbers: Vec<u32> = version.trim()
+        .trim_start_matches(['v', 'V'])
+        .split(|c: char| !c.is_ascii_digit())
+        .take(3)
+        .map_while(|part| part.parse().ok())
+        .collect();
+
+    match numbers.as_slice() {
+        [major, minor, patch, ..] => Some((*major, *minor, *patch)),
+        [major, minor] => Some((*major, *minor, 0)),
+        _ => None,
+    }
+}
+
+// 返回 (错误, 警告)
+pub fn validate_config_files(dir: &str, version: Option<(u32, u32, u32)>) -> (Vec<String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let supports = |since: (u32, u32, u32)| version.is_none_or(|version| version >= since);
+
+    match fs::read_to_string(format!("{}/target.txt", dir)) {
+        Ok(content) => {
+            let targets = TargetList::parse(&content);
+            let moded = targets.entries().iter().filter(|(_, mode)| *mode != TargetMode::Auto).count();
+            if targets.entries().is_empty() {
+                warnings.push("target.txt 中没有任何应用".to_string());
+            }
+            if moded > 0 && !supports(TARGET_MODE_SINCE) {
+                warnings.push(format!("target.txt 中有 {} 个应用使用了 !/? 模式，当前版本的 Tricky Store 不支持", moded));
+            }
+        }
+        Err(_) => errors.push("缺少 target.txt".to_string()),
+    }
+
+    match fs::read_to_string(format!("{}/tee_status", dir)) {
+        Ok(content) if !matches!(content.trim(), "teeBroken=true" | "teeBroken=false") => {
+            errors.push(format!("tee_status 内容无效: {}", content.trim()));
+        }
+        _ => {}
+    }
+
+    if let Ok(content) = fs::read_to_string(format!("{}/security_patch.txt", dir)) {
+        errors.extend(validate_security_patch(&content));
+        if !supports(SECURITY_PATCH_SINCE) {
+            warnings.push(format!(
+                "当前版本的 Tricky Store 不读取 security_patch.txt (需要 v{}.{}.{} 及以上)",
+                SECURITY_PATCH_SINCE.0, SECURITY_PATCH_SINCE.1, SECURITY_PATCH_SINCE.2
+            ));
+        }
+    }
+
+    if !Path::new(&format!("{}/keybox.xml", dir)).exists() {
+        warnings.push("缺少 keybox.xml，将使用 Tricky Store 自带的软件密钥".to_string());
+    }
+
+    (errors, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("{}/tests/fixtures/tricky/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    #[test]
+    fn round_trips_target_list() {
+        let content = fixture("target.txt");
+        let targets = TargetList::parse(&content);
+        assert_eq!(targets.render(), content);
+        assert_eq!(targets.entries(), [
+            ("com.google.android.gms", TargetMode::Generate),
+            ("io.github.vvb2060.keyattestation", TargetMode::LeafHack),
+            ("com.android.vending", TargetMode::Auto),
+            ("com.android.vending", TargetMode::Auto),
+        ]);
+        assert!(!targets.contains("!"));
+        assert_eq!(TargetList::parse("").render(), "\n");
+    }
+
+    #[test]
+    fn edits_target_list_in_place() {
+        let mut targets = TargetList::parse(&fixture("target.txt"));
+
+        // 已有条目只改模式，重复的条目只改第一个
+        assert!(targets.add("com.android.vending", TargetMode::Generate));
+        assert!(!targets.add("com.android.vending", TargetMode::Generate));
+        assert!(targets.add("com.example.new", TargetMode::LeafHack));
+
+        // remove 会删除所有重复条目
+        assert!(targets.remove("io.github.vvb2060.keyattestation"));
+        assert!(!targets.remove("io.github.vvb2060.keyattestation"));
+
+        assert_eq!(targets.render(), "\
+# Tricky Store targets
+com.google.android.gms!
+
+com.android.vending!
+  # indented comment
+com.android.vending
+bad entry with spaces
+!
+com.example.new?
+");
+        assert!(targets.remove("com.android.vending"));
+        assert!(!targets.contains("com.android.vending"));
+    }
+
+    #[test]
+    fn matches_wildcard_patterns() {
+        assert!(pattern_matches("com.google.android.gms", "com.google.android.gms"));
+        assert!(!pattern_matches("com.google.android.gms", "com.google.android.gms.policy"));
+        assert!(pattern_matches("com.google.*", "com.google.android.gms"));
+        assert!(pattern_matches("*.gms", "com.google.android.gms"));
+        assert!(pattern_matches("com.*.android.*", "com.google.android.gms"));
+        assert!(pattern_matches("*", "anything"));
+        assert!(!pattern_matches("com.*.vending", "com.vending"));
+        assert!(!pattern_matches("com.google.*", "com.android.vending"));
+    }
+
+    #[test]
+    fn syncs_with_include_and_exclude() {
+        let installed: Vec<String> = ["com.google.android.gms", "com.google.android.youtube", "com.android.vending", "org.example.app"]
+            .iter()
+            .map(|package| package.to_string())
+            .collect();
+        let mut targets = TargetList::parse("com.google.android.gms!\n");
+
+        let added = targets.sync(
+            &installed,
+            &["com.google.*".to_string(), "com.android.*".to_string()],
+            &["*.youtube".to_string()],
+            TargetMode::LeafHack,
+        );
+        assert_eq!(added, ["com.android.vending"]);
+        assert_eq!(targets.render(), "com.google.android.gms!\ncom.android.vending?\n");
+
+        let added = targets.sync(&installed, &[], &[], TargetMode::Auto);
+        assert_eq!(added, ["com.google.android.youtube", "org.example.app"]);
+    }
+
+    #[test]
+    fn normalizes_patch_dates() {
+        assert_eq!(normalize_patch_date("2024-05-01").as_deref(), Some("2024-05-01"));
+        assert_eq!(normalize_patch_date(" 20240505 ").as_deref(), Some("2024-05-05"));
+        assert_eq!(normalize_patch_date("2024-05").as_deref(), Some("2024-05-01"));
+        assert_eq!(normalize_patch_date("202405").as_deref(), Some("2024-05-01"));
+        assert_eq!(normalize_patch_date("2024-13-01"), None);
+        assert_eq!(normalize_patch_date("2024-00"), None);
+        assert_eq!(normalize_patch_date("2024-05-32"), None);
+        assert_eq!(normalize_patch_date("2024/05/01"), None);
+        assert_eq!(normalize_patch_date("2024-5-1"), None);
+        assert_eq!(normalize_patch_date(""), None);
+    }
+
+    #[test]
+    fn builds_security_patch() {
+        let forced = SecurityPatch::forced("202405").unwrap();
+        assert_eq!(forced.render(), "system=2024-05-01\nboot=2024-05-01\nvendor=2024-05-01\n");
+        assert_eq!(SecurityPatch::forced("latest"), None);
+
+        // vendor 缺失沿用 system，boot 缺失沿用 vendor
+        let patch = SecurityPatch::from_props(Some("2024-05-05"), None, None).unwrap();
+        assert_eq!(patch, SecurityPatch::forced("2024-05-05").unwrap());
+        let patch = SecurityPatch::from_props(Some("2024-05-05"), Some("2024-03-01"), None).unwrap();
+        assert_eq!(patch.render(), "system=2024-05-05\nboot=2024-03-01\nvendor=2024-03-01\n");
+        let patch = SecurityPatch::from_props(Some("2024-05-05"), Some("invalid"), Some("2024-01")).unwrap();
+        assert_eq!(patch.render(), "system=2024-05-05\nboot=2024-01-01\nvendor=2024-05-05\n");
+        assert_eq!(SecurityPatch::from_props(None, Some("2024-03-01"), Some("2024-03-01")), None);
+        assert_eq!(SecurityPatch::from_props(Some("unknown"), None, None), None);
+    }
+
+    #[test]
+    fn validates_security_patch() {
+        assert!(validate_security_patch("# comment\n\n20240501\nsystem=prop\nboot=no\nvendor=2024-05\n").is_empty());
+        assert_eq!(validate_security_patch("all=2024-05-01\nkernel=2024-05-01\nboot=yesterday\n"), [
+            "security_patch.txt 第 2 行: 未知的字段 kernel",
+            "security_patch.txt 第 3 行: 无效的日期 yesterday",
+        ]);
+    }
+
+    #[test]
+    fn parses_module_version() {
+        assert_eq!(parse_module_version("id=tricky_store\nversion=v1.2.1 (123-abc-release)\n"), Some((1, 2, 1)));
+        assert_eq!(parse_module_version("version=1.1\n"), Some((1, 1, 0)));
+        assert_eq!(parse_module_version("version=v2\n"), None);
+        assert_eq!(parse_module_version("versionCode=121\n"), None);
+    }
+
+    #[test]
+    fn validates_config_files() {
+        let dir = std::env::temp_dir().join(format!("rshy-tricky-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_str().unwrap();
+        let write = |name: &str, content: &str| fs::write(dir.join(name), content).unwrap();
+
+        assert_eq!(validate_config_files(path, None), (
+            vec!["缺少 target.txt".to_string()],
+            vec!["缺少 keybox.xml，将使用 Tricky Store 自带的软件密钥".to_string()],
+        ));
+
+        write("target.txt", "# empty\n");
+        write("tee_status", "teeBroken=maybe\n");
+        write("security_patch.txt", "boot=tomorrow\n");
+        write("keybox.xml", "");
+        let (errors, warnings) = validate_config_files(path, None);
+        assert_eq!(errors, [
+            "tee_status 内容无效: teeBroken=maybe",
+            "security_patch.txt 第 1 行: 无效的日期 tomorrow",
+        ]);
+        assert_eq!(warnings, ["target.txt 中没有任何应用"]);
+
+        write("target.txt", &fixture("target.txt"));
+        write("tee_status", "teeBroken=false\n");
+        write("security_patch.txt", "2024-05-01\n");
+        assert_eq!(validate_config_files(path, Some((1, 2, 1))), (vec![], vec![]));
+        let (errors, warnings) = validate_config_files(path, Some((1, 0, 3)));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(warnings, [
+            "target.txt 中有 2 个应用使用了 !/? 模式，当前版本的 Tricky Store 不支持",
+            "当前版本的 Tricky Store 不读取 security_patch.txt (需要 v1.2.1 及以上)",
+        ]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
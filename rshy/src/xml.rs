@@ -0,0 +1,319 @@
+use base64::Engine;
+
+use crate::{der, AppResult};
+
+// Android 12 起 system_server 默认以 ABX (Android Binary XML) 格式保存 packages.xml 等文件
+const ABX_MAGIC: &[u8; 4] = b"ABX\0";
+
+const COMMAND_START_DOCUMENT: u8 = 0;
+const COMMAND_END_DOCUMENT: u8 = 1;
+const COMMAND_START_TAG: u8 = 2;
+const COMMAND_END_TAG: u8 = 3;
+const COMMAND_ATTRIBUTE: u8 = 15;
+
+const TYPE_NULL: u8 = 1 << 4;
+const TYPE_STRING: u8 = 2 << 4;
+const TYPE_STRING_INTERNED: u8 = 3 << 4;
+const TYPE_BYTES_HEX: u8 = 4 << 4;
+const TYPE_BYTES_BASE64: u8 = 5 << 4;
+const TYPE_INT: u8 = 6 << 4;
+const TYPE_INT_HEX: u8 = 7 << 4;
+const TYPE_LONG: u8 = 8 << 4;
+const TYPE_LONG_HEX: u8 = 9 << 4;
+const TYPE_FLOAT: u8 = 10 << 4;
+const TYPE_DOUBLE: u8 = 11 << 4;
+const TYPE_BOOLEAN_TRUE: u8 = 12 << 4;
+const TYPE_BOOLEAN_FALSE: u8 = 13 << 4;
+
+const INTERNED_NEW: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// 只保留标签结构，文本、注释等内容对读取系统配置文件没有用处
+#[derive(Debug, Clone)]
+pub enum Event {
+    Start(Element),
+    End(String),
+}
+
+pub fn is_abx(data: &[u8]) -> bool {
+    data.starts_with(ABX_MAGIC)
+}
+
+pub fn parse(data: &[u8]) -> AppResult<Vec<Event>> {
+    if is_abx(data) {
+        parse_abx(data)
+    } else {
+        parse_text(&String::from_utf8_lossy(data))
+    }
+}
+
+struct AbxReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    interned: Vec<String>,
+}
+
+impl<'a> AbxReader<'a> {
+    fn take(&mut self, length: usize) -> AppResult<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset + length).ok_or("ABX 数据越界")?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> AppResult<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> AppResult<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> AppResult<u64> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    // 长度前缀为 2 字节，内容是 Java 的 modified UTF-8，普通字符与 UTF-8 相同
+    fn string(&mut self) -> AppResult<String> {
+        let length = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    // 首次出现时写入 0xFFFF 和字符串本身，之后只写入它在表中的序号
+    fn interned(&mut self) -> AppResult<String> {
+        let index = self.u16()?;
+        if index == INTERNED_NEW {
+            let value = self.string()?;
+            self.interned.push(value.clone());
+            return Ok(value);
+        }
+        self.interned.get(index as usize)
+            .cloned()
+            .ok_or_else(|| format!("ABX 字符串序号 {} 越界", index).into())
+    }
+
+    fn bytes(&mut self) -> AppResult<&'a [u8]> {
+        let length = self.u16()? as usize;
+        self.take(length)
+    }
+
+    // 各类型转换为与文本 XML 中相同的写法，调用方不需要区分两种格式
+    fn value(&mut self, kind: u8) -> AppResult<String> {
+        Ok(match kind {
+            TYPE_NULL => String::new(),
+            TYPE_STRING => self.string()?,
+            TYPE_STRING_INTERNED => self.interned()?,
+            TYPE_BYTES_HEX => der::to_hex(self.bytes()?),
+            TYPE_BYTES_BASE64 => base64::engine::general_purpose::STANDARD.encode(self.bytes()?),
+            TYPE_INT => (self.u32()? as i32).to_string(),
+            TYPE_INT_HEX => format!("{:x}", self.u32()?),
+            TYPE_LONG => (self.u64()? as i64).to_string(),
+            TYPE_LONG_HEX => format!("{:x}", self.u64()?),
+            TYPE_FLOAT => f32::from_bits(self.u32()?).to_string(),
+            TYPE_DOUBLE => f64::from_bits(self.u64()?).to_string(),
+            TYPE_BOOLEAN_TRUE => "true".to_string(),
+            TYPE_BOOLEAN_FALSE => "false".to_string(),
+            _ => return Err(format!("未知的 ABX 数据类型 {}", kind >> 4).into()),
+        })
+    }
+}
+
+pub fn parse_abx(data: &[u8]) -> AppResult<Vec<Event>> {
+    if !is_abx(data) {
+        return Err("不是 ABX 格式".into());
+    }
+
+    let mut reader = AbxReader {
+        data,
+        offset: ABX_MAGIC.len(),
+        interned: Vec::new(),
+    };
+    let mut events = Vec::new();
+    let mut current: Option<Element> = None;
+
+    while reader.offset < data.len() {
+        let token = reader.take(1)?[0];
+        let command = token & 0x0F;
+        let kind = token & 0xF0;
+
+        if command == COMMAND_ATTRIBUTE {
+            let name = reader.interned()?;
+            let value = reader.value(kind)?;
+            let element = current.as_mut().ok_or("ABX 属性不在标签内")?;
+            element.attributes.push((name, value));
+            continue;
+        }
+        if let Some(element) = current.take() {
+            events.push(Event::Start(element));
+        }
+
+        match command {
+            COMMAND_START_TAG => {
+                current = Some(Element {
+                    name: reader.interned()?,
+                    attributes: Vec::new(),
+                });
+            }
+            COMMAND_END_TAG => events.push(Event::End(reader.interned()?)),
+            COMMAND_START_DOCUMENT => {}
+            COMMAND_END_DOCUMENT => break,
+            _ => {
+                reader.value(kind)?;
+            }
+        }
+    }
+
+    if let Some(element) = current {
+        events.push(Event::Start(element));
+    }
+    Ok(events)
+}
+
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn parse_attributes(mut text: &str) -> AppResult<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    loop {
+        text = text.trim_start();
+        if text.is_empty() {
+            return Ok(attributes);
+        }
+
+        let equals = text.find('=').ok_or("XML 属性缺少 =")?;
+        let name = text[..equals].trim().to_string();
+        text = text[equals + 1..].trim_start();
+
+        let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'').ok_or("XML 属性值缺少引号")?;
+        let end = text[1..].find(quote).ok_or("XML 属性值未结束")? + 1;
+        attributes.push((name, unescape(&text[1..end])));
+        text = &text[end + 1..];
+    }
+}
+
+// 只处理系统配置文件会用到的语法：标签、属性、注释、声明和 CDATA
+pub fn parse_text(text: &str) -> AppResult<Vec<Event>> {
+    let mut events = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+
+        let skip_to = |rest: &str, terminator: &str| -> AppResult<usize> {
+            rest.find(terminator)
+                .map(|end| end + terminator.len())
+                .ok_or_else(|| format!("XML 中的 {} 未结束", &rest[..rest.len().min(16)]).into())
+        };
+        if rest.starts_with("<!--") {
+            rest = &rest[skip_to(rest, "-->")?..];
+            continue;
+        }
+        if rest.starts_with("<![CDATA[") {
+            rest = &rest[skip_to(rest, "]]>")?..];
+            continue;
+        }
+        if rest.starts_with("<?") {
+            rest = &rest[skip_to(rest, "?>")?..];
+            continue;
+        }
+        if rest.starts_with("<!") {
+            rest = &rest[skip_to(rest, ">")?..];
+            continue;
+        }
+
+        let end = tag_end(rest).ok_or("XML 标签未结束")?;
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            events.push(Event::End(name.trim().to_string()));
+            continue;
+        }
+
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = tag[..name_end].to_string();
+        if name.is_empty() {
+            return Err("XML 标签缺少名称".into());
+        }
+        events.push(Event::Start(Element {
+            name: name.clone(),
+            attributes: parse_attributes(&tag[name_end..])?,
+        }));
+        if self_closing {
+            events.push(Event::End(name));
+        }
+    }
+
+    Ok(events)
+}
+
+// 属性值中允许出现 >，需要跳过引号内的内容
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}